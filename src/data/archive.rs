@@ -0,0 +1,302 @@
+//! ## Capture Archives
+//!
+//! A self-describing container for the traces acquired on one trigger, stored
+//! together with the identity of the scope and the panel setup that produced
+//! them.
+//!
+//! An archive starts with the magic `MAUIARC\0`, followed by the length of a
+//! JSON header as a little endian `u64`, the header, and the waveforms one
+//! after the other. Each waveform is stored in the binary layout of a
+//! `WAVEFORM? ALL` response. The header lists the traces with the offset,
+//! length and CRC-32 of their data relative to the end of the header, along
+//! with a summary of their descriptor, so that the archive can be inspected
+//! with nothing more than a JSON parser.
+//!
+
+use crate::data::waveform::Waveform;
+use crate::utils::crc32;
+use crate::{MauiError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The first bytes of every archive.
+const MAGIC: &[u8; 8] = b"MAUIARC\0";
+
+/// The version of the header written by this crate.
+const VERSION: u32 = 1;
+
+/// ## Capture
+///
+/// The traces acquired on one trigger, with everything needed to reproduce
+/// the acquisition.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture {
+    /// The response of the scope to `*IDN?`.
+    pub identity: String,
+    /// The panel setup of the scope, as returned by `PNSU?`.
+    pub panel_setup: String,
+    /// Time at which the capture was made, on the clock of this computer.
+    pub timestamp: SystemTime,
+    /// The captured traces, in the order they were requested.
+    pub waveforms: Vec<(String, Waveform)>,
+}
+
+impl Capture {
+    /// ## Waveform
+    ///
+    /// Return the waveform captured for `trace`, if there is one.
+    ///
+    pub fn waveform(&self, trace: &str) -> Option<&Waveform> {
+        self.waveforms
+            .iter()
+            .find(|(name, _)| name == trace)
+            .map(|(_, waveform)| waveform)
+    }
+}
+
+/// The JSON header of an archive.
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    identity: String,
+    /// Nanoseconds since 1970-01-01 00:00:00 UTC.
+    timestamp_ns: u64,
+    panel_setup: String,
+    traces: Vec<TraceEntry>,
+}
+
+/// The location and summary of one trace in the header of an archive.
+#[derive(Debug, Serialize, Deserialize)]
+struct TraceEntry {
+    name: String,
+    offset: u64,
+    length: u64,
+    crc32: u32,
+    points: u32,
+    segments: u32,
+    horizontal_interval: f64,
+    horizontal_offset: f64,
+    horizontal_unit: String,
+    vertical_unit: String,
+}
+
+/// ## Read
+///
+/// Read an archive file into a [`Capture`].
+///
+pub fn read(path: impl AsRef<Path>) -> Result<Capture> {
+    let bytes = fs::read(path)?;
+    decode(&bytes)
+}
+
+/// ## Write
+///
+/// Write a [`Capture`] to an archive file.
+///
+pub fn write(path: impl AsRef<Path>, capture: &Capture) -> Result<()> {
+    fs::write(path, encode(capture)?)?;
+    Ok(())
+}
+
+/// ## Decode
+///
+/// Decode the content of an archive file, verifying the CRC-32 of every
+/// trace.
+///
+pub fn decode(bytes: &[u8]) -> Result<Capture> {
+    let invalid = |reason: String| MauiError::InvalidWaveform(format!("archive {}", reason));
+
+    if bytes.len() < 16 || &bytes[..8] != MAGIC {
+        return Err(invalid("does not start with the MAUIARC magic".into()));
+    }
+    let mut length = [0u8; 8];
+    length.copy_from_slice(&bytes[8..16]);
+    let data_start = usize::try_from(u64::from_le_bytes(length))
+        .ok()
+        .and_then(|length| length.checked_add(16))
+        .filter(|start| *start <= bytes.len())
+        .ok_or_else(|| invalid("header is truncated".into()))?;
+
+    let header: Header = serde_json::from_slice(&bytes[16..data_start])
+        .map_err(|e| invalid(format!("header is not valid: {}", e)))?;
+    if header.version > VERSION {
+        return Err(invalid(format!(
+            "version {} is newer than the supported version {}",
+            header.version, VERSION
+        )));
+    }
+
+    let data = &bytes[data_start..];
+    let waveforms = header
+        .traces
+        .into_iter()
+        .map(|entry| {
+            let block = usize::try_from(entry.offset)
+                .ok()
+                .zip(usize::try_from(entry.length).ok())
+                .and_then(|(offset, length)| data.get(offset..offset.checked_add(length)?))
+                .ok_or_else(|| invalid(format!("data of trace {} is truncated", entry.name)))?;
+            if crc32(block) != entry.crc32 {
+                return Err(invalid(format!(
+                    "data of trace {} is corrupted",
+                    entry.name
+                )));
+            }
+            Ok((entry.name, Waveform::parse(block)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Capture {
+        identity: header.identity,
+        panel_setup: header.panel_setup,
+        timestamp: UNIX_EPOCH + Duration::from_nanos(header.timestamp_ns),
+        waveforms,
+    })
+}
+
+/// ## Encode
+///
+/// Encode a [`Capture`] into the content of an archive file. Fails if the
+/// timestamp is before 1970.
+///
+pub fn encode(capture: &Capture) -> Result<Vec<u8>> {
+    let timestamp_ns = capture
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_nanos() as u64)
+        .map_err(|_| MauiError::InvalidArgument("capture timestamp is before 1970".into()))?;
+
+    let mut data = Vec::new();
+    let mut traces = Vec::with_capacity(capture.waveforms.len());
+    for (name, waveform) in &capture.waveforms {
+        let block = waveform.to_bytes();
+        let descriptor = &waveform.descriptor;
+        traces.push(TraceEntry {
+            name: name.clone(),
+            offset: data.len() as u64,
+            length: block.len() as u64,
+            crc32: crc32(&block),
+            points: waveform.voltages.len() as u32,
+            segments: descriptor.subarray_count.max(1),
+            horizontal_interval: descriptor.horizontal_interval as f64,
+            horizontal_offset: descriptor.horizontal_offset,
+            horizontal_unit: descriptor.horizontal_unit.clone(),
+            vertical_unit: descriptor.vertical_unit.clone(),
+        });
+        data.extend(block);
+    }
+
+    let header = Header {
+        version: VERSION,
+        identity: capture.identity.clone(),
+        timestamp_ns,
+        panel_setup: capture.panel_setup.clone(),
+        traces,
+    };
+    let header = serde_json::to_vec_pretty(&header).map_err(|e| {
+        MauiError::InvalidArgument(format!("could not encode archive header: {}", e))
+    })?;
+
+    let mut bytes = Vec::with_capacity(16 + header.len() + data.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&(header.len() as u64).to_le_bytes());
+    bytes.extend(header);
+    bytes.extend(data);
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::wavedesc::WaveDesc;
+
+    /// A waveform as decoded from the scope, so that it encodes losslessly.
+    fn waveform(voltages: Vec<f64>) -> Waveform {
+        let waveform = Waveform {
+            descriptor: WaveDesc {
+                vertical_gain: 0.5,
+                horizontal_interval: 0.25,
+                ..WaveDesc::default()
+            },
+            user_text: String::new(),
+            trigger_times: Vec::new(),
+            ris_times: Vec::new(),
+            times: Vec::new(),
+            voltages,
+            voltages_2: None,
+        };
+        Waveform::parse(&waveform.to_bytes()).unwrap()
+    }
+
+    fn capture() -> Capture {
+        Capture {
+            identity: "LECROY,WAVERUNNER9254M,LCRY0001,9.6.0".into(),
+            panel_setup: "XMAP 1.0\n...".into(),
+            timestamp: UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789),
+            waveforms: vec![
+                ("C1".into(), waveform(vec![0.5, 1.0, -1.5])),
+                ("F1".into(), waveform(vec![2.0; 10])),
+            ],
+        }
+    }
+
+    fn is_invalid(result: Result<Capture>, reason: &str) -> bool {
+        matches!(result, Err(MauiError::InvalidWaveform(e)) if e.contains(reason))
+    }
+
+    #[test]
+    fn encode_then_decode_round_trips() {
+        let capture = capture();
+        let bytes = encode(&capture).unwrap();
+        assert!(bytes.starts_with(MAGIC));
+
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded, capture);
+        assert_eq!(decoded.waveform("F1").unwrap().voltages, [2.0; 10]);
+        assert!(decoded.waveform("C2").is_none());
+    }
+
+    #[test]
+    fn corrupted_data_is_rejected() {
+        let mut bytes = encode(&capture()).unwrap();
+        *bytes.last_mut().unwrap() ^= 0x01;
+        assert!(is_invalid(decode(&bytes), "trace F1 is corrupted"));
+    }
+
+    #[test]
+    fn truncated_archive_is_rejected() {
+        let bytes = encode(&capture()).unwrap();
+        let header_end = 16 + u64::from_le_bytes(bytes[8..16].try_into().unwrap()) as usize;
+        for length in [0, 8, 15, 16, header_end - 1] {
+            assert!(is_invalid(decode(&bytes[..length]), ""), "{} bytes", length);
+        }
+        assert!(is_invalid(
+            decode(&bytes[..bytes.len() - 1]),
+            "trace F1 is truncated"
+        ));
+    }
+
+    #[test]
+    fn invalid_header_is_rejected() {
+        assert!(is_invalid(decode(b"NOTANARCHIVE0000"), "magic"));
+
+        let mut huge = MAGIC.to_vec();
+        huge.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(is_invalid(decode(&huge), "header is truncated"));
+
+        let mut garbage = MAGIC.to_vec();
+        garbage.extend_from_slice(&4u64.to_le_bytes());
+        garbage.extend_from_slice(b"{{{{");
+        assert!(is_invalid(decode(&garbage), "header is not valid"));
+
+        let header =
+            br#"{"version":2,"identity":"","timestamp_ns":0,"panel_setup":"","traces":[]}"#;
+        let mut newer = MAGIC.to_vec();
+        newer.extend_from_slice(&(header.len() as u64).to_le_bytes());
+        newer.extend_from_slice(header);
+        assert!(is_invalid(decode(&newer), "version 2"));
+    }
+}
@@ -0,0 +1,592 @@
+//! ## Export
+//!
+//! Export of [`Waveform`]s to formats readable without this crate: CSV,
+//! NumPy `.npy` and `.npz` archives, and WAV audio files.
+//!
+
+use crate::data::wavedesc::{CommType, WaveDesc};
+use crate::data::waveform::Waveform;
+use crate::utils::crc32;
+use crate::{MauiError, Result};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// ## WAV Format
+///
+/// The sample format of an exported WAV file.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavFormat {
+    /// 16 bit integer samples holding the ADC codes of the scope, so that
+    /// full scale is the vertical range of the acquisition. Lossless for
+    /// waveforms downloaded as words.
+    Pcm16,
+    /// 32 bit float samples holding the voltages unscaled.
+    Float32,
+}
+
+impl Waveform {
+    /// ## To CSV
+    ///
+    /// Format the waveform as CSV with `Time` and `Ampl` columns, preceded by
+    /// `#` comment lines describing the acquisition. A `Segment` column is
+    /// added for sequence waveforms and an `Ampl2` column for waveforms with a
+    /// second wave array.
+    ///
+    pub fn to_csv(&self) -> String {
+        let descriptor = &self.descriptor;
+        let mut csv = String::new();
+
+        for (key, value) in metadata(descriptor) {
+            let _ = writeln!(csv, "# {},{}", key, value);
+        }
+
+        let segmented = descriptor.subarray_count > 1;
+        let segment_length =
+            (self.voltages.len() / descriptor.subarray_count.max(1) as usize).max(1);
+        let mut columns = vec!["Time", "Ampl"];
+        if segmented {
+            columns.insert(0, "Segment");
+        }
+        if self.voltages_2.is_some() {
+            columns.push("Ampl2");
+        }
+        let _ = writeln!(csv, "{}", columns.join(","));
+
+        for (i, (time, volts)) in self.times.iter().zip(&self.voltages).enumerate() {
+            if segmented {
+                let _ = write!(csv, "{},", i / segment_length);
+            }
+            let _ = write!(csv, "{:e},{}", time, volts);
+            if let Some(volts) = self.voltages_2.as_ref().and_then(|v| v.get(i)) {
+                let _ = write!(csv, ",{}", volts);
+            }
+            csv.push('\n');
+        }
+
+        csv
+    }
+
+    /// ## Write CSV
+    ///
+    /// Write the waveform to a CSV file, see [`Waveform::to_csv`].
+    ///
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> Result<()> {
+        fs::write(path, self.to_csv())?;
+        Ok(())
+    }
+
+    /// ## To NPY
+    ///
+    /// Encode the waveform as a NumPy `.npy` array of float64 with one row per
+    /// sample and the time and voltage as columns:
+    ///
+    /// ```python
+    /// times, voltages = np.load("waveform.npy").T
+    /// ```
+    ///
+    pub fn to_npy(&self) -> Vec<u8> {
+        let values: Vec<f64> = self
+            .times
+            .iter()
+            .zip(&self.voltages)
+            .flat_map(|(time, volts)| [*time, *volts])
+            .collect();
+        npy(&values, &format!("({}, 2)", self.voltages.len()))
+    }
+
+    /// ## Write NPY
+    ///
+    /// Write the waveform to a `.npy` file, see [`Waveform::to_npy`].
+    ///
+    pub fn write_npy(&self, path: impl AsRef<Path>) -> Result<()> {
+        fs::write(path, self.to_npy())?;
+        Ok(())
+    }
+
+    /// ## To NPZ
+    ///
+    /// Encode the waveform as an uncompressed NumPy `.npz` archive of float64
+    /// arrays: `time`, `voltage`, `voltage_2` when there is a second wave
+    /// array, and `trigger_time` and `trigger_offset` with one entry per
+    /// segment of a sequence waveform.
+    ///
+    /// Fails if the archive would exceed the 4 GiB limit of zip files.
+    ///
+    pub fn to_npz(&self) -> Result<Vec<u8>> {
+        let trigger_times: Vec<f64> = self.trigger_times.iter().map(|t| t.time).collect();
+        let trigger_offsets: Vec<f64> = self.trigger_times.iter().map(|t| t.offset).collect();
+
+        let mut arrays = vec![
+            ("time", npy_1d(&self.times)),
+            ("voltage", npy_1d(&self.voltages)),
+        ];
+        if let Some(voltages_2) = &self.voltages_2 {
+            arrays.push(("voltage_2", npy_1d(voltages_2)));
+        }
+        arrays.push(("trigger_time", npy_1d(&trigger_times)));
+        arrays.push(("trigger_offset", npy_1d(&trigger_offsets)));
+
+        let mut zip = Zip::default();
+        for (name, data) in &arrays {
+            zip.add(&format!("{}.npy", name), data)?;
+        }
+        zip.finish()
+    }
+
+    /// ## Write NPZ
+    ///
+    /// Write the waveform to a `.npz` file, see [`Waveform::to_npz`].
+    ///
+    pub fn write_npz(&self, path: impl AsRef<Path>) -> Result<()> {
+        fs::write(path, self.to_npz()?)?;
+        Ok(())
+    }
+
+    /// ## To WAV
+    ///
+    /// Encode the voltages as a mono WAV file sampled at the sample rate of
+    /// the waveform. Segments of a sequence waveform are written one after
+    /// the other.
+    ///
+    /// Fails if the sample rate does not fit in a WAV header, which is the
+    /// case above about 1 GS/s.
+    ///
+    pub fn to_wav(&self, format: WavFormat) -> Result<Vec<u8>> {
+        // divide in single precision, the precision of the interval, so that
+        // 1e-8 s gives 100 MS/s rather than 100000001 S/s
+        let interval = self.descriptor.horizontal_interval;
+        let rate = (1.0 / interval).round() as f64;
+        if !(interval > 0.0 && rate >= 1.0 && rate <= (u32::MAX / 4) as f64) {
+            return Err(MauiError::InvalidArgument(format!(
+                "a sample interval of {} s cannot be written to a WAV file",
+                interval
+            )));
+        }
+        let rate = rate as u32;
+
+        let (tag, bits, data): (u16, u16, Vec<u8>) = match format {
+            WavFormat::Pcm16 => (1, 16, pcm16(&self.descriptor, &self.voltages)),
+            WavFormat::Float32 => (
+                3,
+                32,
+                self.voltages
+                    .iter()
+                    .flat_map(|volts| (*volts as f32).to_le_bytes())
+                    .collect(),
+            ),
+        };
+        let block_align = bits / 8;
+        let data_length = u32::try_from(data.len())
+            .ok()
+            .filter(|length| *length <= u32::MAX - 36)
+            .ok_or_else(|| {
+                MauiError::InvalidArgument("waveform is too long for a WAV file".into())
+            })?;
+
+        let mut wav = Vec::with_capacity(44 + data.len());
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_length).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        wav.extend_from_slice(b"fmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&tag.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&rate.to_le_bytes());
+        wav.extend_from_slice(&(rate * block_align as u32).to_le_bytes());
+        wav.extend_from_slice(&block_align.to_le_bytes());
+        wav.extend_from_slice(&bits.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_length.to_le_bytes());
+        wav.extend(data);
+        Ok(wav)
+    }
+
+    /// ## Write WAV
+    ///
+    /// Write the waveform to a WAV file, see [`Waveform::to_wav`].
+    ///
+    pub fn write_wav(&self, path: impl AsRef<Path>, format: WavFormat) -> Result<()> {
+        fs::write(path, self.to_wav(format)?)?;
+        Ok(())
+    }
+}
+
+/// The descriptor fields written in the header of a CSV export.
+fn metadata(descriptor: &WaveDesc) -> Vec<(&'static str, String)> {
+    let trigger = &descriptor.trigger_time;
+    vec![
+        ("Instrument", descriptor.instrument_name.clone()),
+        (
+            "Instrument Number",
+            descriptor.instrument_number.to_string(),
+        ),
+        ("Trace Label", descriptor.trace_label.clone()),
+        ("Wave Source", format!("{:?}", descriptor.wave_source)),
+        (
+            "Trigger Time",
+            format!(
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:09.6}",
+                trigger.year,
+                trigger.months,
+                trigger.days,
+                trigger.hours,
+                trigger.minutes,
+                trigger.seconds
+            ),
+        ),
+        ("Record Type", format!("{:?}", descriptor.record_type)),
+        ("Segments", descriptor.subarray_count.to_string()),
+        ("Points", descriptor.wave_array_count.to_string()),
+        (
+            "Horizontal Interval",
+            format!("{:e}", descriptor.horizontal_interval),
+        ),
+        (
+            "Horizontal Offset",
+            format!("{:e}", descriptor.horizontal_offset),
+        ),
+        ("Horizontal Unit", descriptor.horizontal_unit.clone()),
+        ("Vertical Gain", format!("{:e}", descriptor.vertical_gain)),
+        (
+            "Vertical Offset",
+            format!("{:e}", descriptor.vertical_offset),
+        ),
+        ("Vertical Unit", descriptor.vertical_unit.clone()),
+        (
+            "Vertical Coupling",
+            format!("{:?}", descriptor.vertical_coupling),
+        ),
+        (
+            "Probe Attenuation",
+            descriptor.probe_attenuation.to_string(),
+        ),
+        ("Timebase", format!("{:?}", descriptor.timebase)),
+    ]
+}
+
+/// Quantize voltages back into the ADC codes of the scope, scaled to 16 bits.
+fn pcm16(descriptor: &WaveDesc, voltages: &[f64]) -> Vec<u8> {
+    let gain = descriptor.vertical_gain as f64;
+    let offset = descriptor.vertical_offset as f64;
+    let scale = match descriptor.comm_type {
+        CommType::Byte => 256.0,
+        CommType::Word => 1.0,
+    };
+    voltages
+        .iter()
+        .flat_map(|volts| {
+            let code = ((volts + offset) / gain * scale).round();
+            (code.clamp(i16::MIN as f64, i16::MAX as f64) as i16).to_le_bytes()
+        })
+        .collect()
+}
+
+/// Encode a one dimensional `.npy` array.
+fn npy_1d(values: &[f64]) -> Vec<u8> {
+    npy(values, &format!("({},)", values.len()))
+}
+
+/// Encode a `.npy` version 1.0 file of little endian float64 in C order.
+fn npy(values: &[f64], shape: &str) -> Vec<u8> {
+    let mut header = format!(
+        "{{'descr': '<f8', 'fortran_order': False, 'shape': {}, }}",
+        shape
+    );
+    // the magic, version and header length take 10 bytes, and the header is
+    // padded with spaces and a newline to align the data on 64 bytes
+    let padding = 63 - (10 + header.len()) % 64;
+    header.extend(std::iter::repeat_n(' ', padding));
+    header.push('\n');
+
+    let mut bytes = Vec::with_capacity(10 + header.len() + values.len() * 8);
+    bytes.extend_from_slice(b"\x93NUMPY\x01\x00");
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    for value in values {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes
+}
+
+/// A zip archive of stored, uncompressed, entries.
+#[derive(Default)]
+struct Zip {
+    bytes: Vec<u8>,
+    directory: Vec<u8>,
+    entries: u16,
+}
+
+impl Zip {
+    /// The DOS date of the entries, 1980-01-01.
+    const DATE: u16 = 0x21;
+
+    /// Append an entry named `name` holding `data`.
+    fn add(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        let too_large =
+            || MauiError::InvalidArgument("waveform is too large for a zip archive".into());
+        let offset = u32::try_from(self.bytes.len()).map_err(|_| too_large())?;
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+        let crc = crc32(data);
+
+        // fields shared by the local header and the central directory
+        let mut common = Vec::with_capacity(26);
+        common.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        common.extend_from_slice(&0u16.to_le_bytes()); // flags
+        common.extend_from_slice(&0u16.to_le_bytes()); // stored
+        common.extend_from_slice(&0u16.to_le_bytes()); // time
+        common.extend_from_slice(&Self::DATE.to_le_bytes());
+        common.extend_from_slice(&crc.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes()); // compressed
+        common.extend_from_slice(&size.to_le_bytes()); // uncompressed
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes()); // extra field
+
+        self.bytes.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        self.bytes.extend_from_slice(&common);
+        self.bytes.extend_from_slice(name.as_bytes());
+        self.bytes.extend_from_slice(data);
+
+        self.directory
+            .extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        self.directory.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        self.directory.extend_from_slice(&common);
+        self.directory.extend_from_slice(&0u16.to_le_bytes()); // comment
+        self.directory.extend_from_slice(&0u16.to_le_bytes()); // disk
+        self.directory.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+        self.directory.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+        self.directory.extend_from_slice(&offset.to_le_bytes());
+        self.directory.extend_from_slice(name.as_bytes());
+
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Append the central directory and return the archive.
+    fn finish(mut self) -> Result<Vec<u8>> {
+        let too_large =
+            || MauiError::InvalidArgument("waveform is too large for a zip archive".into());
+        let offset = u32::try_from(self.bytes.len()).map_err(|_| too_large())?;
+        let size = self.directory.len() as u32;
+
+        self.bytes.append(&mut self.directory);
+        self.bytes.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        self.bytes.extend_from_slice(&0u16.to_le_bytes()); // disk
+        self.bytes.extend_from_slice(&0u16.to_le_bytes()); // disk of the directory
+        self.bytes.extend_from_slice(&self.entries.to_le_bytes());
+        self.bytes.extend_from_slice(&self.entries.to_le_bytes());
+        self.bytes.extend_from_slice(&size.to_le_bytes());
+        self.bytes.extend_from_slice(&offset.to_le_bytes());
+        self.bytes.extend_from_slice(&0u16.to_le_bytes()); // comment
+        Ok(self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::waveform::SegmentTrigger;
+
+    fn waveform(comm_type: CommType) -> Waveform {
+        Waveform {
+            descriptor: WaveDesc {
+                comm_type,
+                vertical_gain: 0.25,
+                vertical_offset: 0.5,
+                horizontal_interval: 1e-3,
+                wave_array_count: 3,
+                ..WaveDesc::default()
+            },
+            user_text: String::new(),
+            trigger_times: vec![SegmentTrigger {
+                time: 0.0,
+                offset: -1e-3,
+            }],
+            ris_times: Vec::new(),
+            times: vec![0.0, 1e-3, 2e-3],
+            voltages: vec![-0.5, 0.0, 0.25],
+            voltages_2: None,
+        }
+    }
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Decode a `.npy` file into its header and values.
+    fn read_npy(bytes: &[u8]) -> (String, Vec<f64>) {
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let header_length = u16_at(bytes, 8) as usize;
+        assert_eq!((10 + header_length) % 64, 0);
+        let header = std::str::from_utf8(&bytes[10..10 + header_length]).unwrap();
+        assert!(header.ends_with('\n'));
+        let values = bytes[10 + header_length..]
+            .chunks_exact(8)
+            .map(|value| f64::from_le_bytes(value.try_into().unwrap()))
+            .collect();
+        (header.trim_end().to_string(), values)
+    }
+
+    /// Decode a zip archive through its central directory, checking every
+    /// local header and CRC on the way.
+    fn read_zip(bytes: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let end = bytes.len() - 22;
+        assert_eq!(u32_at(bytes, end), 0x0605_4b50);
+        let entries = u16_at(bytes, end + 10) as usize;
+        let directory_size = u32_at(bytes, end + 12) as usize;
+        let mut position = u32_at(bytes, end + 16) as usize;
+        assert_eq!(position + directory_size, end);
+
+        let mut files = Vec::new();
+        for _ in 0..entries {
+            assert_eq!(u32_at(bytes, position), 0x0201_4b50);
+            let crc = u32_at(bytes, position + 16);
+            let size = u32_at(bytes, position + 20) as usize;
+            assert_eq!(u32_at(bytes, position + 24) as usize, size);
+            let name_length = u16_at(bytes, position + 28) as usize;
+            let local = u32_at(bytes, position + 42) as usize;
+            let name = &bytes[position + 46..position + 46 + name_length];
+            position += 46 + name_length;
+
+            assert_eq!(u32_at(bytes, local), 0x0403_4b50);
+            assert_eq!(u16_at(bytes, local + 8), 0, "entry is stored");
+            assert_eq!(u32_at(bytes, local + 14), crc);
+            assert_eq!(u32_at(bytes, local + 18) as usize, size);
+            assert_eq!(&bytes[local + 30..local + 30 + name_length], name);
+            let start = local + 30 + name_length + u16_at(bytes, local + 28) as usize;
+            let data = bytes[start..start + size].to_vec();
+            assert_eq!(crc32(&data), crc);
+
+            files.push((String::from_utf8(name.to_vec()).unwrap(), data));
+        }
+        files
+    }
+
+    #[test]
+    fn csv_has_metadata_header_and_rows() {
+        let csv = waveform(CommType::Word).to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        let header = lines.iter().position(|l| !l.starts_with("# ")).unwrap();
+
+        assert!(lines.contains(&"# Points,3"));
+        assert!(lines.contains(&"# Segments,1"));
+        assert_eq!(
+            lines[header..],
+            ["Time,Ampl", "0e0,-0.5", "1e-3,0", "2e-3,0.25"]
+        );
+    }
+
+    #[test]
+    fn csv_adds_segment_and_second_array_columns() {
+        let mut waveform = waveform(CommType::Word);
+        waveform.descriptor.subarray_count = 3;
+        waveform.voltages_2 = Some(vec![1.0, 2.0, 3.0]);
+        let csv = waveform.to_csv();
+        let rows: Vec<&str> = csv.lines().filter(|l| !l.starts_with('#')).collect();
+
+        assert_eq!(
+            rows,
+            [
+                "Segment,Time,Ampl,Ampl2",
+                "0,0e0,-0.5,1",
+                "1,1e-3,0,2",
+                "2,2e-3,0.25,3"
+            ]
+        );
+    }
+
+    #[test]
+    fn npy_is_aligned_and_holds_time_and_voltage_columns() {
+        let waveform = waveform(CommType::Word);
+        let (header, values) = read_npy(&waveform.to_npy());
+
+        assert_eq!(
+            header,
+            "{'descr': '<f8', 'fortran_order': False, 'shape': (3, 2), }"
+        );
+        assert_eq!(values, [0.0, -0.5, 1e-3, 0.0, 2e-3, 0.25]);
+        assert!(read_npy(&npy_1d(&[])).1.is_empty());
+    }
+
+    #[test]
+    fn npz_parses_with_matching_crcs() {
+        let mut waveform = waveform(CommType::Word);
+        let files = read_zip(&waveform.to_npz().unwrap());
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "time.npy",
+                "voltage.npy",
+                "trigger_time.npy",
+                "trigger_offset.npy"
+            ]
+        );
+        assert_eq!(read_npy(&files[0].1).1, waveform.times);
+        assert!(read_npy(&files[1].1).0.contains("'shape': (3,)"));
+        assert_eq!(read_npy(&files[1].1).1, waveform.voltages);
+        assert_eq!(read_npy(&files[3].1).1, [-1e-3]);
+
+        waveform.voltages_2 = Some(vec![1.0, 2.0, 3.0]);
+        let files = read_zip(&waveform.to_npz().unwrap());
+        assert_eq!(files[2].0, "voltage_2.npy");
+        assert_eq!(read_npy(&files[2].1).1, [1.0, 2.0, 3.0]);
+    }
+
+    /// Check the RIFF, fmt and data chunks of a mono WAV file and return its
+    /// format tag, sample rate, bits per sample and data.
+    fn read_wav(wav: &[u8]) -> (u16, u32, u16, &[u8]) {
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(u32_at(wav, 4) as usize, wav.len() - 8);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(wav, 16), 16);
+        assert_eq!(u16_at(wav, 22), 1, "mono");
+        let (tag, rate, bits) = (u16_at(wav, 20), u32_at(wav, 24), u16_at(wav, 34));
+        assert_eq!(u16_at(wav, 32), bits / 8);
+        assert_eq!(u32_at(wav, 28), rate * (bits / 8) as u32);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32_at(wav, 40) as usize, wav.len() - 44);
+        (tag, rate, bits, &wav[44..])
+    }
+
+    #[test]
+    fn wav_pcm16_holds_adc_codes() {
+        let wav = waveform(CommType::Word).to_wav(WavFormat::Pcm16).unwrap();
+        let (tag, rate, bits, data) = read_wav(&wav);
+        assert_eq!((tag, rate, bits), (1, 1000, 16));
+        assert_eq!(data, [0, 0, 2, 0, 3, 0]);
+
+        // byte codes are scaled to the full 16 bit range
+        let wav = waveform(CommType::Byte).to_wav(WavFormat::Pcm16).unwrap();
+        assert_eq!(read_wav(&wav).3, [0, 0, 0, 2, 0, 3]);
+    }
+
+    #[test]
+    fn wav_float32_holds_voltages() {
+        let wav = waveform(CommType::Word).to_wav(WavFormat::Float32).unwrap();
+        let (tag, rate, bits, data) = read_wav(&wav);
+        assert_eq!((tag, rate, bits), (3, 1000, 32));
+        let samples: Vec<f32> = data
+            .chunks_exact(4)
+            .map(|sample| f32::from_le_bytes(sample.try_into().unwrap()))
+            .collect();
+        assert_eq!(samples, [-0.5, 0.0, 0.25]);
+    }
+
+    #[test]
+    fn wav_rejects_rates_beyond_the_header() {
+        let mut waveform = waveform(CommType::Word);
+        waveform.descriptor.horizontal_interval = 1e-10;
+        assert!(matches!(
+            waveform.to_wav(WavFormat::Pcm16),
+            Err(MauiError::InvalidArgument(_))
+        ));
+        waveform.descriptor.horizontal_interval = 0.0;
+        assert!(waveform.to_wav(WavFormat::Float32).is_err());
+    }
+}
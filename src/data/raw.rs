@@ -0,0 +1,483 @@
+//! ## Raw Waveform
+//!
+//! A compact representation of waveforms that keeps the ADC codes sent by
+//! the scope, one or two bytes per sample, and scales them to volts and
+//! seconds only when they are read.
+//!
+
+use crate::data::wavedesc::{CommType, WaveDesc};
+use crate::data::waveform::{
+    decode_f64, decode_samples, decode_text, segment_length, Blocks, SegmentTrigger, Waveform,
+};
+use crate::Result;
+use std::ops::{Bound, Range, RangeBounds};
+
+/// ## Samples
+///
+/// The ADC codes of a wave array, in the width they were transferred in.
+/// They are copied once out of the response, which does not outlive the
+/// download, and then only borrowed.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Samples {
+    Byte(Vec<i8>),
+    Word(Vec<i16>),
+}
+
+impl Samples {
+    /// Decode the samples of a wave array.
+    fn decode(descriptor: &WaveDesc, bytes: &[u8]) -> Samples {
+        match descriptor.comm_type {
+            CommType::Byte => Samples::Byte(bytes.iter().map(|&b| b as i8).collect()),
+            CommType::Word => Samples::Word(decode_samples(descriptor, bytes).collect()),
+        }
+    }
+
+    /// ## Len
+    ///
+    /// Return the number of samples.
+    ///
+    pub fn len(&self) -> usize {
+        match self {
+            Samples::Byte(samples) => samples.len(),
+            Samples::Word(samples) => samples.len(),
+        }
+    }
+
+    /// ## Is Empty
+    ///
+    /// Return whether there are no samples.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// ## Get
+    ///
+    /// Return the ADC code at `index`.
+    ///
+    pub fn get(&self, index: usize) -> Option<i16> {
+        match self {
+            Samples::Byte(samples) => samples.get(index).map(|&s| s as i16),
+            Samples::Word(samples) => samples.get(index).copied(),
+        }
+    }
+
+    /// ## Iter
+    ///
+    /// Iterate over the ADC codes.
+    ///
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = i16> + '_ {
+        (0..self.len()).map(move |i| self.get(i).unwrap_or_default())
+    }
+
+    /// Append the samples of `other`, widening to words if the widths differ.
+    pub(crate) fn append(&mut self, other: Samples) {
+        match (&mut *self, other) {
+            (Samples::Byte(samples), Samples::Byte(other)) => samples.extend(other),
+            (Samples::Word(samples), Samples::Word(other)) => samples.extend(other),
+            (_, other) => {
+                let mut samples: Vec<i16> = self.iter().collect();
+                samples.extend(other.iter());
+                *self = Samples::Word(samples);
+            }
+        }
+    }
+}
+
+/// ## Raw Waveform
+///
+/// A waveform decoded from the same blocks as a [`Waveform`], but keeping the
+/// samples as ADC codes. Voltages and times are computed on demand, either
+/// one at a time, through iterators, or converted in bulk to `f32` or `f64`.
+///
+/// A 100 Mpts record takes 200 MB as a raw waveform of words, against 1.6 GB
+/// for the voltages and times of a [`Waveform`].
+///
+/// Parsing copies the samples out of the response into owned vectors, so the
+/// response can be dropped right away. Slices, segments and the iterators
+/// over voltages and times borrow those samples without copying them again.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawWaveform {
+    pub descriptor: WaveDesc,
+    pub user_text: String,
+    pub trigger_times: Vec<SegmentTrigger>,
+    pub ris_times: Vec<f64>,
+    pub samples: Samples,
+    /// The second wave array, only present for complex and extrema records.
+    pub samples_2: Option<Samples>,
+}
+
+impl RawWaveform {
+    /// ## Parse
+    ///
+    /// Decode a waveform from a `WAVEFORM? ALL` response or the content of a
+    /// `.trc` file, like [`Waveform::parse`].
+    ///
+    pub fn parse(bytes: &[u8]) -> Result<RawWaveform> {
+        let blocks = Blocks::split(bytes)?;
+        let descriptor = blocks.descriptor;
+
+        Ok(RawWaveform {
+            user_text: decode_text(blocks.user_text),
+            trigger_times: decode_f64(&descriptor, blocks.trigtime)
+                .collect::<Vec<f64>>()
+                .chunks_exact(2)
+                .map(|pair| SegmentTrigger {
+                    time: pair[0],
+                    offset: pair[1],
+                })
+                .collect(),
+            ris_times: decode_f64(&descriptor, blocks.ris_time).collect(),
+            samples: Samples::decode(&descriptor, blocks.wave_array_1),
+            samples_2: (!blocks.wave_array_2.is_empty())
+                .then(|| Samples::decode(&descriptor, blocks.wave_array_2)),
+            descriptor,
+        })
+    }
+
+    /// ## Len
+    ///
+    /// Return the number of samples.
+    ///
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// ## Is Empty
+    ///
+    /// Return whether there are no samples.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// ## Voltage
+    ///
+    /// Return the sample at `index` in volts.
+    ///
+    pub fn voltage(&self, index: usize) -> Option<f64> {
+        self.samples
+            .get(index)
+            .map(|sample| self.descriptor.to_volts(sample as f64))
+    }
+
+    /// ## Time
+    ///
+    /// Return the time of the sample at `index`, relative to the trigger of
+    /// its segment.
+    ///
+    pub fn time(&self, index: usize) -> Option<f64> {
+        (index < self.len()).then(|| self.time_of(index))
+    }
+
+    /// ## Slice
+    ///
+    /// Return a view on the samples in `range`, or `None` if the range is out
+    /// of bounds.
+    ///
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> Option<RawSlice<'_>> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.checked_add(1)?,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.checked_add(1)?,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len(),
+        };
+        (start <= end && end <= self.len()).then_some(RawSlice {
+            waveform: self,
+            range: start..end,
+        })
+    }
+
+    /// ## Segments
+    ///
+    /// Return a view on each segment of a sequence waveform. A waveform that
+    /// was not acquired in sequence mode is returned as a single segment.
+    ///
+    pub fn segments(&self) -> impl Iterator<Item = RawSlice<'_>> + '_ {
+        let segment_length = segment_length(&self.descriptor, self.len());
+        (0..self.len())
+            .step_by(segment_length)
+            .map(move |start| RawSlice {
+                waveform: self,
+                range: start..(start + segment_length).min(self.len()),
+            })
+    }
+
+    /// ## Voltages
+    ///
+    /// Iterate over the samples in volts.
+    ///
+    pub fn voltages(&self) -> impl ExactSizeIterator<Item = f64> + '_ {
+        self.all().voltages()
+    }
+
+    /// ## Times
+    ///
+    /// Iterate over the time of each sample, relative to the trigger of its
+    /// segment.
+    ///
+    pub fn times(&self) -> impl ExactSizeIterator<Item = f64> + '_ {
+        self.all().times()
+    }
+
+    /// ## To F64
+    ///
+    /// Convert all samples to volts in double precision.
+    ///
+    pub fn to_f64(&self) -> Vec<f64> {
+        self.all().to_f64()
+    }
+
+    /// ## To F32
+    ///
+    /// Convert all samples to volts in single precision, which is the
+    /// precision of the gain and offset of the descriptor.
+    ///
+    pub fn to_f32(&self) -> Vec<f32> {
+        self.all().to_f32()
+    }
+
+    /// ## To Waveform
+    ///
+    /// Scale every sample into a [`Waveform`].
+    ///
+    pub fn to_waveform(&self) -> Waveform {
+        Waveform {
+            descriptor: self.descriptor.clone(),
+            user_text: self.user_text.clone(),
+            trigger_times: self.trigger_times.clone(),
+            ris_times: self.ris_times.clone(),
+            times: self.times().collect(),
+            voltages: self.to_f64(),
+            voltages_2: self.samples_2.as_ref().map(|samples| {
+                samples
+                    .iter()
+                    .map(|sample| self.descriptor.to_volts(sample as f64))
+                    .collect()
+            }),
+        }
+    }
+
+    /// A view on all the samples.
+    fn all(&self) -> RawSlice<'_> {
+        RawSlice {
+            waveform: self,
+            range: 0..self.len(),
+        }
+    }
+
+    /// Return the time of the sample at `index`, which must be in bounds.
+    fn time_of(&self, index: usize) -> f64 {
+        // the samples of each segment are timed from the trigger of that segment
+        let segment_length = segment_length(&self.descriptor, self.len());
+        match self.trigger_times.get(index / segment_length) {
+            Some(trigger) if self.descriptor.subarray_count > 1 => {
+                trigger.offset
+                    + self.descriptor.horizontal_interval as f64 * (index % segment_length) as f64
+            }
+            _ => self.descriptor.time_of(index),
+        }
+    }
+}
+
+/// ## Raw Slice
+///
+/// A view on a range of the samples of a [`RawWaveform`], scaled on demand.
+///
+#[derive(Debug, Clone)]
+pub struct RawSlice<'a> {
+    waveform: &'a RawWaveform,
+    range: Range<usize>,
+}
+
+impl<'a> RawSlice<'a> {
+    /// ## Range
+    ///
+    /// Return the range of the samples in the waveform.
+    ///
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// ## Len
+    ///
+    /// Return the number of samples.
+    ///
+    pub fn len(&self) -> usize {
+        self.range.len()
+    }
+
+    /// ## Is Empty
+    ///
+    /// Return whether there are no samples.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.range.is_empty()
+    }
+
+    /// ## Samples
+    ///
+    /// Iterate over the ADC codes.
+    ///
+    pub fn samples(&self) -> impl ExactSizeIterator<Item = i16> + 'a {
+        let samples = &self.waveform.samples;
+        self.range
+            .clone()
+            .map(move |i| samples.get(i).unwrap_or_default())
+    }
+
+    /// ## Voltages
+    ///
+    /// Iterate over the samples in volts.
+    ///
+    pub fn voltages(&self) -> impl ExactSizeIterator<Item = f64> + 'a {
+        let descriptor = &self.waveform.descriptor;
+        self.samples()
+            .map(move |sample| descriptor.to_volts(sample as f64))
+    }
+
+    /// ## Times
+    ///
+    /// Iterate over the time of each sample, relative to the trigger of its
+    /// segment.
+    ///
+    pub fn times(&self) -> impl ExactSizeIterator<Item = f64> + 'a {
+        let waveform = self.waveform;
+        self.range.clone().map(move |i| waveform.time_of(i))
+    }
+
+    /// ## To F64
+    ///
+    /// Convert the samples to volts in double precision.
+    ///
+    pub fn to_f64(&self) -> Vec<f64> {
+        self.voltages().collect()
+    }
+
+    /// ## To F32
+    ///
+    /// Convert the samples to volts in single precision.
+    ///
+    pub fn to_f32(&self) -> Vec<f32> {
+        let gain = self.waveform.descriptor.vertical_gain;
+        let offset = self.waveform.descriptor.vertical_offset;
+        self.samples()
+            .map(|sample| gain * sample as f32 - offset)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A raw waveform of `codes` sent as `comm_type`, in `segments` segments
+    /// triggered with an offset of -1 s.
+    fn waveform(comm_type: CommType, codes: &[i16], segments: u32) -> RawWaveform {
+        let descriptor = WaveDesc {
+            comm_type,
+            vertical_gain: 0.5,
+            vertical_offset: 1.0,
+            horizontal_interval: 0.25,
+            horizontal_offset: -2.0,
+            subarray_count: segments,
+            ..WaveDesc::default()
+        };
+        let bytes: Vec<u8> = codes
+            .iter()
+            .flat_map(|&code| match comm_type {
+                CommType::Byte => vec![code as i8 as u8],
+                CommType::Word => code.to_le_bytes().to_vec(),
+            })
+            .collect();
+        let trigtime: Vec<u8> = (0..segments)
+            .flat_map(|i| [i as f64, -1.0])
+            .flat_map(f64::to_le_bytes)
+            .collect();
+        let descriptor = WaveDesc {
+            trigtime_array_length: trigtime.len() as u32,
+            wave_array_1_length: bytes.len() as u32,
+            wave_array_count: codes.len() as u32,
+            ..descriptor
+        };
+
+        let mut block = descriptor.to_bytes();
+        block.extend(trigtime);
+        block.extend(bytes);
+        RawWaveform::parse(&block).unwrap()
+    }
+
+    #[test]
+    fn byte_samples_keep_their_width() {
+        let raw = waveform(CommType::Byte, &[-128, -1, 0, 127], 1);
+        assert_eq!(raw.samples, Samples::Byte(vec![-128, -1, 0, 127]));
+        assert_eq!(raw.samples.iter().collect::<Vec<_>>(), [-128, -1, 0, 127]);
+        assert_eq!(raw.samples.iter().len(), 4);
+        assert_eq!(raw.voltage(3), Some(0.5 * 127.0 - 1.0));
+        assert_eq!(raw.voltage(4), None);
+    }
+
+    #[test]
+    fn word_samples_keep_their_width() {
+        let raw = waveform(CommType::Word, &[i16::MIN, -2, 2, i16::MAX], 1);
+        assert_eq!(raw.samples, Samples::Word(vec![i16::MIN, -2, 2, i16::MAX]));
+        assert_eq!(raw.samples.get(1), Some(-2));
+        assert_eq!(raw.samples.get(4), None);
+        assert_eq!(raw.to_f64(), [-16385.0, -2.0, 0.0, 16382.5]);
+        assert_eq!(raw.to_f32(), [-16385.0f32, -2.0, 0.0, 16382.5]);
+        assert_eq!(raw.times().collect::<Vec<_>>(), [-2.0, -1.75, -1.5, -1.25]);
+    }
+
+    #[test]
+    fn mixed_widths_are_widened_when_appended() {
+        let mut samples = Samples::Byte(vec![1, -1]);
+        samples.append(Samples::Byte(vec![2]));
+        assert_eq!(samples, Samples::Byte(vec![1, -1, 2]));
+        samples.append(Samples::Word(vec![300]));
+        assert_eq!(samples, Samples::Word(vec![1, -1, 2, 300]));
+        assert!(!samples.is_empty());
+    }
+
+    #[test]
+    fn slices_are_bounded() {
+        let raw = waveform(CommType::Word, &[0, 2, 4, 6, 8], 1);
+        let slice = raw.slice(1..3).unwrap();
+        assert_eq!(slice.range(), 1..3);
+        assert_eq!(slice.samples().collect::<Vec<_>>(), [2, 4]);
+        assert_eq!(slice.to_f64(), [0.0, 1.0]);
+        assert_eq!(slice.to_f32(), [0.0f32, 1.0]);
+        assert_eq!(slice.times().collect::<Vec<_>>(), [-1.75, -1.5]);
+
+        assert_eq!(raw.slice(..).unwrap().len(), 5);
+        assert_eq!(raw.slice(3..=4).unwrap().range(), 3..5);
+        assert!(raw.slice(5..).unwrap().is_empty());
+        assert!(raw.slice(..6).is_none());
+        assert!(raw.slice(4..=5).is_none());
+        assert!(raw.slice((Bound::Excluded(5), Bound::Unbounded)).is_none());
+        assert!(raw.slice(..=usize::MAX).is_none());
+    }
+
+    #[test]
+    fn segments_cover_every_sample() {
+        let raw = waveform(CommType::Byte, &[0, 1, 2, 3, 4, 5], 3);
+        let segments: Vec<_> = raw.segments().collect();
+        assert_eq!(
+            segments.iter().map(RawSlice::range).collect::<Vec<_>>(),
+            [0..2, 2..4, 4..6]
+        );
+        // each segment is timed from its own trigger offset
+        assert_eq!(segments[2].times().collect::<Vec<_>>(), [-1.0, -0.75]);
+
+        let single = waveform(CommType::Byte, &[0, 1, 2], 1);
+        let segments: Vec<_> = single.segments().collect();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].range(), 0..3);
+        assert_eq!(waveform(CommType::Byte, &[], 1).segments().count(), 0);
+    }
+}
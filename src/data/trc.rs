@@ -0,0 +1,132 @@
+//! ## TRC Files
+//!
+//! Reading and writing of the LeCroy `.trc` files produced by the binary
+//! autosave mode. A `.trc` file holds the same blocks as a `WAVEFORM? ALL`
+//! response, wrapped in an IEEE 488.2 `#9` block header, so no connection to
+//! a scope is needed to use this module.
+//!
+
+use crate::data::waveform::Waveform;
+use crate::Result;
+use std::fs;
+use std::path::Path;
+
+/// ## Read
+///
+/// Read a `.trc` file into a [`Waveform`].
+///
+pub fn read(path: impl AsRef<Path>) -> Result<Waveform> {
+    let bytes = fs::read(path)?;
+    decode(&bytes)
+}
+
+/// ## Write
+///
+/// Write a [`Waveform`] to a `.trc` file that can be recalled into a memory
+/// trace on the scope.
+///
+pub fn write(path: impl AsRef<Path>, waveform: &Waveform) -> Result<()> {
+    fs::write(path, encode(waveform))?;
+    Ok(())
+}
+
+/// ## Decode
+///
+/// Decode the content of a `.trc` file. The block header is optional.
+///
+pub fn decode(bytes: &[u8]) -> Result<Waveform> {
+    Waveform::parse(bytes)
+}
+
+/// ## Encode
+///
+/// Encode a [`Waveform`] into the content of a `.trc` file.
+///
+pub fn encode(waveform: &Waveform) -> Vec<u8> {
+    let data = waveform.to_bytes();
+    let mut bytes = format!("#9{:0>9}", data.len()).into_bytes();
+    bytes.extend_from_slice(&data);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::wavedesc::{ByteOrder, CommType, WaveDesc};
+    use crate::data::waveform::SegmentTrigger;
+    use crate::MauiError;
+
+    /// A waveform whose voltages are exact in the quantization of `comm_type`.
+    fn waveform(comm_type: CommType, byte_order: ByteOrder) -> Waveform {
+        let descriptor = WaveDesc {
+            comm_type,
+            byte_order,
+            vertical_gain: 0.25,
+            vertical_offset: 0.5,
+            horizontal_interval: 1e-6,
+            horizontal_offset: -2e-6,
+            instrument_name: "LECROY".into(),
+            trace_label: "run 1".into(),
+            ..WaveDesc::default()
+        };
+        let voltages = vec![-0.5, 0.0, 0.25, 1.0, 2.5];
+        Waveform {
+            times: (0..voltages.len()).map(|i| descriptor.time_of(i)).collect(),
+            descriptor,
+            user_text: "bench 3".into(),
+            trigger_times: vec![SegmentTrigger {
+                time: 0.0,
+                offset: -2e-6,
+            }],
+            ris_times: Vec::new(),
+            voltages,
+            voltages_2: None,
+        }
+    }
+
+    #[test]
+    fn write_then_read_round_trips() {
+        let path = std::env::temp_dir().join(format!("rs-maui-{}.trc", std::process::id()));
+        for (comm_type, byte_order) in [
+            (CommType::Byte, ByteOrder::LittleEndian),
+            (CommType::Word, ByteOrder::LittleEndian),
+            (CommType::Word, ByteOrder::BigEndian),
+        ] {
+            let original = waveform(comm_type, byte_order);
+            write(&path, &original).unwrap();
+            let bytes = fs::read(&path).unwrap();
+            let read = read(&path).unwrap();
+
+            assert!(bytes.starts_with(format!("#9{:0>9}", bytes.len() - 11).as_bytes()));
+            assert_eq!(read.voltages, original.voltages);
+            assert_eq!(read.times, original.times);
+            assert_eq!(read.user_text, original.user_text);
+            assert_eq!(read.trigger_times, original.trigger_times);
+            assert_eq!(read.descriptor.comm_type, comm_type);
+            assert_eq!(read.descriptor.byte_order, byte_order);
+            assert_eq!(read.descriptor.trace_label, "run 1");
+            assert_eq!(read.descriptor.wave_array_count, 5);
+            assert_eq!(encode(&read), bytes);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn header_is_optional() {
+        let original = waveform(CommType::Word, ByteOrder::LittleEndian);
+        let bytes = encode(&original);
+        assert_eq!(decode(&bytes[11..]).unwrap(), decode(&bytes).unwrap());
+    }
+
+    #[test]
+    fn truncated_file_is_rejected() {
+        let bytes = encode(&waveform(CommType::Word, ByteOrder::LittleEndian));
+        for length in [bytes.len() - 1, 11 + 346, 11 + 100, 11, 0] {
+            assert!(
+                matches!(decode(&bytes[..length]), Err(MauiError::InvalidWaveform(_))),
+                "{} bytes",
+                length
+            );
+        }
+    }
+}
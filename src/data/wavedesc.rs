@@ -0,0 +1,986 @@
+//! ## WAVEDESC
+//!
+//! Parser and writer for the LeCroy waveform descriptor, the 346 byte block that
+//! precedes the sample data of every waveform sent by a MAUI oscilloscope or
+//! stored in a `.trc` file. The layout follows the `LECROY_2_3` template.
+//!
+
+use crate::{MauiError, Result};
+
+/// The length of a `LECROY_2_3` waveform descriptor.
+pub const WAVEDESC_LENGTH: usize = 346;
+
+/// The name every descriptor starts with.
+const DESCRIPTOR_NAME: &[u8] = b"WAVEDESC";
+
+// Offsets of the descriptor fields.
+const TEMPLATE_NAME: usize = 16;
+const COMM_TYPE: usize = 32;
+const COMM_ORDER: usize = 34;
+const WAVE_DESCRIPTOR: usize = 36;
+const USER_TEXT: usize = 40;
+const RES_DESC1: usize = 44;
+const TRIGTIME_ARRAY: usize = 48;
+const RIS_TIME_ARRAY: usize = 52;
+const RES_ARRAY1: usize = 56;
+const WAVE_ARRAY_1: usize = 60;
+const WAVE_ARRAY_2: usize = 64;
+const INSTRUMENT_NAME: usize = 76;
+const INSTRUMENT_NUMBER: usize = 92;
+const TRACE_LABEL: usize = 96;
+const WAVE_ARRAY_COUNT: usize = 116;
+const PNTS_PER_SCREEN: usize = 120;
+const FIRST_VALID_PNT: usize = 124;
+const LAST_VALID_PNT: usize = 128;
+const FIRST_POINT: usize = 132;
+const SPARSING_FACTOR: usize = 136;
+const SEGMENT_INDEX: usize = 140;
+const SUBARRAY_COUNT: usize = 144;
+const SWEEPS_PER_ACQ: usize = 148;
+const POINTS_PER_PAIR: usize = 152;
+const PAIR_OFFSET: usize = 154;
+const VERTICAL_GAIN: usize = 156;
+const VERTICAL_OFFSET: usize = 160;
+const MAX_VALUE: usize = 164;
+const MIN_VALUE: usize = 168;
+const NOMINAL_BITS: usize = 172;
+const NOM_SUBARRAY_COUNT: usize = 174;
+const HORIZ_INTERVAL: usize = 176;
+const HORIZ_OFFSET: usize = 180;
+const PIXEL_OFFSET: usize = 188;
+const VERTUNIT: usize = 196;
+const HORUNIT: usize = 244;
+const HORIZ_UNCERTAINTY: usize = 292;
+const TRIGGER_TIME: usize = 296;
+const ACQ_DURATION: usize = 312;
+const RECORD_TYPE: usize = 316;
+const PROCESSING_DONE: usize = 318;
+const RIS_SWEEPS: usize = 322;
+const TIMEBASE: usize = 324;
+const VERT_COUPLING: usize = 326;
+const PROBE_ATT: usize = 328;
+const FIXED_VERT_GAIN: usize = 332;
+const BANDWIDTH_LIMIT: usize = 334;
+const VERTICAL_VERNIER: usize = 336;
+const ACQ_VERT_OFFSET: usize = 340;
+const WAVE_SOURCE: usize = 344;
+
+/// ## Comm Type
+///
+/// The size of each sample in the wave arrays.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommType {
+    /// Samples are signed 8 bit integers.
+    Byte,
+    /// Samples are signed 16 bit integers.
+    Word,
+}
+
+impl CommType {
+    /// ## Sample Size
+    ///
+    /// Return the number of bytes per sample.
+    ///
+    pub fn sample_size(&self) -> usize {
+        match self {
+            CommType::Byte => 1,
+            CommType::Word => 2,
+        }
+    }
+}
+
+/// ## Byte Order
+///
+/// The byte order of the descriptor and of the 16 bit samples.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// HIFIRST, big endian.
+    BigEndian,
+    /// LOFIRST, little endian.
+    LittleEndian,
+}
+
+/// ## Record Type
+///
+/// How the waveform was acquired.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+    SingleSweep,
+    Interleaved,
+    Histogram,
+    Graph,
+    FilterCoefficient,
+    Complex,
+    Extrema,
+    SequenceObsolete,
+    CenteredRis,
+    PeakDetect,
+    Unknown(i16),
+}
+
+impl From<RecordType> for i16 {
+    fn from(value: RecordType) -> Self {
+        match value {
+            RecordType::SingleSweep => 0,
+            RecordType::Interleaved => 1,
+            RecordType::Histogram => 2,
+            RecordType::Graph => 3,
+            RecordType::FilterCoefficient => 4,
+            RecordType::Complex => 5,
+            RecordType::Extrema => 6,
+            RecordType::SequenceObsolete => 7,
+            RecordType::CenteredRis => 8,
+            RecordType::PeakDetect => 9,
+            RecordType::Unknown(value) => value,
+        }
+    }
+}
+
+impl From<i16> for RecordType {
+    fn from(value: i16) -> Self {
+        match value {
+            0 => RecordType::SingleSweep,
+            1 => RecordType::Interleaved,
+            2 => RecordType::Histogram,
+            3 => RecordType::Graph,
+            4 => RecordType::FilterCoefficient,
+            5 => RecordType::Complex,
+            6 => RecordType::Extrema,
+            7 => RecordType::SequenceObsolete,
+            8 => RecordType::CenteredRis,
+            9 => RecordType::PeakDetect,
+            value => RecordType::Unknown(value),
+        }
+    }
+}
+
+/// ## Processing Done
+///
+/// The processing applied to the waveform before it was sent.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessingDone {
+    NoProcessing,
+    FirFilter,
+    Interpolated,
+    Sparsed,
+    Autoscaled,
+    NoResult,
+    Rolling,
+    Cumulative,
+    Unknown(i16),
+}
+
+impl From<ProcessingDone> for i16 {
+    fn from(value: ProcessingDone) -> Self {
+        match value {
+            ProcessingDone::NoProcessing => 0,
+            ProcessingDone::FirFilter => 1,
+            ProcessingDone::Interpolated => 2,
+            ProcessingDone::Sparsed => 3,
+            ProcessingDone::Autoscaled => 4,
+            ProcessingDone::NoResult => 5,
+            ProcessingDone::Rolling => 6,
+            ProcessingDone::Cumulative => 7,
+            ProcessingDone::Unknown(value) => value,
+        }
+    }
+}
+
+impl From<i16> for ProcessingDone {
+    fn from(value: i16) -> Self {
+        match value {
+            0 => ProcessingDone::NoProcessing,
+            1 => ProcessingDone::FirFilter,
+            2 => ProcessingDone::Interpolated,
+            3 => ProcessingDone::Sparsed,
+            4 => ProcessingDone::Autoscaled,
+            5 => ProcessingDone::NoResult,
+            6 => ProcessingDone::Rolling,
+            7 => ProcessingDone::Cumulative,
+            value => ProcessingDone::Unknown(value),
+        }
+    }
+}
+
+/// ## Timebase
+///
+/// The time per division of the acquisition.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timebase {
+    /// Seconds per division.
+    PerDivision(f64),
+    /// The scope was clocked externally.
+    External,
+    Unknown(i16),
+}
+
+impl From<Timebase> for i16 {
+    fn from(value: Timebase) -> Self {
+        match value {
+            Timebase::PerDivision(seconds) => one_two_five_code(seconds, 1e-12, 47),
+            Timebase::External => 100,
+            Timebase::Unknown(value) => value,
+        }
+    }
+}
+
+impl From<i16> for Timebase {
+    fn from(value: i16) -> Self {
+        match value {
+            // 1 ps/div up to 5 ks/div in a 1-2-5 sequence
+            0..=47 => Timebase::PerDivision(one_two_five(value, 1e-12)),
+            100 => Timebase::External,
+            value => Timebase::Unknown(value),
+        }
+    }
+}
+
+/// ## Vertical Coupling
+///
+/// The input coupling of the source channel.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerticalCoupling {
+    Dc50Ohm,
+    Ground,
+    Dc1MOhm,
+    Ac1MOhm,
+    Unknown(i16),
+}
+
+impl From<VerticalCoupling> for i16 {
+    fn from(value: VerticalCoupling) -> Self {
+        match value {
+            VerticalCoupling::Dc50Ohm => 0,
+            VerticalCoupling::Ground => 1,
+            VerticalCoupling::Dc1MOhm => 2,
+            VerticalCoupling::Ac1MOhm => 4,
+            VerticalCoupling::Unknown(value) => value,
+        }
+    }
+}
+
+impl From<i16> for VerticalCoupling {
+    fn from(value: i16) -> Self {
+        match value {
+            0 => VerticalCoupling::Dc50Ohm,
+            1 | 3 => VerticalCoupling::Ground,
+            2 => VerticalCoupling::Dc1MOhm,
+            4 => VerticalCoupling::Ac1MOhm,
+            value => VerticalCoupling::Unknown(value),
+        }
+    }
+}
+
+/// ## Wave Source
+///
+/// The channel the waveform was acquired on.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaveSource {
+    /// Channel 1 to 4.
+    Channel(u8),
+    Unknown,
+}
+
+impl From<WaveSource> for i16 {
+    fn from(value: WaveSource) -> Self {
+        match value {
+            WaveSource::Channel(channel @ 1..=4) => channel as i16 - 1,
+            _ => 9,
+        }
+    }
+}
+
+impl From<i16> for WaveSource {
+    fn from(value: i16) -> Self {
+        match value {
+            0..=3 => WaveSource::Channel(value as u8 + 1),
+            _ => WaveSource::Unknown,
+        }
+    }
+}
+
+/// ## Trigger Time
+///
+/// The time stamp of the trigger of the acquisition.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriggerTime {
+    pub seconds: f64,
+    pub minutes: u8,
+    pub hours: u8,
+    pub days: u8,
+    pub months: u8,
+    pub year: u16,
+}
+
+impl TriggerTime {
+    /// ## To Unix Seconds
+    ///
+    /// Return the time stamp as seconds since 1970-01-01 00:00:00. The scope
+    /// clock carries no time zone, so the result is in the time zone the scope
+    /// is set to.
+    ///
+    pub fn to_unix_seconds(&self) -> f64 {
+        // days from the civil calendar, shifted to start the year in March
+        let month = self.months as i64;
+        let year = self.year as i64 - (month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + self.days as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+
+        let seconds = days * 86_400 + self.hours as i64 * 3_600 + self.minutes as i64 * 60;
+        seconds as f64 + self.seconds
+    }
+}
+
+/// ## WaveDesc
+///
+/// A parsed waveform descriptor. Lengths are in bytes, counts in samples.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct WaveDesc {
+    pub template_name: String,
+    pub comm_type: CommType,
+    pub byte_order: ByteOrder,
+    pub wave_descriptor_length: u32,
+    pub user_text_length: u32,
+    pub res_desc1_length: u32,
+    pub trigtime_array_length: u32,
+    pub ris_time_array_length: u32,
+    pub res_array1_length: u32,
+    pub wave_array_1_length: u32,
+    pub wave_array_2_length: u32,
+    pub instrument_name: String,
+    pub instrument_number: u32,
+    pub trace_label: String,
+    pub wave_array_count: u32,
+    pub points_per_screen: u32,
+    pub first_valid_point: u32,
+    pub last_valid_point: u32,
+    pub first_point: u32,
+    pub sparsing_factor: u32,
+    pub segment_index: u32,
+    pub subarray_count: u32,
+    pub sweeps_per_acquisition: u32,
+    pub points_per_pair: u16,
+    pub pair_offset: u16,
+    pub vertical_gain: f32,
+    pub vertical_offset: f32,
+    pub max_value: f32,
+    pub min_value: f32,
+    pub nominal_bits: u16,
+    pub nominal_subarray_count: u16,
+    pub horizontal_interval: f32,
+    pub horizontal_offset: f64,
+    pub pixel_offset: f64,
+    pub vertical_unit: String,
+    pub horizontal_unit: String,
+    pub horizontal_uncertainty: f32,
+    pub trigger_time: TriggerTime,
+    pub acquisition_duration: f32,
+    pub record_type: RecordType,
+    pub processing_done: ProcessingDone,
+    pub ris_sweeps: u16,
+    pub timebase: Timebase,
+    pub vertical_coupling: VerticalCoupling,
+    pub probe_attenuation: f32,
+    /// Volts per division, `None` if the code is not known.
+    pub fixed_vertical_gain: Option<f64>,
+    pub bandwidth_limit: bool,
+    pub vertical_vernier: f32,
+    pub acquisition_vertical_offset: f32,
+    pub wave_source: WaveSource,
+}
+
+impl Default for WaveDesc {
+    /// An empty little endian descriptor for 16 bit samples.
+    fn default() -> Self {
+        WaveDesc {
+            template_name: "LECROY_2_3".into(),
+            comm_type: CommType::Word,
+            byte_order: ByteOrder::LittleEndian,
+            wave_descriptor_length: WAVEDESC_LENGTH as u32,
+            user_text_length: 0,
+            res_desc1_length: 0,
+            trigtime_array_length: 0,
+            ris_time_array_length: 0,
+            res_array1_length: 0,
+            wave_array_1_length: 0,
+            wave_array_2_length: 0,
+            instrument_name: String::new(),
+            instrument_number: 0,
+            trace_label: String::new(),
+            wave_array_count: 0,
+            points_per_screen: 0,
+            first_valid_point: 0,
+            last_valid_point: 0,
+            first_point: 0,
+            sparsing_factor: 1,
+            segment_index: 0,
+            subarray_count: 1,
+            sweeps_per_acquisition: 1,
+            points_per_pair: 0,
+            pair_offset: 0,
+            vertical_gain: 1.0,
+            vertical_offset: 0.0,
+            max_value: 0.0,
+            min_value: 0.0,
+            nominal_bits: 8,
+            nominal_subarray_count: 1,
+            horizontal_interval: 1.0,
+            horizontal_offset: 0.0,
+            pixel_offset: 0.0,
+            vertical_unit: "V".into(),
+            horizontal_unit: "S".into(),
+            horizontal_uncertainty: 0.0,
+            trigger_time: TriggerTime {
+                seconds: 0.0,
+                minutes: 0,
+                hours: 0,
+                days: 1,
+                months: 1,
+                year: 2000,
+            },
+            acquisition_duration: 0.0,
+            record_type: RecordType::SingleSweep,
+            processing_done: ProcessingDone::NoProcessing,
+            ris_sweeps: 1,
+            timebase: Timebase::Unknown(0),
+            vertical_coupling: VerticalCoupling::Dc1MOhm,
+            probe_attenuation: 1.0,
+            fixed_vertical_gain: None,
+            bandwidth_limit: false,
+            vertical_vernier: 1.0,
+            acquisition_vertical_offset: 0.0,
+            wave_source: WaveSource::Unknown,
+        }
+    }
+}
+
+impl WaveDesc {
+    /// ## Parse
+    ///
+    /// Parse a descriptor from `bytes`, which must start with `WAVEDESC`. The
+    /// byte order is read from the descriptor itself.
+    ///
+    pub fn parse(bytes: &[u8]) -> Result<WaveDesc> {
+        if bytes.len() < WAVEDESC_LENGTH {
+            return Err(MauiError::InvalidWaveform(format!(
+                "descriptor is {} bytes long, expected {}",
+                bytes.len(),
+                WAVEDESC_LENGTH
+            )));
+        }
+        if !bytes.starts_with(DESCRIPTOR_NAME) {
+            return Err(MauiError::InvalidWaveform(
+                "descriptor does not start with WAVEDESC".into(),
+            ));
+        }
+
+        // COMM_ORDER is 0 for HIFIRST and 1 for LOFIRST, in either order the
+        // first byte tells them apart
+        let byte_order = match (bytes[COMM_ORDER], bytes[COMM_ORDER + 1]) {
+            (0, 0) => ByteOrder::BigEndian,
+            (1, 0) => ByteOrder::LittleEndian,
+            (a, b) => {
+                return Err(MauiError::InvalidWaveform(format!(
+                    "unknown byte order {:#04x}{:02x}",
+                    a, b
+                )))
+            }
+        };
+        let r = Fields { bytes, byte_order };
+
+        let comm_type = match r.i16(COMM_TYPE) {
+            0 => CommType::Byte,
+            1 => CommType::Word,
+            value => {
+                return Err(MauiError::InvalidWaveform(format!(
+                    "unknown comm type {}",
+                    value
+                )))
+            }
+        };
+
+        Ok(WaveDesc {
+            template_name: r.string(TEMPLATE_NAME, 16),
+            comm_type,
+            byte_order,
+            wave_descriptor_length: r.u32(WAVE_DESCRIPTOR),
+            user_text_length: r.u32(USER_TEXT),
+            res_desc1_length: r.u32(RES_DESC1),
+            trigtime_array_length: r.u32(TRIGTIME_ARRAY),
+            ris_time_array_length: r.u32(RIS_TIME_ARRAY),
+            res_array1_length: r.u32(RES_ARRAY1),
+            wave_array_1_length: r.u32(WAVE_ARRAY_1),
+            wave_array_2_length: r.u32(WAVE_ARRAY_2),
+            instrument_name: r.string(INSTRUMENT_NAME, 16),
+            instrument_number: r.u32(INSTRUMENT_NUMBER),
+            trace_label: r.string(TRACE_LABEL, 16),
+            wave_array_count: r.u32(WAVE_ARRAY_COUNT),
+            points_per_screen: r.u32(PNTS_PER_SCREEN),
+            first_valid_point: r.u32(FIRST_VALID_PNT),
+            last_valid_point: r.u32(LAST_VALID_PNT),
+            first_point: r.u32(FIRST_POINT),
+            sparsing_factor: r.u32(SPARSING_FACTOR),
+            segment_index: r.u32(SEGMENT_INDEX),
+            subarray_count: r.u32(SUBARRAY_COUNT),
+            sweeps_per_acquisition: r.u32(SWEEPS_PER_ACQ),
+            points_per_pair: r.u16(POINTS_PER_PAIR),
+            pair_offset: r.u16(PAIR_OFFSET),
+            vertical_gain: r.f32(VERTICAL_GAIN),
+            vertical_offset: r.f32(VERTICAL_OFFSET),
+            max_value: r.f32(MAX_VALUE),
+            min_value: r.f32(MIN_VALUE),
+            nominal_bits: r.u16(NOMINAL_BITS),
+            nominal_subarray_count: r.u16(NOM_SUBARRAY_COUNT),
+            horizontal_interval: r.f32(HORIZ_INTERVAL),
+            horizontal_offset: r.f64(HORIZ_OFFSET),
+            pixel_offset: r.f64(PIXEL_OFFSET),
+            vertical_unit: r.string(VERTUNIT, 48),
+            horizontal_unit: r.string(HORUNIT, 48),
+            horizontal_uncertainty: r.f32(HORIZ_UNCERTAINTY),
+            trigger_time: TriggerTime {
+                seconds: r.f64(TRIGGER_TIME),
+                minutes: bytes[TRIGGER_TIME + 8],
+                hours: bytes[TRIGGER_TIME + 9],
+                days: bytes[TRIGGER_TIME + 10],
+                months: bytes[TRIGGER_TIME + 11],
+                year: r.u16(TRIGGER_TIME + 12),
+            },
+            acquisition_duration: r.f32(ACQ_DURATION),
+            record_type: RecordType::from(r.i16(RECORD_TYPE)),
+            processing_done: ProcessingDone::from(r.i16(PROCESSING_DONE)),
+            ris_sweeps: r.u16(RIS_SWEEPS),
+            timebase: Timebase::from(r.i16(TIMEBASE)),
+            vertical_coupling: VerticalCoupling::from(r.i16(VERT_COUPLING)),
+            probe_attenuation: r.f32(PROBE_ATT),
+            fixed_vertical_gain: match r.i16(FIXED_VERT_GAIN) {
+                // 1 uV/div up to 1 kV/div in a 1-2-5 sequence
+                code @ 0..=27 => Some(one_two_five(code, 1e-6)),
+                _ => None,
+            },
+            bandwidth_limit: r.i16(BANDWIDTH_LIMIT) != 0,
+            vertical_vernier: r.f32(VERTICAL_VERNIER),
+            acquisition_vertical_offset: r.f32(ACQ_VERT_OFFSET),
+            wave_source: WaveSource::from(r.i16(WAVE_SOURCE)),
+        })
+    }
+
+    /// ## To Bytes
+    ///
+    /// Serialize the descriptor into a 346 byte block in its byte order.
+    /// Strings longer than their field are truncated.
+    ///
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; WAVEDESC_LENGTH];
+        bytes[..DESCRIPTOR_NAME.len()].copy_from_slice(DESCRIPTOR_NAME);
+        let mut w = FieldsMut {
+            bytes: &mut bytes,
+            byte_order: self.byte_order,
+        };
+
+        w.string(TEMPLATE_NAME, 16, &self.template_name);
+        w.u16(COMM_TYPE, self.comm_type.sample_size() as u16 - 1);
+        w.u16(
+            COMM_ORDER,
+            match self.byte_order {
+                ByteOrder::BigEndian => 0,
+                ByteOrder::LittleEndian => 1,
+            },
+        );
+        w.u32(WAVE_DESCRIPTOR, self.wave_descriptor_length);
+        w.u32(USER_TEXT, self.user_text_length);
+        w.u32(RES_DESC1, self.res_desc1_length);
+        w.u32(TRIGTIME_ARRAY, self.trigtime_array_length);
+        w.u32(RIS_TIME_ARRAY, self.ris_time_array_length);
+        w.u32(RES_ARRAY1, self.res_array1_length);
+        w.u32(WAVE_ARRAY_1, self.wave_array_1_length);
+        w.u32(WAVE_ARRAY_2, self.wave_array_2_length);
+        w.string(INSTRUMENT_NAME, 16, &self.instrument_name);
+        w.u32(INSTRUMENT_NUMBER, self.instrument_number);
+        w.string(TRACE_LABEL, 16, &self.trace_label);
+        w.u32(WAVE_ARRAY_COUNT, self.wave_array_count);
+        w.u32(PNTS_PER_SCREEN, self.points_per_screen);
+        w.u32(FIRST_VALID_PNT, self.first_valid_point);
+        w.u32(LAST_VALID_PNT, self.last_valid_point);
+        w.u32(FIRST_POINT, self.first_point);
+        w.u32(SPARSING_FACTOR, self.sparsing_factor);
+        w.u32(SEGMENT_INDEX, self.segment_index);
+        w.u32(SUBARRAY_COUNT, self.subarray_count);
+        w.u32(SWEEPS_PER_ACQ, self.sweeps_per_acquisition);
+        w.u16(POINTS_PER_PAIR, self.points_per_pair);
+        w.u16(PAIR_OFFSET, self.pair_offset);
+        w.f32(VERTICAL_GAIN, self.vertical_gain);
+        w.f32(VERTICAL_OFFSET, self.vertical_offset);
+        w.f32(MAX_VALUE, self.max_value);
+        w.f32(MIN_VALUE, self.min_value);
+        w.u16(NOMINAL_BITS, self.nominal_bits);
+        w.u16(NOM_SUBARRAY_COUNT, self.nominal_subarray_count);
+        w.f32(HORIZ_INTERVAL, self.horizontal_interval);
+        w.f64(HORIZ_OFFSET, self.horizontal_offset);
+        w.f64(PIXEL_OFFSET, self.pixel_offset);
+        w.string(VERTUNIT, 48, &self.vertical_unit);
+        w.string(HORUNIT, 48, &self.horizontal_unit);
+        w.f32(HORIZ_UNCERTAINTY, self.horizontal_uncertainty);
+        w.f64(TRIGGER_TIME, self.trigger_time.seconds);
+        w.bytes[TRIGGER_TIME + 8] = self.trigger_time.minutes;
+        w.bytes[TRIGGER_TIME + 9] = self.trigger_time.hours;
+        w.bytes[TRIGGER_TIME + 10] = self.trigger_time.days;
+        w.bytes[TRIGGER_TIME + 11] = self.trigger_time.months;
+        w.u16(TRIGGER_TIME + 12, self.trigger_time.year);
+        w.f32(ACQ_DURATION, self.acquisition_duration);
+        w.u16(RECORD_TYPE, i16::from(self.record_type) as u16);
+        w.u16(PROCESSING_DONE, i16::from(self.processing_done) as u16);
+        w.u16(RIS_SWEEPS, self.ris_sweeps);
+        w.u16(TIMEBASE, i16::from(self.timebase) as u16);
+        w.u16(VERT_COUPLING, i16::from(self.vertical_coupling) as u16);
+        w.f32(PROBE_ATT, self.probe_attenuation);
+        let fixed_vertical_gain = match self.fixed_vertical_gain {
+            Some(volts) => one_two_five_code(volts, 1e-6, 27),
+            None => -1,
+        };
+        w.u16(FIXED_VERT_GAIN, fixed_vertical_gain as u16);
+        w.u16(BANDWIDTH_LIMIT, self.bandwidth_limit as u16);
+        w.f32(VERTICAL_VERNIER, self.vertical_vernier);
+        w.f32(ACQ_VERT_OFFSET, self.acquisition_vertical_offset);
+        w.u16(WAVE_SOURCE, i16::from(self.wave_source) as u16);
+
+        bytes
+    }
+
+    /// ## Find
+    ///
+    /// Return the position of the descriptor in a buffer that may start with
+    /// a command header or a block header, such as a `WAVEFORM?` response.
+    ///
+    pub fn find(bytes: &[u8]) -> Option<usize> {
+        bytes
+            .windows(DESCRIPTOR_NAME.len())
+            .position(|window| window == DESCRIPTOR_NAME)
+    }
+
+    /// ## Block Length
+    ///
+    /// Return the length of the descriptor and all the blocks following it.
+    ///
+    pub fn block_length(&self) -> usize {
+        [
+            self.wave_descriptor_length,
+            self.user_text_length,
+            self.res_desc1_length,
+            self.trigtime_array_length,
+            self.ris_time_array_length,
+            self.res_array1_length,
+            self.wave_array_1_length,
+            self.wave_array_2_length,
+        ]
+        .iter()
+        .map(|&length| length as usize)
+        .sum()
+    }
+
+    /// ## To Volts
+    ///
+    /// Convert a raw sample into volts, or into the vertical unit of the
+    /// waveform.
+    ///
+    pub fn to_volts(&self, sample: f64) -> f64 {
+        self.vertical_gain as f64 * sample - self.vertical_offset as f64
+    }
+
+    /// ## Time Of
+    ///
+    /// Return the time in seconds, relative to the trigger, of the sample at
+    /// `index` in the wave array.
+    ///
+    pub fn time_of(&self, index: usize) -> f64 {
+        self.horizontal_interval as f64 * index as f64 + self.horizontal_offset
+    }
+}
+
+/// Return the value of `code` in a 1-2-5 sequence starting at `first`.
+fn one_two_five(code: i16, first: f64) -> f64 {
+    let mantissa = [1.0, 2.0, 5.0][(code % 3) as usize];
+    mantissa * first * 10f64.powi((code / 3) as i32)
+}
+
+/// Return the code of the 1-2-5 step closest to `value` in a sequence
+/// starting at `first`, up to `last`.
+fn one_two_five_code(value: f64, first: f64, last: i16) -> i16 {
+    (0..=last)
+        .min_by(|&a, &b| {
+            let a = (one_two_five(a, first).ln() - value.ln()).abs();
+            let b = (one_two_five(b, first).ln() - value.ln()).abs();
+            a.total_cmp(&b)
+        })
+        .unwrap_or(0)
+}
+
+/// Typed access to the fields of a descriptor in its byte order.
+struct Fields<'a> {
+    bytes: &'a [u8],
+    byte_order: ByteOrder,
+}
+
+impl Fields<'_> {
+    fn array<const N: usize>(&self, offset: usize) -> [u8; N] {
+        let mut array = [0u8; N];
+        array.copy_from_slice(&self.bytes[offset..offset + N]);
+        array
+    }
+
+    fn u16(&self, offset: usize) -> u16 {
+        match self.byte_order {
+            ByteOrder::BigEndian => u16::from_be_bytes(self.array(offset)),
+            ByteOrder::LittleEndian => u16::from_le_bytes(self.array(offset)),
+        }
+    }
+
+    fn i16(&self, offset: usize) -> i16 {
+        self.u16(offset) as i16
+    }
+
+    fn u32(&self, offset: usize) -> u32 {
+        match self.byte_order {
+            ByteOrder::BigEndian => u32::from_be_bytes(self.array(offset)),
+            ByteOrder::LittleEndian => u32::from_le_bytes(self.array(offset)),
+        }
+    }
+
+    fn f32(&self, offset: usize) -> f32 {
+        f32::from_bits(self.u32(offset))
+    }
+
+    fn f64(&self, offset: usize) -> f64 {
+        let bits = match self.byte_order {
+            ByteOrder::BigEndian => u64::from_be_bytes(self.array(offset)),
+            ByteOrder::LittleEndian => u64::from_le_bytes(self.array(offset)),
+        };
+        f64::from_bits(bits)
+    }
+
+    /// Read a NUL padded string.
+    fn string(&self, offset: usize, length: usize) -> String {
+        let field = &self.bytes[offset..offset + length];
+        let end = field.iter().position(|&b| b == 0).unwrap_or(length);
+        String::from_utf8_lossy(&field[..end])
+            .trim_end()
+            .to_string()
+    }
+}
+
+/// Typed writes to the fields of a descriptor in its byte order.
+struct FieldsMut<'a> {
+    bytes: &'a mut [u8],
+    byte_order: ByteOrder,
+}
+
+impl FieldsMut<'_> {
+    fn u16(&mut self, offset: usize, value: u16) {
+        let bytes = match self.byte_order {
+            ByteOrder::BigEndian => value.to_be_bytes(),
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+        };
+        self.bytes[offset..offset + 2].copy_from_slice(&bytes);
+    }
+
+    fn u32(&mut self, offset: usize, value: u32) {
+        let bytes = match self.byte_order {
+            ByteOrder::BigEndian => value.to_be_bytes(),
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+        };
+        self.bytes[offset..offset + 4].copy_from_slice(&bytes);
+    }
+
+    fn f32(&mut self, offset: usize, value: f32) {
+        self.u32(offset, value.to_bits());
+    }
+
+    fn f64(&mut self, offset: usize, value: f64) {
+        let bytes = match self.byte_order {
+            ByteOrder::BigEndian => value.to_bits().to_be_bytes(),
+            ByteOrder::LittleEndian => value.to_bits().to_le_bytes(),
+        };
+        self.bytes[offset..offset + 8].copy_from_slice(&bytes);
+    }
+
+    /// Write a NUL padded string.
+    fn string(&mut self, offset: usize, length: usize, value: &str) {
+        let value = value.as_bytes();
+        let end = value.len().min(length);
+        self.bytes[offset..offset + end].copy_from_slice(&value[..end]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a descriptor by hand, writing each field at its documented
+    /// offset in the LECROY_2_3 template.
+    fn known_descriptor(comm_type: u16, big_endian: bool) -> Vec<u8> {
+        let mut bytes = vec![0u8; 346];
+        let mut put = |offset: usize, value: &[u8]| {
+            bytes[offset..offset + value.len()].copy_from_slice(value);
+        };
+        let u16 = |v: u16| match big_endian {
+            true => v.to_be_bytes().to_vec(),
+            false => v.to_le_bytes().to_vec(),
+        };
+        let u32 = |v: u32| match big_endian {
+            true => v.to_be_bytes().to_vec(),
+            false => v.to_le_bytes().to_vec(),
+        };
+        let f64 = |v: f64| match big_endian {
+            true => v.to_be_bytes().to_vec(),
+            false => v.to_le_bytes().to_vec(),
+        };
+
+        put(0, b"WAVEDESC");
+        put(16, b"LECROY_2_3");
+        put(32, &u16(comm_type));
+        put(34, &u16(!big_endian as u16));
+        put(36, &u32(346));
+        put(60, &u32(2000));
+        put(76, b"LECROY");
+        put(92, &u32(12345));
+        put(116, &u32(1000));
+        put(156, &u32(0.001f32.to_bits()));
+        put(160, &u32(0.5f32.to_bits()));
+        put(176, &u32(1e-9f32.to_bits()));
+        put(180, &f64(-5e-7));
+        put(196, b"V");
+        put(244, b"S");
+        put(296, &f64(12.5));
+        put(304, &[30, 14, 17, 10]);
+        put(308, &u16(2026));
+        put(316, &u16(9));
+        put(324, &u16(14));
+        put(326, &u16(1));
+        put(332, &u16(13));
+        put(334, &u16(1));
+        put(344, &u16(2));
+        bytes
+    }
+
+    fn check_known(desc: &WaveDesc) {
+        assert_eq!(desc.template_name, "LECROY_2_3");
+        assert_eq!(desc.wave_descriptor_length, 346);
+        assert_eq!(desc.wave_array_1_length, 2000);
+        assert_eq!(desc.instrument_name, "LECROY");
+        assert_eq!(desc.instrument_number, 12345);
+        assert_eq!(desc.wave_array_count, 1000);
+        assert_eq!(desc.vertical_gain, 0.001);
+        assert_eq!(desc.vertical_offset, 0.5);
+        assert_eq!(desc.horizontal_interval, 1e-9);
+        assert_eq!(desc.horizontal_offset, -5e-7);
+        assert_eq!(desc.vertical_unit, "V");
+        assert_eq!(desc.horizontal_unit, "S");
+        assert_eq!(
+            desc.trigger_time,
+            TriggerTime {
+                seconds: 12.5,
+                minutes: 30,
+                hours: 14,
+                days: 17,
+                months: 10,
+                year: 2026,
+            }
+        );
+        assert_eq!(desc.record_type, RecordType::PeakDetect);
+        // code 14 is 50 ns/div in the 1-2-5 sequence starting at 1 ps/div
+        match desc.timebase {
+            Timebase::PerDivision(seconds) => assert!((seconds - 50e-9).abs() < 1e-18),
+            timebase => panic!("unexpected timebase {:?}", timebase),
+        }
+        assert_eq!(desc.vertical_coupling, VerticalCoupling::Ground);
+        // code 13 is 20 mV/div in the 1-2-5 sequence starting at 1 uV/div
+        assert!((desc.fixed_vertical_gain.unwrap() - 20e-3).abs() < 1e-12);
+        assert!(desc.bandwidth_limit);
+        assert_eq!(desc.wave_source, WaveSource::Channel(3));
+    }
+
+    #[test]
+    fn parse_known_little_endian_word() {
+        let desc = WaveDesc::parse(&known_descriptor(1, false)).unwrap();
+        assert_eq!(desc.byte_order, ByteOrder::LittleEndian);
+        assert_eq!(desc.comm_type, CommType::Word);
+        check_known(&desc);
+    }
+
+    #[test]
+    fn parse_known_big_endian_byte() {
+        let desc = WaveDesc::parse(&known_descriptor(0, true)).unwrap();
+        assert_eq!(desc.byte_order, ByteOrder::BigEndian);
+        assert_eq!(desc.comm_type, CommType::Byte);
+        check_known(&desc);
+    }
+
+    #[test]
+    fn parse_write_parse_round_trip() {
+        for (comm_type, big_endian) in [(0, false), (1, false), (0, true), (1, true)] {
+            let bytes = known_descriptor(comm_type, big_endian);
+            let desc = WaveDesc::parse(&bytes).unwrap();
+            let written = desc.to_bytes();
+            assert_eq!(written, bytes);
+            assert_eq!(WaveDesc::parse(&written).unwrap(), desc);
+        }
+    }
+
+    #[test]
+    fn unknown_codes_survive_round_trip() {
+        let desc = WaveDesc {
+            record_type: RecordType::Unknown(42),
+            processing_done: ProcessingDone::from(99),
+            timebase: Timebase::Unknown(77),
+            ..WaveDesc::default()
+        };
+        assert_eq!(WaveDesc::parse(&desc.to_bytes()).unwrap(), desc);
+    }
+
+    #[test]
+    fn truncated_descriptor_is_rejected() {
+        let bytes = known_descriptor(1, false);
+        assert!(matches!(
+            WaveDesc::parse(&bytes[..345]),
+            Err(MauiError::InvalidWaveform(_))
+        ));
+        assert!(WaveDesc::parse(&[]).is_err());
+    }
+
+    #[test]
+    fn missing_name_is_rejected() {
+        let mut bytes = known_descriptor(1, false);
+        bytes[..8].copy_from_slice(b"WAVEDESX");
+        assert!(matches!(
+            WaveDesc::parse(&bytes),
+            Err(MauiError::InvalidWaveform(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_comm_type_is_rejected() {
+        let mut bytes = known_descriptor(1, false);
+        bytes[32..34].copy_from_slice(&7u16.to_le_bytes());
+        let error = WaveDesc::parse(&bytes).unwrap_err().to_string();
+        assert!(error.contains("unknown comm type 7"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_byte_order_is_rejected() {
+        let mut bytes = known_descriptor(1, false);
+        bytes[34..36].copy_from_slice(&[2, 0]);
+        let error = WaveDesc::parse(&bytes).unwrap_err().to_string();
+        assert!(error.contains("unknown byte order"), "{}", error);
+    }
+}
@@ -0,0 +1,367 @@
+//! ## Waveform
+//!
+//! Decoding of the binary waveforms sent by `WAVEFORM?` into samples scaled
+//! to volts and seconds, and encoding back into the same binary layout.
+//!
+
+use crate::data::raw::RawWaveform;
+use crate::data::wavedesc::{ByteOrder, CommType, WaveDesc, WAVEDESC_LENGTH};
+use crate::{MauiError, Result};
+
+/// ## Segment Trigger
+///
+/// An entry of the TRIGTIME array, describing the trigger of one segment.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SegmentTrigger {
+    /// Time of the trigger relative to the trigger of the first segment.
+    pub time: f64,
+    /// Time of the first sample of the segment relative to its trigger.
+    pub offset: f64,
+}
+
+/// ## Segment
+///
+/// One segment of a waveform acquired in sequence mode.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    /// The position of the segment in the sequence, starting at 0.
+    pub index: usize,
+    /// Time of the trigger relative to the trigger of the first segment.
+    pub trigger_time: f64,
+    /// Time of the trigger in seconds since 1970-01-01, in the time zone of
+    /// the scope clock.
+    pub timestamp: f64,
+    /// Time of each sample relative to the trigger of this segment.
+    pub times: Vec<f64>,
+    pub voltages: Vec<f64>,
+}
+
+/// ## Waveform
+///
+/// A waveform decoded from the WAVEDESC, USERTEXT, TRIGTIME, RISTIME,
+/// WAVE_ARRAY_1 and WAVE_ARRAY_2 blocks. The samples are scaled with the
+/// vertical gain and offset of the descriptor, and `times` holds the time of
+/// each sample relative to the trigger of its segment.
+///
+/// Waveforms acquired in sequence mode hold all the segments one after the
+/// other; use [`Waveform::segments`] to split them.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Waveform {
+    pub descriptor: WaveDesc,
+    pub user_text: String,
+    pub trigger_times: Vec<SegmentTrigger>,
+    pub ris_times: Vec<f64>,
+    pub times: Vec<f64>,
+    pub voltages: Vec<f64>,
+    /// The second wave array, only present for complex and extrema records.
+    pub voltages_2: Option<Vec<f64>>,
+}
+
+impl Waveform {
+    /// ## Parse
+    ///
+    /// Decode a waveform from a `WAVEFORM? ALL` response or the content of a
+    /// `.trc` file. Anything before the descriptor, such as a command header
+    /// or an IEEE 488.2 block header, is skipped.
+    ///
+    pub fn parse(bytes: &[u8]) -> Result<Waveform> {
+        Ok(RawWaveform::parse(bytes)?.to_waveform())
+    }
+
+    /// ## Segments
+    ///
+    /// Split the samples into one record per segment, using the TRIGTIME array
+    /// for the trigger time of each segment. A waveform that was not acquired
+    /// in sequence mode is returned as a single segment.
+    ///
+    pub fn segments(&self) -> Vec<Segment> {
+        let segment_length = segment_length(&self.descriptor, self.voltages.len());
+        let timestamp = self.descriptor.trigger_time.to_unix_seconds();
+
+        self.voltages
+            .chunks(segment_length)
+            .zip(self.times.chunks(segment_length))
+            .enumerate()
+            .map(|(index, (voltages, times))| {
+                let trigger_time = self
+                    .trigger_times
+                    .get(index)
+                    .map(|trigger| trigger.time)
+                    .unwrap_or(0.0);
+                Segment {
+                    index,
+                    trigger_time,
+                    timestamp: timestamp + trigger_time,
+                    times: times.to_vec(),
+                    voltages: voltages.to_vec(),
+                }
+            })
+            .collect()
+    }
+
+    /// ## To Bytes
+    ///
+    /// Encode the waveform into the binary layout of a `WAVEFORM? ALL`
+    /// response, without the block header. The voltages are quantized with
+    /// the comm type, byte order, gain and offset of the descriptor, and the
+    /// block lengths of the descriptor are updated to match the content.
+    ///
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let user_text = self.user_text.as_bytes();
+        let trigtime: Vec<f64> = self
+            .trigger_times
+            .iter()
+            .flat_map(|trigger| [trigger.time, trigger.offset])
+            .collect();
+        let wave_array_1 = encode_samples(&self.descriptor, &self.voltages);
+        let wave_array_2 = self
+            .voltages_2
+            .as_ref()
+            .map(|voltages| encode_samples(&self.descriptor, voltages))
+            .unwrap_or_default();
+
+        let descriptor = WaveDesc {
+            wave_descriptor_length: WAVEDESC_LENGTH as u32,
+            user_text_length: user_text.len() as u32,
+            res_desc1_length: 0,
+            trigtime_array_length: (trigtime.len() * 8) as u32,
+            ris_time_array_length: (self.ris_times.len() * 8) as u32,
+            res_array1_length: 0,
+            wave_array_1_length: wave_array_1.len() as u32,
+            wave_array_2_length: wave_array_2.len() as u32,
+            wave_array_count: self.voltages.len() as u32,
+            ..self.descriptor.clone()
+        };
+
+        let mut bytes = descriptor.to_bytes();
+        bytes.extend_from_slice(user_text);
+        bytes.extend(encode_f64(&descriptor, &trigtime));
+        bytes.extend(encode_f64(&descriptor, &self.ris_times));
+        bytes.extend(wave_array_1);
+        bytes.extend(wave_array_2);
+        bytes
+    }
+}
+
+/// The blocks of a binary waveform, split according to the lengths in its
+/// descriptor.
+pub(crate) struct Blocks<'a> {
+    pub(crate) descriptor: WaveDesc,
+    pub(crate) user_text: &'a [u8],
+    pub(crate) trigtime: &'a [u8],
+    pub(crate) ris_time: &'a [u8],
+    pub(crate) wave_array_1: &'a [u8],
+    pub(crate) wave_array_2: &'a [u8],
+}
+
+impl<'a> Blocks<'a> {
+    /// Locate the descriptor in `bytes` and split the blocks following it.
+    pub(crate) fn split(bytes: &'a [u8]) -> Result<Blocks<'a>> {
+        let start = WaveDesc::find(bytes)
+            .ok_or_else(|| MauiError::InvalidWaveform("no WAVEDESC block found".into()))?;
+        let bytes = &bytes[start..];
+        let descriptor = WaveDesc::parse(bytes)?;
+
+        if bytes.len() < descriptor.block_length() {
+            return Err(MauiError::InvalidWaveform(format!(
+                "waveform is truncated, expected {} bytes after WAVEDESC but got {}",
+                descriptor.block_length(),
+                bytes.len()
+            )));
+        }
+
+        let mut rest = &bytes[descriptor.wave_descriptor_length as usize..];
+        let mut take = |length: u32| {
+            let (block, tail) = rest.split_at(length as usize);
+            rest = tail;
+            block
+        };
+        let user_text = take(descriptor.user_text_length);
+        take(descriptor.res_desc1_length);
+        let trigtime = take(descriptor.trigtime_array_length);
+        let ris_time = take(descriptor.ris_time_array_length);
+        take(descriptor.res_array1_length);
+        let wave_array_1 = take(descriptor.wave_array_1_length);
+        let wave_array_2 = take(descriptor.wave_array_2_length);
+
+        Ok(Blocks {
+            descriptor,
+            user_text,
+            trigtime,
+            ris_time,
+            wave_array_1,
+            wave_array_2,
+        })
+    }
+}
+
+/// Return the number of samples in each segment of a wave array of `count`
+/// samples.
+pub(crate) fn segment_length(descriptor: &WaveDesc, count: usize) -> usize {
+    let segments = descriptor.subarray_count.max(1) as usize;
+    (count / segments).max(1)
+}
+
+/// Decode the raw samples of a wave array.
+pub(crate) fn decode_samples<'a>(
+    descriptor: &WaveDesc,
+    bytes: &'a [u8],
+) -> impl Iterator<Item = i16> + 'a {
+    let comm_type = descriptor.comm_type;
+    let byte_order = descriptor.byte_order;
+    bytes
+        .chunks_exact(comm_type.sample_size())
+        .map(move |sample| match (comm_type, byte_order) {
+            (CommType::Byte, _) => sample[0] as i8 as i16,
+            (CommType::Word, ByteOrder::BigEndian) => i16::from_be_bytes([sample[0], sample[1]]),
+            (CommType::Word, ByteOrder::LittleEndian) => i16::from_le_bytes([sample[0], sample[1]]),
+        })
+}
+
+/// Quantize voltages into the raw samples of a wave array, saturating at the
+/// limits of the comm type.
+fn encode_samples(descriptor: &WaveDesc, voltages: &[f64]) -> Vec<u8> {
+    let gain = descriptor.vertical_gain as f64;
+    let offset = descriptor.vertical_offset as f64;
+    let mut bytes = Vec::with_capacity(voltages.len() * descriptor.comm_type.sample_size());
+    for volts in voltages {
+        let raw = ((volts + offset) / gain).round();
+        match (descriptor.comm_type, descriptor.byte_order) {
+            (CommType::Byte, _) => {
+                bytes.push(raw.clamp(i8::MIN as f64, i8::MAX as f64) as i8 as u8)
+            }
+            (CommType::Word, byte_order) => {
+                let raw = raw.clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+                match byte_order {
+                    ByteOrder::BigEndian => bytes.extend_from_slice(&raw.to_be_bytes()),
+                    ByteOrder::LittleEndian => bytes.extend_from_slice(&raw.to_le_bytes()),
+                }
+            }
+        }
+    }
+    bytes
+}
+
+/// Encode an array of doubles such as TRIGTIME or RISTIME.
+fn encode_f64<'a>(descriptor: &WaveDesc, values: &'a [f64]) -> impl Iterator<Item = u8> + 'a {
+    let byte_order = descriptor.byte_order;
+    values.iter().flat_map(move |value| match byte_order {
+        ByteOrder::BigEndian => value.to_be_bytes(),
+        ByteOrder::LittleEndian => value.to_le_bytes(),
+    })
+}
+
+/// Decode an array of doubles such as TRIGTIME or RISTIME.
+pub(crate) fn decode_f64<'a>(
+    descriptor: &WaveDesc,
+    bytes: &'a [u8],
+) -> impl Iterator<Item = f64> + 'a {
+    let byte_order = descriptor.byte_order;
+    bytes.chunks_exact(8).map(move |value| {
+        let mut array = [0u8; 8];
+        array.copy_from_slice(value);
+        match byte_order {
+            ByteOrder::BigEndian => f64::from_be_bytes(array),
+            ByteOrder::LittleEndian => f64::from_le_bytes(array),
+        }
+    })
+}
+
+/// Decode the NUL padded USERTEXT block.
+pub(crate) fn decode_text(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sequence of three segments of two samples, triggered 1 ms apart.
+    fn sequence() -> Vec<u8> {
+        let descriptor = WaveDesc {
+            subarray_count: 3,
+            horizontal_interval: 0.5,
+            horizontal_offset: -5.0,
+            ..WaveDesc::default()
+        };
+        let trigger_times = (0..3)
+            .map(|i| SegmentTrigger {
+                time: i as f64 * 1e-3,
+                offset: -0.25 * (i + 1) as f64,
+            })
+            .collect();
+        Waveform {
+            descriptor,
+            user_text: String::new(),
+            trigger_times,
+            ris_times: Vec::new(),
+            times: Vec::new(),
+            voltages: vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+            voltages_2: None,
+        }
+        .to_bytes()
+    }
+
+    #[test]
+    fn segments_are_split_with_their_triggers() {
+        let waveform = Waveform::parse(&sequence()).unwrap();
+        let start = waveform.descriptor.trigger_time.to_unix_seconds();
+        let segments = waveform.segments();
+
+        assert_eq!(segments.len(), 3);
+        for (index, segment) in segments.iter().enumerate() {
+            let offset = -0.25 * (index + 1) as f64;
+            assert_eq!(segment.index, index);
+            assert_eq!(segment.trigger_time, index as f64 * 1e-3);
+            assert_eq!(segment.timestamp, start + index as f64 * 1e-3);
+            assert_eq!(
+                segment.voltages,
+                [2.0 * index as f64 + 1.0, 2.0 * index as f64 + 2.0]
+            );
+            assert_eq!(segment.times, [offset, offset + 0.5]);
+        }
+    }
+
+    #[test]
+    fn single_sweep_is_one_segment() {
+        let bytes = Waveform {
+            descriptor: WaveDesc {
+                horizontal_interval: 0.5,
+                horizontal_offset: -1.0,
+                ..WaveDesc::default()
+            },
+            user_text: String::new(),
+            trigger_times: Vec::new(),
+            ris_times: Vec::new(),
+            times: Vec::new(),
+            voltages: vec![1.0, 2.0, 3.0],
+            voltages_2: None,
+        }
+        .to_bytes();
+        let segments = Waveform::parse(&bytes).unwrap().segments();
+
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].trigger_time, 0.0);
+        assert_eq!(segments[0].times, [-1.0, -0.5, 0.0]);
+        assert_eq!(segments[0].voltages, [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn raw_times_follow_segment_triggers() {
+        let raw = RawWaveform::parse(&sequence()).unwrap();
+        assert_eq!(raw.time(0), Some(-0.25));
+        assert_eq!(raw.time(1), Some(0.25));
+        assert_eq!(raw.time(2), Some(-0.5));
+        assert_eq!(raw.time(5), Some(-0.25));
+        assert_eq!(raw.time(6), None);
+
+        // without TRIGTIME, samples are timed from the descriptor
+        let mut raw = raw;
+        raw.trigger_times.clear();
+        assert_eq!(raw.time(3), Some(raw.descriptor.time_of(3)));
+    }
+}
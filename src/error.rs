@@ -0,0 +1,87 @@
+//! ## Error Module
+//!
+//! The error type returned by every fallible method of the crate.
+//!
+
+use thiserror::Error;
+
+/// ## Result
+///
+/// Shorthand for results carrying a [`MauiError`].
+///
+pub type Result<T> = std::result::Result<T, MauiError>;
+
+/// ## MAUI Error
+///
+/// Every way in which talking to the oscilloscope can fail. Match on the
+/// variant to find the cause of a failure instead of inspecting the message.
+///
+#[derive(Debug, Error)]
+pub enum MauiError {
+    /// The connection to the device failed or was lost.
+    #[error("transport error: {0}")]
+    Transport(String),
+
+    /// The device did not answer in time, or a wait reached its deadline.
+    #[error("timed out: {0}")]
+    Timeout(String),
+
+    /// A wait was stopped through its cancellation token.
+    #[error("wait cancelled")]
+    Cancelled,
+
+    /// The device answered with something that could not be understood.
+    #[error("could not parse response '{response}': {reason}")]
+    Parse { response: String, reason: String },
+
+    /// Binary waveform data, such as a WAVEDESC block or a capture archive, is
+    /// malformed.
+    #[error("invalid waveform data: {0}")]
+    InvalidWaveform(String),
+
+    /// An argument was rejected before anything was sent to the device.
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+
+    /// The device reported a command error (CMR register) for a command.
+    #[error("command error {code} on '{command}': {description}")]
+    Command {
+        command: String,
+        code: u8,
+        description: String,
+    },
+
+    /// The device reported an execution error (EXR register) for a command.
+    #[error("execution error {code} on '{command}': {description}")]
+    Execution {
+        command: String,
+        code: u8,
+        description: String,
+    },
+
+    /// The device adapted a value of a command to the closest legal value (VAB
+    /// bit of the status byte).
+    #[error("value adapted by the device on '{command}'")]
+    ValueAdapted { command: String },
+
+    /// The operation is not available on this model or over this transport.
+    #[error("not supported: {0}")]
+    Unsupported(String),
+
+    /// A file on the controller could not be read or written.
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl MauiError {
+    /// ## Parse
+    ///
+    /// Build a parse error from the offending response and a reason.
+    ///
+    pub fn parse(response: impl Into<String>, reason: impl ToString) -> MauiError {
+        MauiError::Parse {
+            response: response.into(),
+            reason: reason.to_string(),
+        }
+    }
+}
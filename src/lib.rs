@@ -3,25 +3,68 @@
 //! A pure Rust driver for Teledyne-Lecroy MAUI oscilloscopes.
 //!
 
-use anyhow::Result;
 use instrument_ctl::Instrument;
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+mod error;
+pub use error::{MauiError, Result};
+
+mod transport;
+pub use transport::Transport;
+
+mod strict;
+use strict::StrictTransport;
+
+mod wait;
+pub use wait::{CancellationToken, WaitOptions};
+
+mod path;
+pub use path::{DevicePath, IntoDevicePath};
+
+mod stream;
+pub use stream::{Acquisitions, Frame, StreamConfig};
+#[cfg(feature = "async")]
+pub use stream::AcquisitionStream;
 
-mod subsystems {
+pub mod transports {
+    pub mod record;
+    pub mod simulated;
+    pub mod tcp;
+    pub mod vicp;
+    pub mod vxi11;
+}
+
+pub mod subsystems {
     pub mod acquisition;
     pub mod communication;
     pub mod setup;
+    pub mod status;
     pub mod storage;
     pub mod waveform;
     pub mod vbs;
 }
 
+pub mod data {
+    pub mod archive;
+    pub mod export;
+    pub mod raw;
+    pub mod trc;
+    pub mod wavedesc;
+    pub mod waveform;
+}
+
 mod utils;
 
+use data::archive::Capture;
+use transports::{tcp::TcpTransport, vicp::VicpTransport, vxi11::Vxi11Transport};
+
 use subsystems::{
     acquisition::AcquisitionSubsystem, communication::CommunicationSubsystem,
-    setup::SetupSubsystem, storage::StorageSubsystem, vbs::VbsSubsystem,
-    waveform::WaveformSubsystem,
+    setup::SetupSubsystem, status::StatusSubsystem, storage::StorageSubsystem,
+    vbs::VbsSubsystem, waveform::WaveformSubsystem,
 };
 
 /// ## MAUI Oscilloscope
@@ -29,7 +72,8 @@ use subsystems::{
 /// Object through which communication with the oscilloscope is done.
 ///
 pub struct MauiOscilloscope {
-    client: Arc<Instrument>,
+    client: Arc<StrictTransport>,
+    pub status: StatusSubsystem,
     pub communication: CommunicationSubsystem,
     pub vbs: VbsSubsystem,
     pub acquisition: AcquisitionSubsystem,
@@ -43,13 +87,38 @@ impl MauiOscilloscope {
     ///
     /// Connect and initialize the device.
     ///
-    pub fn connect(visa_address: &str) -> Result<MauiOscilloscope> {
+    /// The transport is selected from the address:
+    /// - `vicp://<host>[:<port>]` connects with VICP, port 1861 by default
+    /// - `vxi11://<host>[/<device>]` connects with VXI-11, device `inst0` by default
+    /// - `tcp://<host>[:<port>]` connects with a raw socket, port 5025 by default
+    /// - anything else is treated as a VISA address
+    ///
+    pub fn connect(address: &str) -> Result<MauiOscilloscope> {
         // Connect
-        let client = Arc::new(Instrument::connect(visa_address)?);
+        let client: Arc<dyn Transport> = if let Some(host) = address.strip_prefix("vicp://") {
+            Arc::new(VicpTransport::connect(host.trim_end_matches('/'))?)
+        } else if let Some(host) = address.strip_prefix("vxi11://") {
+            match host.trim_end_matches('/').split_once('/') {
+                Some((host, device)) => Arc::new(Vxi11Transport::connect_device(host, device)?),
+                None => Arc::new(Vxi11Transport::connect(host.trim_end_matches('/'))?),
+            }
+        } else if let Some(host) = address.strip_prefix("tcp://") {
+            Arc::new(TcpTransport::connect(host.trim_end_matches('/'))?)
+        } else {
+            let instrument = Instrument::connect(address)
+                .map_err(|e| MauiError::Transport(format!("{:#}", e)))?;
+            Arc::new(instrument)
+        };
 
-        // Setup the status
-        // let status = Arc::new(Status::init(&client));
+        Self::with_transport(client)
+    }
 
+    /// ## With Transport
+    ///
+    /// Initialize the device over an already established transport. This is how
+    /// simulated, recorded or custom connections are plugged into the driver.
+    ///
+    pub fn with_transport(client: Arc<dyn Transport>) -> Result<MauiOscilloscope> {
         // Set the COMM HEADERS off
         client.command("CHDR OFF")?;
 
@@ -63,7 +132,12 @@ impl MauiOscilloscope {
         let cmd = format!("INE {}", mask);
         client.command(&cmd)?;
 
+        // Every subsystem goes through the strict mode wrapper
+        let strict = Arc::new(StrictTransport::new(client));
+        let client: Arc<dyn Transport> = strict.clone();
+
         // Setup the subsystems
+        let status = StatusSubsystem::init(&client);
         let communication = CommunicationSubsystem::init(&client);
         let vbs = VbsSubsystem::init(&client);
         let acquisition = AcquisitionSubsystem::init(&client);
@@ -72,7 +146,8 @@ impl MauiOscilloscope {
         let waveform = WaveformSubsystem::init(&client);
 
         Ok(MauiOscilloscope {
-            client,
+            client: strict,
+            status,
             communication,
             vbs,
             acquisition,
@@ -90,6 +165,83 @@ impl MauiOscilloscope {
         self.client.set_timeout(duration);
     }
 
+    /// ## Set Strict
+    ///
+    /// Enable or disable strict mode. While enabled, every command sent by any
+    /// subsystem is followed by a check of the status registers, and a command
+    /// error (CMR), execution error (EXR) or adapted value (VAB) is returned
+    /// as an error carrying the offending command.
+    ///
+    /// Enabling strict mode clears the status registers with `*CLS`, so that
+    /// errors left over from earlier commands are not reported. Strict mode
+    /// costs three extra queries per command, and clears the status registers
+    /// again when a value was adapted.
+    ///
+    pub fn set_strict(&self, strict: bool) -> Result<()> {
+        if strict && !self.client.is_enabled() {
+            self.client.command("*CLS")?;
+        }
+        self.client.set_enabled(strict);
+        Ok(())
+    }
+
+    /// ## Is Strict
+    ///
+    /// Return whether strict mode is enabled.
+    ///
+    pub fn is_strict(&self) -> bool {
+        self.client.is_enabled()
+    }
+
+    /// ## Acquisitions
+    ///
+    /// Return an iterator that repeatedly arms the scope, waits for the
+    /// trigger and downloads `traces`, yielding one timestamped [`Frame`] per
+    /// acquisition until the stop condition of `config` is met.
+    ///
+    /// The scope is only armed when the next frame is requested, and the
+    /// iterator ends after yielding the first error.
+    ///
+    pub fn acquisitions(&self, traces: &[&str], config: StreamConfig) -> Acquisitions {
+        let client: Arc<dyn Transport> = self.client.clone();
+        Acquisitions::new(&client, traces, config)
+    }
+
+    /// ## Acquisition Stream
+    ///
+    /// Asynchronous variant of [`MauiOscilloscope::acquisitions`]. The frames
+    /// are acquired on a background thread, at most `buffer` frames ahead of
+    /// the consumer (see [`StreamConfig::with_buffer`]).
+    ///
+    #[cfg(feature = "async")]
+    pub fn acquisition_stream(&self, traces: &[&str], config: StreamConfig) -> AcquisitionStream {
+        AcquisitionStream::spawn(self.acquisitions(traces, config))
+    }
+
+    /// ## Capture
+    ///
+    /// Download the last acquisition of `traces` together with the identity
+    /// and panel setup of the scope, ready to be stored with
+    /// [`data::archive::write`].
+    ///
+    pub fn capture(&self, traces: &[&str]) -> Result<Capture> {
+        let identity = self.client.query("*IDN?")?;
+        let panel_setup = self.setup.get_panel_setup()?;
+        let timestamp = SystemTime::now();
+
+        let mut waveforms = Vec::with_capacity(traces.len());
+        for trace in traces {
+            waveforms.push((trace.to_string(), self.waveform.read_waveform(trace)?));
+        }
+
+        Ok(Capture {
+            identity,
+            panel_setup,
+            timestamp,
+            waveforms,
+        })
+    }
+
     /// ## Command
     ///
     /// Send a command to the oscilloscope.
@@ -99,6 +251,16 @@ impl MauiOscilloscope {
         Ok(())
     }
 
+    /// ## Command Raw
+    ///
+    /// Send a command holding binary data, such as a definite length block, to
+    /// the oscilloscope.
+    ///
+    pub fn command_raw(&self, cmd: &[u8]) -> Result<()> {
+        self.client.command_raw(cmd)?;
+        Ok(())
+    }
+
     /// ## Query
     ///
     /// Send a command to the oscilloscope and return the response as a string.
@@ -0,0 +1,389 @@
+//! ## Path Module
+//!
+//! Paths on the file system of the oscilloscope, validated before they are
+//! sent to the device.
+//!
+
+use crate::{MauiError, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// The characters Windows does not allow in a file name, along with `'`,
+/// which would end the quoted path of a remote command.
+const ILLEGAL_CHARACTERS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*', '\''];
+
+/// The file names Windows reserves for devices, with or without an extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The longest path Windows accepts.
+const MAX_PATH: usize = 259;
+
+/// ## Device Path
+///
+/// An absolute path on the file system of the oscilloscope, such as
+/// `D:\Waveforms\Run1`, with an optional drive letter. A path without a drive
+/// refers to the current drive of the scope, usually `D:`.
+///
+/// Paths are parsed from strings using either `\` or `/` as separator, and
+/// every component is checked against the rules of Windows, so that a
+/// malformed path is rejected before anything is sent to the device.
+///
+/// ```
+/// # use rs_maui::DevicePath;
+/// let directory: DevicePath = "D:/Waveforms".parse()?;
+/// let file = directory.join("C1Trace00000.trc")?;
+/// assert_eq!(file.to_string(), "D:\\Waveforms\\C1Trace00000.trc");
+/// assert!(DevicePath::parse("D:\\Waveforms\\CON.trc").is_err());
+/// # Ok::<(), rs_maui::MauiError>(())
+/// ```
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DevicePath {
+    drive: Option<char>,
+    components: Vec<String>,
+}
+
+impl DevicePath {
+    /// ## Parse
+    ///
+    /// Parse and validate a path. A leading separator is optional, and empty
+    /// components, such as those of a trailing separator, are ignored.
+    ///
+    pub fn parse(path: &str) -> Result<DevicePath> {
+        let invalid = |reason: String| {
+            MauiError::InvalidArgument(format!("invalid device path '{}': {}", path, reason))
+        };
+        if path.trim().is_empty() {
+            return Err(invalid("path is empty".into()));
+        }
+
+        // split off the drive letter, any other ':' is an illegal character
+        let (drive, rest) = match path.as_bytes() {
+            [letter, b':', ..] if letter.is_ascii_alphabetic() => {
+                (Some(letter.to_ascii_uppercase() as char), &path[2..])
+            }
+            _ => (None, path),
+        };
+
+        let mut device_path = DevicePath::root(drive);
+        for component in rest.split(['\\', '/']).filter(|c| !c.is_empty()) {
+            check_component(component).map_err(invalid)?;
+            device_path.components.push(component.to_string());
+        }
+        device_path.check_length().map_err(invalid)?;
+
+        Ok(device_path)
+    }
+
+    /// ## Root
+    ///
+    /// Return the root directory of a drive, or of the current drive.
+    ///
+    pub fn root(drive: Option<char>) -> DevicePath {
+        DevicePath {
+            drive: drive.map(|d| d.to_ascii_uppercase()),
+            components: Vec::new(),
+        }
+    }
+
+    /// ## Join
+    ///
+    /// Return this path extended with a relative path, whose components are
+    /// validated like those of [`DevicePath::parse`].
+    ///
+    pub fn join(&self, relative: &str) -> Result<DevicePath> {
+        let mut path = self.clone();
+        for component in relative.split(['\\', '/']).filter(|c| !c.is_empty()) {
+            check_component(component).map_err(|reason| {
+                MauiError::InvalidArgument(format!(
+                    "invalid device path component '{}': {}",
+                    relative, reason
+                ))
+            })?;
+            path.components.push(component.to_string());
+        }
+        path.check_length().map_err(|reason| {
+            MauiError::InvalidArgument(format!("invalid device path '{}': {}", path, reason))
+        })?;
+        Ok(path)
+    }
+
+    /// ## Drive
+    ///
+    /// Return the drive letter, if the path has one.
+    ///
+    pub fn drive(&self) -> Option<char> {
+        self.drive
+    }
+
+    /// ## Components
+    ///
+    /// Iterate over the names of the directories and file of the path.
+    ///
+    pub fn components(&self) -> impl Iterator<Item = &str> + '_ {
+        self.components.iter().map(String::as_str)
+    }
+
+    /// ## Is Root
+    ///
+    /// Return whether the path is the root directory of a drive.
+    ///
+    pub fn is_root(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    /// ## File Name
+    ///
+    /// Return the last component of the path, or `None` for a root directory.
+    ///
+    pub fn file_name(&self) -> Option<&str> {
+        self.components.last().map(String::as_str)
+    }
+
+    /// ## Parent
+    ///
+    /// Return the directory holding the path, or `None` for a root directory.
+    ///
+    pub fn parent(&self) -> Option<DevicePath> {
+        let (_, parent) = self.components.split_last()?;
+        Some(DevicePath {
+            drive: self.drive,
+            components: parent.to_vec(),
+        })
+    }
+
+    /// ## To Directory String
+    ///
+    /// Return the path with a trailing `\`, as the automation objects of the
+    /// scope expect for directories.
+    ///
+    pub fn to_directory_string(&self) -> String {
+        match self.is_root() {
+            true => self.to_string(),
+            false => format!("{}\\", self),
+        }
+    }
+
+    /// ## To VBS String
+    ///
+    /// Return the path as a quoted VBScript string literal. Directories keep
+    /// a trailing `\` when `directory` is set.
+    ///
+    pub fn to_vbs_string(&self, directory: bool) -> String {
+        let path = match directory {
+            true => self.to_directory_string(),
+            false => self.to_string(),
+        };
+        format!("\"{}\"", path.replace('"', "\"\""))
+    }
+
+    /// Check that the path is not longer than Windows allows.
+    fn check_length(&self) -> std::result::Result<(), String> {
+        let length = self.to_string().len();
+        match length > MAX_PATH {
+            true => Err(format!(
+                "path is {} characters long, the limit is {}",
+                length, MAX_PATH
+            )),
+            false => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for DevicePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(drive) = self.drive {
+            write!(f, "{}:", drive)?;
+        }
+        if self.components.is_empty() {
+            return write!(f, "\\");
+        }
+        for component in &self.components {
+            write!(f, "\\{}", component)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for DevicePath {
+    type Err = MauiError;
+
+    fn from_str(path: &str) -> Result<DevicePath> {
+        DevicePath::parse(path)
+    }
+}
+
+/// ## Into Device Path
+///
+/// Conversion into a [`DevicePath`], implemented for the path itself and for
+/// strings, which are parsed. Every method taking a path on the device
+/// accepts this trait, so that malformed paths are rejected before anything
+/// is sent.
+///
+pub trait IntoDevicePath {
+    /// Convert into a validated [`DevicePath`].
+    fn into_device_path(self) -> Result<DevicePath>;
+}
+
+impl IntoDevicePath for DevicePath {
+    fn into_device_path(self) -> Result<DevicePath> {
+        Ok(self)
+    }
+}
+
+impl IntoDevicePath for &DevicePath {
+    fn into_device_path(self) -> Result<DevicePath> {
+        Ok(self.clone())
+    }
+}
+
+impl IntoDevicePath for &str {
+    fn into_device_path(self) -> Result<DevicePath> {
+        DevicePath::parse(self)
+    }
+}
+
+impl IntoDevicePath for String {
+    fn into_device_path(self) -> Result<DevicePath> {
+        DevicePath::parse(&self)
+    }
+}
+
+impl IntoDevicePath for &String {
+    fn into_device_path(self) -> Result<DevicePath> {
+        DevicePath::parse(self)
+    }
+}
+
+/// Check that a file or directory name is allowed by Windows and can be
+/// quoted in a remote command, returning the reason it is not.
+pub(crate) fn check_component(name: &str) -> std::result::Result<(), String> {
+    if name == "." || name == ".." {
+        return Err(format!("relative component '{}' is not allowed", name));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| ILLEGAL_CHARACTERS.contains(c) || c.is_control())
+    {
+        return Err(format!("'{}' contains the illegal character {:?}", name, c));
+    }
+    if name.ends_with(['.', ' ']) {
+        return Err(format!("'{}' ends with a dot or a space", name));
+    }
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        return Err(format!("'{}' is a name reserved by Windows", name));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_drive_and_components() {
+        let path = DevicePath::parse("d:/Waveforms\\Run1/").unwrap();
+        assert_eq!(path.drive(), Some('D'));
+        assert_eq!(path.components().collect::<Vec<_>>(), ["Waveforms", "Run1"]);
+        assert_eq!(path.to_string(), "D:\\Waveforms\\Run1");
+
+        let path = DevicePath::parse("\\Waveforms").unwrap();
+        assert_eq!(path.drive(), None);
+        assert_eq!(path.to_string(), "\\Waveforms");
+
+        let root = DevicePath::parse("C:").unwrap();
+        assert!(root.is_root());
+        assert_eq!(root.to_string(), "C:\\");
+        assert_eq!(DevicePath::parse("/").unwrap(), DevicePath::root(None));
+
+        // a drive is a single letter at the start, any other ':' is illegal
+        assert!(DevicePath::parse("").is_err());
+        assert!(DevicePath::parse("1:/a").is_err());
+        assert!(DevicePath::parse("DD:/a").is_err());
+        assert!(DevicePath::parse("a/b:c").is_err());
+    }
+
+    #[test]
+    fn reserved_names_are_rejected_with_extensions() {
+        for name in ["CON", "con.trc", "LPT1.txt", "Nul .lss", "com9.tar.gz"] {
+            assert!(check_component(name).is_err(), "{}", name);
+            assert!(
+                DevicePath::parse(&format!("D:\\{}", name)).is_err(),
+                "{}",
+                name
+            );
+        }
+        for name in ["CONSOLE", "COM10.trc", "my.CON", "LPT"] {
+            assert!(check_component(name).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn illegal_components_are_rejected() {
+        for name in [
+            ".", "..", "a.", "a ", "it's", "a*b", "a?b", "a|b", "a<b>", "a\"b", "a\tb",
+        ] {
+            assert!(check_component(name).is_err(), "{:?}", name);
+        }
+        for name in [".trc", "a.b.c", " a", "C1 Trace"] {
+            assert!(check_component(name).is_ok(), "{:?}", name);
+        }
+        assert!(DevicePath::parse("D:\\Waveforms\\..\\Windows").is_err());
+        assert!(DevicePath::parse("D:\\Run.\\a.trc").is_err());
+    }
+
+    #[test]
+    fn paths_longer_than_max_path_are_rejected() {
+        // `D:\` and the name
+        let longest = format!("D:\\{}", "a".repeat(MAX_PATH - 3));
+        assert_eq!(
+            DevicePath::parse(&longest).unwrap().to_string().len(),
+            MAX_PATH
+        );
+        assert!(DevicePath::parse(&format!("{}a", longest)).is_err());
+
+        let directory = DevicePath::parse(&format!("D:\\{}", "a".repeat(MAX_PATH - 5))).unwrap();
+        assert!(directory.join("b").is_ok());
+        assert!(directory.join("bc").is_err());
+    }
+
+    #[test]
+    fn join_validates_components() {
+        let directory = DevicePath::parse("D:\\Waveforms").unwrap();
+        assert_eq!(
+            directory.join("Run1/C1Trace00000.trc").unwrap().to_string(),
+            "D:\\Waveforms\\Run1\\C1Trace00000.trc"
+        );
+        assert_eq!(directory.join("").unwrap(), directory);
+        assert!(directory.join("../Windows").is_err());
+        assert!(directory.join("aux.trc").is_err());
+        assert!(directory.join("C:\\a").is_err());
+    }
+
+    #[test]
+    fn parent_and_file_name() {
+        let file = DevicePath::parse("D:\\Waveforms\\C1Trace00000.trc").unwrap();
+        assert_eq!(file.file_name(), Some("C1Trace00000.trc"));
+        let parent = file.parent().unwrap();
+        assert_eq!(parent.to_string(), "D:\\Waveforms");
+        assert_eq!(parent.parent().unwrap(), DevicePath::root(Some('d')));
+        assert_eq!(DevicePath::root(Some('D')).parent(), None);
+        assert_eq!(DevicePath::root(Some('D')).file_name(), None);
+    }
+
+    #[test]
+    fn vbs_string_is_quoted() {
+        let directory = DevicePath::parse("D:\\Waveforms\\Run 1").unwrap();
+        assert_eq!(directory.to_vbs_string(false), "\"D:\\Waveforms\\Run 1\"");
+        assert_eq!(directory.to_vbs_string(true), "\"D:\\Waveforms\\Run 1\\\"");
+        assert_eq!(directory.to_directory_string(), "D:\\Waveforms\\Run 1\\");
+
+        let root = DevicePath::root(Some('D'));
+        assert_eq!(root.to_vbs_string(true), "\"D:\\\"");
+        assert_eq!(root.to_directory_string(), "D:\\");
+    }
+}
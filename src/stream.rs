@@ -0,0 +1,571 @@
+//! ## Stream Module
+//!
+//! Repeated acquisitions yielded as timestamped frames, either from a blocking
+//! iterator or, with the `async` feature, from an asynchronous stream.
+//!
+
+use crate::data::waveform::Waveform;
+use crate::subsystems::{acquisition::AcquisitionSubsystem, waveform::WaveformSubsystem};
+use crate::{CancellationToken, MauiError, Result, Transport, WaitOptions};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A condition evaluated on every frame, ending the stream when true.
+type StopCondition = Arc<dyn Fn(&Frame) -> bool + Send + Sync>;
+
+/// ## Frame
+///
+/// The traces downloaded after one trigger.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// The position of the frame in the stream, starting at 0.
+    pub index: usize,
+    /// Time at which the trigger was noticed, on the clock of this computer.
+    pub timestamp: SystemTime,
+    /// The downloaded traces, in the order they were requested.
+    pub waveforms: Vec<(String, Waveform)>,
+}
+
+impl Frame {
+    /// ## Waveform
+    ///
+    /// Return the waveform downloaded for `trace`, if it was requested.
+    ///
+    pub fn waveform(&self, trace: &str) -> Option<&Waveform> {
+        self.waveforms
+            .iter()
+            .find(|(name, _)| name == trace)
+            .map(|(_, waveform)| waveform)
+    }
+}
+
+/// ## Stream Config
+///
+/// When a stream of acquisitions stops and how long each frame may wait for
+/// its trigger. By default the stream never stops and waits indefinitely for
+/// each trigger.
+///
+/// The stream ends without an error once `max_frames` frames have been
+/// yielded, once the `duration` has passed, when a cancellation token is
+/// cancelled, or after the frame for which the stop condition returns true.
+/// A trigger that does not come within the trigger timeout ends the stream
+/// with a timeout error.
+///
+/// ```ignore
+/// let config = StreamConfig::new()
+///     .with_trigger_timeout(Duration::from_secs(5))
+///     .with_max_frames(100);
+/// for frame in scope.acquisitions(&["C1", "C2"], config) {
+///     let frame = frame?;
+/// }
+/// ```
+///
+#[derive(Clone, Default)]
+pub struct StreamConfig {
+    trigger_timeout: Option<Duration>,
+    max_frames: Option<usize>,
+    duration: Option<Duration>,
+    tokens: Vec<CancellationToken>,
+    stop_condition: Option<StopCondition>,
+    buffer: Option<usize>,
+}
+
+impl fmt::Debug for StreamConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamConfig")
+            .field("trigger_timeout", &self.trigger_timeout)
+            .field("max_frames", &self.max_frames)
+            .field("duration", &self.duration)
+            .field("tokens", &self.tokens)
+            .field("stop_condition", &self.stop_condition.is_some())
+            .field("buffer", &self.buffer)
+            .finish()
+    }
+}
+
+impl StreamConfig {
+    /// ## New
+    ///
+    /// A stream that never stops and waits indefinitely for each trigger.
+    ///
+    pub fn new() -> StreamConfig {
+        StreamConfig::default()
+    }
+
+    /// ## With Trigger Timeout
+    ///
+    /// Fail with a timeout error when a trigger does not come within `timeout`
+    /// of arming.
+    ///
+    pub fn with_trigger_timeout(mut self, timeout: Duration) -> StreamConfig {
+        self.trigger_timeout = Some(timeout);
+        self
+    }
+
+    /// ## With Max Frames
+    ///
+    /// Stop after `frames` frames.
+    ///
+    pub fn with_max_frames(mut self, frames: usize) -> StreamConfig {
+        self.max_frames = Some(frames);
+        self
+    }
+
+    /// ## With Duration
+    ///
+    /// Stop once `duration` has passed since the stream was created. A wait
+    /// for a trigger in progress at that point is abandoned.
+    ///
+    pub fn with_duration(mut self, duration: Duration) -> StreamConfig {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// ## With Cancellation
+    ///
+    /// Stop when `token` is cancelled, including in the middle of a wait for
+    /// a trigger.
+    ///
+    pub fn with_cancellation(mut self, token: CancellationToken) -> StreamConfig {
+        self.tokens.push(token);
+        self
+    }
+
+    /// ## With Stop Condition
+    ///
+    /// Stop after the first frame for which `condition` returns true. That
+    /// frame is still yielded.
+    ///
+    pub fn with_stop_condition<F>(mut self, condition: F) -> StreamConfig
+    where
+        F: Fn(&Frame) -> bool + Send + Sync + 'static,
+    {
+        self.stop_condition = Some(Arc::new(condition));
+        self
+    }
+
+    /// ## With Buffer
+    ///
+    /// Number of frames an asynchronous stream acquires ahead of its consumer
+    /// before it stops arming the scope. At least one frame is buffered, which
+    /// is also the default. Blocking iterators only acquire when asked for the
+    /// next frame, so they ignore this setting.
+    ///
+    pub fn with_buffer(mut self, frames: usize) -> StreamConfig {
+        self.buffer = Some(frames);
+        self
+    }
+}
+
+/// ## Acquisitions
+///
+/// Iterator over repeated acquisitions, created by
+/// [`MauiOscilloscope::acquisitions`](crate::MauiOscilloscope::acquisitions).
+///
+/// Every call to `next` arms the scope, waits for the trigger and downloads
+/// the requested traces, so the scope is never armed faster than the frames
+/// are consumed. After an error has been yielded, the iterator is finished.
+///
+pub struct Acquisitions {
+    acquisition: AcquisitionSubsystem,
+    waveform: WaveformSubsystem,
+    traces: Vec<String>,
+    config: StreamConfig,
+    deadline: Option<Instant>,
+    index: usize,
+    finished: bool,
+}
+
+impl Acquisitions {
+    /// Create the iterator over its own handles on the subsystems, so that it
+    /// does not borrow the oscilloscope and can be moved to another thread.
+    pub(crate) fn new(
+        client: &Arc<dyn Transport>,
+        traces: &[&str],
+        config: StreamConfig,
+    ) -> Acquisitions {
+        Acquisitions {
+            acquisition: AcquisitionSubsystem::init(client),
+            waveform: WaveformSubsystem::init(client),
+            traces: traces.iter().map(|trace| trace.to_string()).collect(),
+            deadline: config.duration.map(|duration| Instant::now() + duration),
+            config,
+            index: 0,
+            finished: false,
+        }
+    }
+
+    /// The wait options of the next trigger wait: the earliest of the trigger
+    /// timeout and the end of the stream, and the tokens of the config.
+    fn wait_options(&self) -> WaitOptions {
+        let trigger_deadline = self.config.trigger_timeout.map(|t| Instant::now() + t);
+        let deadline = match (trigger_deadline, self.deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let options = match deadline {
+            Some(deadline) => WaitOptions::deadline(deadline),
+            None => WaitOptions::new(),
+        };
+        self.config.tokens.iter().fold(options, |options, token| {
+            options.with_cancellation(token.clone())
+        })
+    }
+
+    /// Return whether the stream should end before arming again.
+    fn should_stop(&self) -> bool {
+        self.config.max_frames.is_some_and(|max| self.index >= max)
+            || self.deadline.is_some_and(|d| Instant::now() >= d)
+            || self.config.tokens.iter().any(|t| t.is_cancelled())
+    }
+
+    /// Acquire and download the next frame, or return `None` if the stream
+    /// has ended.
+    fn next_frame(&mut self) -> Result<Option<Frame>> {
+        if self.should_stop() {
+            return Ok(None);
+        }
+        // fail on a bad trace before waiting for a trigger
+        for trace in &self.traces {
+            WaveformSubsystem::verify_waveform_trace(trace)?;
+        }
+
+        match self
+            .acquisition
+            .arm_and_wait_for_acquisition(&self.wait_options())
+        {
+            Ok(()) => {}
+            Err(MauiError::Cancelled) => return Ok(None),
+            Err(MauiError::Timeout(_)) if self.should_stop() => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let timestamp = SystemTime::now();
+
+        let waveforms = self
+            .traces
+            .iter()
+            .map(|trace| Ok((trace.clone(), self.waveform.read_waveform(trace)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let frame = Frame {
+            index: self.index,
+            timestamp,
+            waveforms,
+        };
+        self.index += 1;
+        Ok(Some(frame))
+    }
+}
+
+impl Iterator for Acquisitions {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Result<Frame>> {
+        if self.finished {
+            return None;
+        }
+
+        match self.next_frame() {
+            Ok(Some(frame)) => {
+                if let Some(condition) = &self.config.stop_condition {
+                    self.finished = condition(&frame);
+                }
+                Some(Ok(frame))
+            }
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+pub use self::background::AcquisitionStream;
+
+#[cfg(feature = "async")]
+mod background {
+    use super::{Acquisitions, Frame};
+    use crate::{CancellationToken, Result};
+    use futures_core::Stream;
+    use std::collections::VecDeque;
+    use std::pin::Pin;
+    use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+    use std::task::{Context, Poll, Waker};
+    use std::thread;
+
+    /// Number of frames buffered by an asynchronous stream by default.
+    const DEFAULT_BUFFER: usize = 1;
+
+    /// ## Acquisition Stream
+    ///
+    /// Asynchronous stream of acquisitions, created by
+    /// [`MauiOscilloscope::acquisition_stream`](crate::MauiOscilloscope::acquisition_stream).
+    ///
+    /// The acquisitions run on a background thread, which stops arming the
+    /// scope while the buffer of the stream is full. Dropping the stream
+    /// cancels a wait for a trigger in progress and stops the thread.
+    ///
+    pub struct AcquisitionStream {
+        channel: Arc<Channel>,
+        token: CancellationToken,
+    }
+
+    /// Bounded queue of frames between the background thread and the stream.
+    struct Channel {
+        state: Mutex<State>,
+        space: Condvar,
+    }
+
+    struct State {
+        frames: VecDeque<Result<Frame>>,
+        capacity: usize,
+        waker: Option<Waker>,
+        producing: bool,
+        receiving: bool,
+    }
+
+    impl Channel {
+        fn lock(&self) -> MutexGuard<'_, State> {
+            self.state.lock().unwrap_or_else(|e| e.into_inner())
+        }
+    }
+
+    impl AcquisitionStream {
+        /// Start the background thread pulling frames from `acquisitions`.
+        pub(crate) fn spawn(mut acquisitions: Acquisitions) -> AcquisitionStream {
+            let token = CancellationToken::new();
+            acquisitions.config.tokens.push(token.clone());
+
+            let channel = Arc::new(Channel {
+                state: Mutex::new(State {
+                    frames: VecDeque::new(),
+                    capacity: acquisitions.config.buffer.unwrap_or(DEFAULT_BUFFER).max(1),
+                    waker: None,
+                    producing: true,
+                    receiving: true,
+                }),
+                space: Condvar::new(),
+            });
+
+            let producer = channel.clone();
+            thread::spawn(move || {
+                loop {
+                    // back-pressure: do not arm while the buffer is full
+                    {
+                        let mut state = producer.lock();
+                        while state.receiving && state.frames.len() >= state.capacity {
+                            state = producer
+                                .space
+                                .wait(state)
+                                .unwrap_or_else(|e| e.into_inner());
+                        }
+                        if !state.receiving {
+                            break;
+                        }
+                    }
+
+                    let frame = acquisitions.next();
+
+                    let mut state = producer.lock();
+                    match frame {
+                        Some(frame) => state.frames.push_back(frame),
+                        None => break,
+                    }
+                    if let Some(waker) = state.waker.take() {
+                        waker.wake();
+                    }
+                }
+
+                let mut state = producer.lock();
+                state.producing = false;
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            });
+
+            AcquisitionStream { channel, token }
+        }
+    }
+
+    impl Stream for AcquisitionStream {
+        type Item = Result<Frame>;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame>>> {
+            let mut state = self.channel.lock();
+            if let Some(frame) = state.frames.pop_front() {
+                self.channel.space.notify_one();
+                return Poll::Ready(Some(frame));
+            }
+            if !state.producing {
+                return Poll::Ready(None);
+            }
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    impl Drop for AcquisitionStream {
+        fn drop(&mut self) {
+            self.channel.lock().receiving = false;
+            self.channel.space.notify_one();
+            self.token.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transports::simulated::SimulatedScope;
+    use crate::MauiOscilloscope;
+    use std::thread;
+
+    fn scope(signal_present: bool) -> (Arc<SimulatedScope>, MauiOscilloscope) {
+        let sim = Arc::new(SimulatedScope::new());
+        sim.set_signal_present(signal_present);
+        if !signal_present {
+            // without a signal, only the auto trigger mode acquires
+            sim.command("TRMD NORM").unwrap();
+        }
+        let scope = MauiOscilloscope::with_transport(sim.clone()).unwrap();
+        (sim, scope)
+    }
+
+    #[test]
+    fn max_frames_ends_the_iterator() {
+        let (sim, scope) = scope(true);
+        let config = StreamConfig::new().with_max_frames(3);
+        let frames = scope
+            .acquisitions(&["C1", "C2"], config)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(frames.len(), 3);
+        assert_eq!(sim.acquisition_count(), 3);
+        for (index, frame) in frames.iter().enumerate() {
+            assert_eq!(frame.index, index);
+            assert!(frame.waveform("C1").is_some());
+            assert!(frame.waveform("C2").is_some());
+            assert!(frame.waveform("C3").is_none());
+        }
+    }
+
+    #[test]
+    fn stop_condition_yields_its_frame() {
+        let (_, scope) = scope(true);
+        let config = StreamConfig::new().with_stop_condition(|frame| frame.index == 1);
+        let frames: Vec<_> = scope.acquisitions(&["C1"], config).collect();
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].as_ref().unwrap().index, 1);
+    }
+
+    #[test]
+    fn cancellation_ends_a_wait_for_trigger() {
+        let (_, scope) = scope(false);
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            canceller.cancel();
+        });
+
+        let start = Instant::now();
+        let mut frames = scope.acquisitions(&["C1"], StreamConfig::new().with_cancellation(token));
+        assert!(frames.next().is_none());
+        assert!(frames.next().is_none());
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn missing_trigger_is_a_timeout() {
+        let (_, scope) = scope(false);
+        let config = StreamConfig::new().with_trigger_timeout(Duration::from_millis(100));
+        let mut frames = scope.acquisitions(&["C1"], config);
+        assert!(matches!(frames.next(), Some(Err(MauiError::Timeout(_)))));
+        assert!(frames.next().is_none());
+    }
+
+    #[test]
+    fn duration_ends_the_stream_without_error() {
+        let (_, scope) = scope(false);
+        let config = StreamConfig::new().with_duration(Duration::from_millis(100));
+        assert!(scope.acquisitions(&["C1"], config).next().is_none());
+    }
+
+    #[cfg(feature = "async")]
+    mod background {
+        use super::*;
+        use futures_core::Stream;
+        use std::pin::Pin;
+        use std::task::{Context, Poll, Waker};
+
+        /// Poll `stream` until it yields, without an executor.
+        fn next(stream: &mut AcquisitionStream) -> Option<Result<Frame>> {
+            let mut cx = Context::from_waker(Waker::noop());
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                if let Poll::Ready(frame) = Pin::new(&mut *stream).poll_next(&mut cx) {
+                    return frame;
+                }
+                assert!(Instant::now() < deadline, "stream did not yield");
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+
+        /// Wait until only the test holds the simulated scope, which means the
+        /// background thread has ended.
+        fn wait_for_thread_exit(sim: &Arc<SimulatedScope>) {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while Arc::strong_count(sim) > 1 {
+                assert!(Instant::now() < deadline, "background thread still running");
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+
+        #[test]
+        fn stream_ends_after_max_frames() {
+            let (sim, scope) = scope(true);
+            let mut stream =
+                scope.acquisition_stream(&["C1"], StreamConfig::new().with_max_frames(2));
+            drop(scope);
+
+            assert_eq!(next(&mut stream).unwrap().unwrap().index, 0);
+            assert_eq!(next(&mut stream).unwrap().unwrap().index, 1);
+            assert!(next(&mut stream).is_none());
+            wait_for_thread_exit(&sim);
+        }
+
+        #[test]
+        fn full_buffer_stops_arming() {
+            let (sim, scope) = scope(true);
+            let stream = scope.acquisition_stream(&["C1"], StreamConfig::new().with_buffer(2));
+            drop(scope);
+
+            thread::sleep(Duration::from_millis(300));
+            assert_eq!(sim.acquisition_count(), 2);
+            drop(stream);
+            wait_for_thread_exit(&sim);
+            assert_eq!(sim.acquisition_count(), 2);
+        }
+
+        #[test]
+        fn dropping_the_stream_cancels_a_wait() {
+            let (sim, scope) = scope(false);
+            let stream = scope.acquisition_stream(&["C1"], StreamConfig::new());
+            drop(scope);
+
+            thread::sleep(Duration::from_millis(100));
+            drop(stream);
+            wait_for_thread_exit(&sim);
+            assert_eq!(sim.acquisition_count(), 0);
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! ## Strict Module
+//!
+//! Optional error checking after every command sent to the oscilloscope.
+//!
+
+use crate::subsystems::status::{DeviceError, StatusByte};
+use crate::utils::parse_response;
+use crate::{MauiError, Result, Transport};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The longest wait for a service request made while holding the exchange
+/// lock, so that other threads sharing the transport are not held up.
+const SRQ_LOCK_SLICE: Duration = Duration::from_millis(50);
+
+/// ## Strict Transport
+///
+/// A [`Transport`] wrapper shared by all the subsystems of a
+/// [`MauiOscilloscope`](crate::MauiOscilloscope). While strict mode is
+/// enabled, every command is followed by a read of the status registers and
+/// any error reported by the device is returned for that command.
+///
+pub(crate) struct StrictTransport {
+    inner: Arc<dyn Transport>,
+    enabled: AtomicBool,
+    // keeps a command and its status check together when used from several threads
+    exchange: Mutex<()>,
+}
+
+impl StrictTransport {
+    /// Wrap a transport, with strict mode disabled.
+    pub(crate) fn new(inner: Arc<dyn Transport>) -> StrictTransport {
+        StrictTransport {
+            inner,
+            enabled: AtomicBool::new(false),
+            exchange: Mutex::new(()),
+        }
+    }
+
+    /// Enable or disable strict mode.
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Return whether strict mode is enabled.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// Read the status registers and turn a reported error into a [`MauiError`]
+    /// for `cmd`.
+    fn check(&self, cmd: &str) -> Result<()> {
+        // CMR and EXR are read instead of ESR, so that events like OPC are
+        // left for whoever is waiting on them
+        let stb = StatusByte::from_bits_retain(parse_response(&self.inner.query("*STB?")?)?);
+
+        let cmr = parse_response::<u8>(&self.inner.query("CMR?")?)?;
+        if let Some(error) = DeviceError::command_error(cmr) {
+            return Err(MauiError::Command {
+                command: cmd.to_string(),
+                code: error.code,
+                description: error.description.to_string(),
+            });
+        }
+
+        let exr = parse_response::<u8>(&self.inner.query("EXR?")?)?;
+        if let Some(error) = DeviceError::execution_error(exr) {
+            return Err(MauiError::Execution {
+                command: cmd.to_string(),
+                code: error.code,
+                description: error.description.to_string(),
+            });
+        }
+
+        if stb.contains(StatusByte::VALUE_ADAPTED) {
+            // VAB is only reset by clearing the status registers
+            self.inner.command("*CLS")?;
+            return Err(MauiError::ValueAdapted {
+                command: cmd.to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        self.exchange.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Transport for StrictTransport {
+    fn command(&self, cmd: &str) -> Result<()> {
+        if !self.is_enabled() {
+            return self.inner.command(cmd);
+        }
+
+        let _exchange = self.lock();
+        self.inner.command(cmd)?;
+        self.check(cmd)
+    }
+
+    fn command_raw(&self, cmd: &[u8]) -> Result<()> {
+        if !self.is_enabled() {
+            return self.inner.command_raw(cmd);
+        }
+
+        let _exchange = self.lock();
+        self.inner.command_raw(cmd)?;
+        self.check(&block_command_header(cmd))
+    }
+
+    fn query(&self, cmd: &str) -> Result<String> {
+        let _exchange = self.lock();
+        self.inner.query(cmd)
+    }
+
+    fn query_raw(&self, cmd: &str) -> Result<Vec<u8>> {
+        let _exchange = self.lock();
+        self.inner.query_raw(cmd)
+    }
+
+    fn query_raw_with_progress(
+        &self,
+        cmd: &str,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<Vec<u8>> {
+        let _exchange = self.lock();
+        self.inner.query_raw_with_progress(cmd, progress)
+    }
+
+    fn command_raw_with_progress(
+        &self,
+        cmd: &[u8],
+        chunk_size: usize,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        if !self.is_enabled() {
+            return self
+                .inner
+                .command_raw_with_progress(cmd, chunk_size, progress);
+        }
+
+        let _exchange = self.lock();
+        self.inner
+            .command_raw_with_progress(cmd, chunk_size, progress)?;
+        self.check(&block_command_header(cmd))
+    }
+
+    fn set_timeout(&self, duration: Duration) {
+        self.inner.set_timeout(duration);
+    }
+
+    fn clear(&self) -> Result<()> {
+        let _exchange = self.lock();
+        self.inner.clear()
+    }
+
+    fn wait_for_srq(&self, timeout: Duration) -> Result<()> {
+        // wait in slices, releasing the lock in between for other exchanges
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let result = {
+                let _exchange = self.lock();
+                self.inner.wait_for_srq(remaining.min(SRQ_LOCK_SLICE))
+            };
+            match result {
+                Err(MauiError::Timeout(_)) if remaining > SRQ_LOCK_SLICE => continue,
+                Err(MauiError::Timeout(_)) => {
+                    return Err(MauiError::Timeout(format!(
+                        "no service request within {:?}",
+                        timeout
+                    )))
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Return the header of a command holding a block, to report it without the
+/// data of its block.
+fn block_command_header(cmd: &[u8]) -> String {
+    let header = match cmd.iter().position(|&b| b == b'#') {
+        Some(end) => &cmd[..end],
+        None => cmd,
+    };
+    String::from_utf8_lossy(header)
+        .trim_end_matches(',')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transports::simulated::SimulatedScope;
+
+    fn strict() -> (Arc<SimulatedScope>, StrictTransport) {
+        let sim = Arc::new(SimulatedScope::new());
+        let strict = StrictTransport::new(sim.clone());
+        strict.set_enabled(true);
+        (sim, strict)
+    }
+
+    #[test]
+    fn command_error_names_the_command() {
+        let (_, strict) = strict();
+        assert!(matches!(
+            strict.command("FROB 1"),
+            Err(MauiError::Command { command, code: 1, .. }) if command == "FROB 1"
+        ));
+        strict.command("TDIV 1E-3").unwrap();
+    }
+
+    #[test]
+    fn execution_error_names_the_command() {
+        let (_, strict) = strict();
+        assert!(matches!(
+            strict.command("VDIV 0.5"),
+            Err(MauiError::Execution { command, code: 27, .. }) if command == "VDIV 0.5"
+        ));
+    }
+
+    #[test]
+    fn value_adapted_clears_the_status() {
+        let (sim, strict) = strict();
+        assert!(matches!(
+            strict.command("TDIV 1E4"),
+            Err(MauiError::ValueAdapted { command }) if command == "TDIV 1E4"
+        ));
+        assert_eq!(sim.history().last().unwrap(), "*CLS");
+        strict.command("TDIV 1E-3").unwrap();
+    }
+
+    #[test]
+    fn disabled_strict_mode_sends_commands_unchecked() {
+        let (sim, strict) = strict();
+        strict.set_enabled(false);
+        assert!(!strict.is_enabled());
+        strict.command("FROB 1").unwrap();
+        strict.command("TDIV 1E4").unwrap();
+
+        assert_eq!(sim.history(), ["FROB 1", "TDIV 1E4"]);
+        assert_eq!(strict.query("CMR?").unwrap().trim(), "1");
+    }
+
+    #[test]
+    fn wait_for_srq_lets_other_exchanges_through() {
+        let (_, strict) = strict();
+        let strict = Arc::new(strict);
+        let waiter = strict.clone();
+        let wait = std::thread::spawn(move || waiter.wait_for_srq(Duration::from_millis(600)));
+
+        std::thread::sleep(Duration::from_millis(50));
+        let start = Instant::now();
+        strict.query("TDIV?").unwrap();
+        assert!(start.elapsed() < Duration::from_millis(300));
+        assert!(matches!(wait.join().unwrap(), Err(MauiError::Timeout(_))));
+    }
+}
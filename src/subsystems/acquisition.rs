@@ -1,8 +1,9 @@
 //! Acquisition Subsystem Module
 //!
 
-use anyhow::{anyhow, Result};
-use instrument_ctl::Instrument;
+use crate::subsystems::status::{InternalStateChange, StatusSubsystem};
+use crate::utils::parse_response;
+use crate::{MauiError, Result, Transport, WaitOptions};
 use std::{sync::Arc, time::Duration};
 
 /// ## Acquisition Subsystem
@@ -10,7 +11,7 @@ use std::{sync::Arc, time::Duration};
 /// Object for controlling the acquisition functions of the oscilloscope.
 /// 
 pub struct AcquisitionSubsystem {
-    client: Arc<Instrument>,
+    client: Arc<dyn Transport>,
 }
 
 impl AcquisitionSubsystem {
@@ -18,7 +19,7 @@ impl AcquisitionSubsystem {
     /// 
     /// Initialize an Acquisition Subsystem object.
     /// 
-    pub fn init(client: &Arc<Instrument>) -> AcquisitionSubsystem {
+    pub fn init(client: &Arc<dyn Transport>) -> AcquisitionSubsystem {
         AcquisitionSubsystem {
             client: client.clone(),
         }
@@ -40,6 +41,26 @@ impl AcquisitionSubsystem {
         Ok(())
     }
 
+    /// ## Arm and Wait for Acquisition
+    ///
+    /// Arm the scope and block until the acquisition it triggers is complete.
+    ///
+    /// Acquisitions completed before arming are discarded first, so that a
+    /// stale acquisition is never mistaken for the new one, and an acquisition
+    /// that completes as soon as the scope is armed is not missed.
+    ///
+    /// Fails with [`MauiError::Timeout`] once the deadline of `options` has
+    /// passed, or with [`MauiError::Cancelled`] if its token is cancelled.
+    ///
+    pub fn arm_and_wait_for_acquisition(&self, options: &WaitOptions) -> Result<()> {
+        StatusSubsystem::init(&self.client).wait_for_internal_state_after(
+            InternalStateChange::NEW_SIGNAL_ACQUIRED,
+            options,
+            || self.arm_acquisition().map(|_| false),
+        )?;
+        Ok(())
+    }
+
     /// ## Force Trigger
     ///
     /// Causes the device to make one acquisition if it is in an active trigger mode.
@@ -65,13 +86,19 @@ impl AcquisitionSubsystem {
     /// Prevents the device from analyzing new commands until the current acquisition has beem completed.
     ///
     /// The optional `timeout_duration` argument specifies the timeout after which the scope stops
-    /// waiting for new acquisitions. If the timeout duration is not given or zero, the scope waits
-    /// indefinitely.
+    /// waiting for new acquisitions, with sub-second resolution. If the timeout duration is not
+    /// given or zero, the scope waits indefinitely.
+    ///
+    /// The wait happens on the scope: this returns immediately, and the next query is answered
+    /// once the acquisition is complete. Use [`StatusSubsystem::wait_for_acquisition`] to block
+    /// the controller with a deadline and a cancellation token instead.
+    ///
+    /// [`StatusSubsystem::wait_for_acquisition`]: crate::subsystems::status::StatusSubsystem::wait_for_acquisition
     ///
     pub fn wait(&self, timeout_duration: Option<Duration>) -> Result<()> {
         let cmd = match timeout_duration {
-            Some(dur) => format!("WAIT {}", dur.as_secs()),
-            None => format!("WAIT"),
+            Some(dur) => format!("WAIT {}", dur.as_secs_f64()),
+            None => String::from("WAIT"),
         };
         self.client.command(&cmd)?;
         Ok(())
@@ -158,8 +185,11 @@ impl AcquisitionSubsystem {
     /// prefix.
     ///
     pub fn auto_setup(&self, channel: u8, find: bool) -> Result<()> {
-        if !(channel >= 1 || channel <= 4) {
-            return Err(anyhow!("channel {} does not exist", channel));
+        if !(1..=4).contains(&channel) {
+            return Err(MauiError::InvalidArgument(format!(
+                "channel {} does not exist",
+                channel
+            )));
         }
 
         let cmd = if find {
@@ -179,14 +209,17 @@ impl AcquisitionSubsystem {
     /// be specified.
     ///
     pub fn set_attenuation(&self, channel: u8, attenuation: u16) -> Result<()> {
-        if !(channel >= 1 || channel <= 4) {
-            return Err(anyhow!("channel {} does not exist", channel));
+        if !(1..=4).contains(&channel) {
+            return Err(MauiError::InvalidArgument(format!(
+                "channel {} does not exist",
+                channel
+            )));
         }
-        if !(attenuation >= 1 || attenuation <= 10000) {
-            return Err(anyhow!(
+        if !(1..=10000).contains(&attenuation) {
+            return Err(MauiError::InvalidArgument(format!(
                 "attenuation factor {} is out of range (1 to 10000)",
                 attenuation
-            ));
+            )));
         }
 
         let cmd = format!("C{}:ATTN {}", channel, attenuation);
@@ -200,12 +233,15 @@ impl AcquisitionSubsystem {
     /// The ATTENUATION? query returns the attenuation factor of the specified channel.
     ///
     pub fn get_attenuation(&self, channel: u8) -> Result<u16> {
-        if !(channel >= 1 || channel <= 4) {
-            return Err(anyhow!("channel {} does not exist", channel));
+        if !(1..=4).contains(&channel) {
+            return Err(MauiError::InvalidArgument(format!(
+                "channel {} does not exist",
+                channel
+            )));
         }
 
         let cmd = format!("C{}:ATTN?", channel);
-        let attentuation = self.client.query(&cmd)?.parse::<u16>()?;
+        let attentuation = parse_response::<u16>(&self.client.query(&cmd)?)?;
 
         Ok(attentuation)
     }
@@ -216,15 +252,21 @@ impl AcquisitionSubsystem {
     /// channel basis. When the <channel> argument is omitted, the BWL command applies to all channels.
     ///
     pub fn set_bandwidth_limit(&self, channel: u8, bandwidth_limit: Option<usize>) -> Result<()> {
-        if !(channel >= 1 || channel <= 4) {
-            return Err(anyhow!("channel {} does not exist", channel));
+        if !(1..=4).contains(&channel) {
+            return Err(MauiError::InvalidArgument(format!(
+                "channel {} does not exist",
+                channel
+            )));
         }
 
         // validate bandwidth limit
         let bandwidth_limit: &str = match bandwidth_limit {
-            Some(bw) => match bw {
-                _ => return Err(anyhow!("{} is not a valid bandwidth limit value", bw)),
-            },
+            Some(bw) => {
+                return Err(MauiError::InvalidArgument(format!(
+                    "{} is not a valid bandwidth limit value",
+                    bw
+                )))
+            }
             None => "OFF",
         };
 
@@ -239,8 +281,11 @@ impl AcquisitionSubsystem {
     /// The response to the BANDWIDTH_LIMIT? query shows the bandwidth filter setting for each channel.
     ///
     pub fn get_bandwidth_limit(&self, channel: u8) -> Result<Option<usize>> {
-        if !(channel >= 1 || channel <= 4) {
-            return Err(anyhow!("channel {} does not exist", channel));
+        if !(1..=4).contains(&channel) {
+            return Err(MauiError::InvalidArgument(format!(
+                "channel {} does not exist",
+                channel
+            )));
         }
 
         let cmd = format!("C{}:BWL?", channel);
@@ -256,7 +301,7 @@ impl AcquisitionSubsystem {
             "3GHZ" => Some(3_000_000_000),
             "4GHZ" => Some(4_000_000_000),
             "6GHZ" => Some(6_000_000_000),
-            _ => panic!("device returned invalid bandwidth limit value: {}", bwl),
+            _ => return Err(MauiError::parse(bwl, "not a bandwidth limit value")),
         };
 
         Ok(bwl)
@@ -267,12 +312,15 @@ impl AcquisitionSubsystem {
     /// The OFFSET? query returns the DC offset value of the specified channel at the probe tip.
     ///
     pub fn get_vertical_offset(&self, channel: u8) -> Result<f32> {
-        if !(channel >= 1 || channel <= 4) {
-            return Err(anyhow!("channel {} does not exist", channel));
+        if !(1..=4).contains(&channel) {
+            return Err(MauiError::InvalidArgument(format!(
+                "channel {} does not exist",
+                channel
+            )));
         }
 
         let cmd = format!("C{}:OFST?", channel);
-        let offset = self.client.query(&cmd)?.parse::<f32>()?;
+        let offset = parse_response::<f32>(&self.client.query(&cmd)?)?;
 
         Ok(offset)
     }
@@ -289,8 +337,11 @@ impl AcquisitionSubsystem {
     /// 2) in the STB register is set.
     ///
     pub fn set_vertical_offset(&self, channel: u8, offset: f32) -> Result<()> {
-        if !(channel >= 1 || channel <= 4) {
-            return Err(anyhow!("channel {} does not exist", channel));
+        if !(1..=4).contains(&channel) {
+            return Err(MauiError::InvalidArgument(format!(
+                "channel {} does not exist",
+                channel
+            )));
         }
 
         let cmd = format!("C{}:OFST {}V", channel, offset);
@@ -315,7 +366,7 @@ impl AcquisitionSubsystem {
     /// Get the timebase value per division.
     /// 
     pub fn get_time_div(&self) -> Result<f32> {
-        let time_div = self.client.query("TDIV?")?.parse::<f32>()?;
+        let time_div = parse_response::<f32>(&self.client.query("TDIV?")?)?;
         Ok(time_div)
     }
 
@@ -326,8 +377,11 @@ impl AcquisitionSubsystem {
     /// on the device.
     /// 
     pub fn set_volt_div(&self, channel: u8, volt_div: f32) -> Result<()> {
-        if !(channel >= 1 || channel <= 4) {
-            return Err(anyhow!("channel {} does not exist", channel));
+        if !(1..=4).contains(&channel) {
+            return Err(MauiError::InvalidArgument(format!(
+                "channel {} does not exist",
+                channel
+            )));
         }
 
         let cmd = format!("C{}:VDIV {}", channel, volt_div);
@@ -340,12 +394,15 @@ impl AcquisitionSubsystem {
     /// Get the volt value per division.
     /// 
     pub fn get_volt_div(&self, channel: u8) -> Result<f32> {
-        if !(channel >= 1 || channel <= 4) {
-            return Err(anyhow!("channel {} does not exist", channel));
+        if !(1..=4).contains(&channel) {
+            return Err(MauiError::InvalidArgument(format!(
+                "channel {} does not exist",
+                channel
+            )));
         }
 
         let cmd = format!("C{}:VDIV?", channel);
-        let vdiv = self.client.query(&cmd)?.parse::<f32>()?;
+        let vdiv = parse_response::<f32>(&self.client.query(&cmd)?)?;
         Ok(vdiv)
     }
 
@@ -353,6 +410,58 @@ impl AcquisitionSubsystem {
     // ACQUISITION MODE METHODS
     // ==========
 
+    /// ## Set Sequence Mode
+    /// 
+    /// Enable sequence mode, where each arm acquires `segments` segments of at most
+    /// `max_samples` samples each. The values will be adjusted to the nearest values
+    /// possible on the device.
+    /// 
+    pub fn set_sequence_mode(&self, segments: u32, max_samples: u32) -> Result<()> {
+        if segments < 2 {
+            return Err(MauiError::InvalidArgument(format!(
+                "sequence mode needs at least 2 segments, got {}",
+                segments
+            )));
+        }
+        if max_samples == 0 {
+            return Err(MauiError::InvalidArgument(
+                "sequence mode needs at least 1 sample per segment".into(),
+            ));
+        }
+
+        let cmd = format!("SEQUENCE ON,{},{}", segments, max_samples);
+        self.client.command(&cmd)?;
+        Ok(())
+    }
+
+    /// ## Set Sequence Mode Off
+    /// 
+    /// Disable sequence mode, returning to single segment acquisitions.
+    /// 
+    pub fn set_sequence_mode_off(&self) -> Result<()> {
+        self.client.command("SEQUENCE OFF")?;
+        Ok(())
+    }
+
+    /// ## Get Sequence Mode
+    /// 
+    /// Get the number of segments and the maximum samples per segment if sequence
+    /// mode is enabled, or `None` if it is disabled.
+    /// 
+    pub fn get_sequence_mode(&self) -> Result<Option<(u32, u32)>> {
+        let resp = self.client.query("SEQUENCE?")?;
+        let fields: Vec<&str> = resp.split(',').map(|f| f.trim()).collect();
+        match fields.as_slice() {
+            ["OFF", ..] => Ok(None),
+            ["ON", segments, max_samples] => {
+                let segments = parse_response::<u32>(segments)?;
+                // the maximum size is answered in scientific notation
+                let max_samples = parse_response::<f64>(max_samples)? as u32;
+                Ok(Some((segments, max_samples)))
+            }
+            _ => Err(MauiError::parse(resp, "invalid sequence mode")),
+        }
+    }
 
     // TRIGGER METHODS
     // ==========
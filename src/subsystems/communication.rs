@@ -1,8 +1,7 @@
 //! # Communication Subsystem Module
 //!
 
-use anyhow::{anyhow, Result};
-use instrument_ctl::Instrument;
+use crate::{MauiError, Result, Transport};
 use std::sync::Arc;
 
 /// ## Communication Subsystem
@@ -10,7 +9,7 @@ use std::sync::Arc;
 /// Object used to control the communications subsystem.
 ///
 pub struct CommunicationSubsystem {
-    client: Arc<Instrument>,
+    client: Arc<dyn Transport>,
 }
 
 impl CommunicationSubsystem {
@@ -18,7 +17,7 @@ impl CommunicationSubsystem {
     ///
     /// Initialize the object.
     ///
-    pub fn init(client: &Arc<Instrument>) -> CommunicationSubsystem {
+    pub fn init(client: &Arc<dyn Transport>) -> CommunicationSubsystem {
         // gather initial values
         CommunicationSubsystem {
             client: client.clone(),
@@ -55,7 +54,7 @@ impl CommunicationSubsystem {
             "OFF" => "off".into(),
             "FD" => "full dialog".into(),
             "EO" => "errors only".into(),
-            _ => return Err(anyhow!("unrecognized log level value: {}", resp[0])),
+            _ => return Err(MauiError::parse(resp[0], "unrecognized log level value")),
         };
 
         Ok(level)
@@ -3,8 +3,7 @@
 //! Subsystem reponsible for saving and loading the panel setup of the oscilloscope.
 //!
 
-use anyhow::{anyhow, Result};
-use instrument_ctl::Instrument;
+use crate::{MauiError, Result, Transport};
 use std::fs::OpenOptions;
 use std::io::{Read, Write};
 use std::sync::Arc;
@@ -14,7 +13,7 @@ use std::sync::Arc;
 /// Object for controlling saving and loading panel setups on the oscilloscope.
 /// 
 pub struct SetupSubsystem {
-    client: Arc<Instrument>,
+    client: Arc<dyn Transport>,
 }
 
 impl SetupSubsystem {
@@ -22,7 +21,7 @@ impl SetupSubsystem {
     /// 
     /// Intialize a Setup Subsystem object.
     /// 
-    pub fn init(client: &Arc<Instrument>) -> SetupSubsystem {
+    pub fn init(client: &Arc<dyn Transport>) -> SetupSubsystem {
         SetupSubsystem {
             client: client.clone(),
         }
@@ -48,9 +47,7 @@ impl SetupSubsystem {
             .open(filepath)?;
 
         // query the oscilloscope for the panel setup
-        let resp = self.client.query("PNSU?")?;
-        // trim the first 11 and last 8 bytes from the resp
-        let setup = &resp[11..resp.len() - 8];
+        let setup = self.get_panel_setup()?;
 
         // write the setup to the string
         file.write_all(setup.as_bytes())?;
@@ -65,8 +62,8 @@ impl SetupSubsystem {
     pub fn load_panel_setup(&self, filepath: &str) -> Result<()> {
         // check that the file has the correct extension
         if !filepath.ends_with(".lss") {
-            return Err(anyhow!(
-                "file is not a panel setup file, it does not have a .lss extension"
+            return Err(MauiError::InvalidArgument(
+                "file is not a panel setup file, it does not have a .lss extension".into(),
             ));
         }
 
@@ -75,6 +72,35 @@ impl SetupSubsystem {
         let mut setup: String = String::new();
         file.read_to_string(&mut setup)?;
 
+        self.set_panel_setup(&setup)
+    }
+
+    /// ## Get Panel Setup
+    ///
+    /// Return the panel setup of the device, as it would be saved in a `.lss` file.
+    ///
+    pub fn get_panel_setup(&self) -> Result<String> {
+        // query the oscilloscope for the panel setup
+        let resp = self.client.query("PNSU?")?;
+        // trim the first 11 and last 8 bytes from the resp
+        if resp.len() < 19
+            || !resp.is_char_boundary(11)
+            || !resp.is_char_boundary(resp.len() - 8)
+        {
+            return Err(MauiError::parse(resp, "panel setup response is too short"));
+        }
+        let setup = &resp[11..resp.len() - 8];
+
+        Ok(setup.to_string())
+    }
+
+    /// ## Set Panel Setup
+    ///
+    /// Send a panel setup, as returned by `get_panel_setup`, to the device.
+    ///
+    pub fn set_panel_setup(&self, setup: &str) -> Result<()> {
+        let mut setup = setup.to_string();
+
         // Add the CRC string
         setup += "ffffffff";
 
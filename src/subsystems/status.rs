@@ -0,0 +1,623 @@
+//! # Status Subsystem
+//!
+//! Subsystem for reading and decoding the status registers of the oscilloscope.
+//!
+
+use crate::utils::parse_response;
+use crate::{MauiError, Result, Transport, WaitOptions};
+use bitflags::bitflags;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The longest single wait for a service request, so that cancellation is
+/// noticed quickly.
+const SRQ_WAIT_SLICE: Duration = Duration::from_millis(200);
+
+bitflags! {
+    /// ## Status Byte
+    ///
+    /// The bits of the status byte register (STB).
+    ///
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct StatusByte: u8 {
+        /// INB, an enabled internal state change has occurred.
+        const INTERNAL_STATE_CHANGE = 1 << 0;
+        /// VAB, a command data value has been adapted to the closest legal value.
+        const VALUE_ADAPTED = 1 << 2;
+        /// MAV, a message is available in the output queue.
+        const MESSAGE_AVAILABLE = 1 << 4;
+        /// ESB, an enabled standard event has occurred.
+        const EVENT_STATUS = 1 << 5;
+        /// MSS/RQS, the scope is requesting service.
+        const REQUEST_SERVICE = 1 << 6;
+    }
+}
+
+bitflags! {
+    /// ## Standard Event Status
+    ///
+    /// The bits of the standard event status register (ESR).
+    ///
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct StandardEventStatus: u8 {
+        /// OPC, all pending operations are complete.
+        const OPERATION_COMPLETE = 1 << 0;
+        /// RQC, the scope requested bus control. Never used.
+        const REQUEST_CONTROL = 1 << 1;
+        /// QYE, a query error occurred.
+        const QUERY_ERROR = 1 << 2;
+        /// DDE, a device specific error occurred, see the DDR register.
+        const DEVICE_ERROR = 1 << 3;
+        /// EXE, an execution error occurred, see the EXR register.
+        const EXECUTION_ERROR = 1 << 4;
+        /// CME, a command error occurred, see the CMR register.
+        const COMMAND_ERROR = 1 << 5;
+        /// URQ, a front panel key was pressed.
+        const USER_REQUEST = 1 << 6;
+        /// PON, the scope was powered on.
+        const POWER_ON = 1 << 7;
+    }
+}
+
+bitflags! {
+    /// ## Internal State Change
+    ///
+    /// The bits of the internal state change register (INR).
+    ///
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct InternalStateChange: u16 {
+        /// A new signal has been acquired.
+        const NEW_SIGNAL_ACQUIRED = 1 << 0;
+        /// A screen dump has terminated.
+        const SCREEN_DUMP_TERMINATED = 1 << 1;
+        /// A return to the local state was detected.
+        const RETURN_TO_LOCAL = 1 << 2;
+        /// A time-out occurred in a data block transfer.
+        const BLOCK_TRANSFER_TIMEOUT = 1 << 3;
+        /// A segment of a sequence waveform has been acquired.
+        const SEGMENT_ACQUIRED = 1 << 4;
+        /// A storage medium was exchanged.
+        const STORAGE_EXCHANGED = 1 << 6;
+        /// The storage medium became full in autosave FILL mode.
+        const STORAGE_FULL = 1 << 7;
+        /// Waveform processing has terminated in F1.
+        const F1_PROCESSED = 1 << 8;
+        /// Waveform processing has terminated in F2.
+        const F2_PROCESSED = 1 << 9;
+        /// Waveform processing has terminated in F3.
+        const F3_PROCESSED = 1 << 10;
+        /// Waveform processing has terminated in F4.
+        const F4_PROCESSED = 1 << 11;
+        /// A pass/fail test has detected the desired outcome.
+        const PASS_FAIL_DETECTED = 1 << 12;
+        /// The trigger is ready.
+        const TRIGGER_READY = 1 << 13;
+        /// A probe was changed.
+        const PROBE_CHANGED = 1 << 14;
+
+        /// Waveform processing has terminated in any function trace.
+        const WAVEFORM_PROCESSED = Self::F1_PROCESSED.bits()
+            | Self::F2_PROCESSED.bits()
+            | Self::F3_PROCESSED.bits()
+            | Self::F4_PROCESSED.bits();
+    }
+}
+
+bitflags! {
+    /// ## Device Dependent Error
+    ///
+    /// The bits of the device dependent error register (DDR).
+    ///
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct DeviceDependentError: u16 {
+        /// Channel 1 overload condition detected.
+        const C1_OVERLOAD = 1 << 0;
+        /// Channel 2 overload condition detected.
+        const C2_OVERLOAD = 1 << 1;
+        /// Channel 3 overload condition detected.
+        const C3_OVERLOAD = 1 << 2;
+        /// Channel 4 overload condition detected.
+        const C4_OVERLOAD = 1 << 3;
+        /// External trigger input overload condition detected.
+        const EXTERNAL_OVERLOAD = 1 << 9;
+        /// Channel 1 hardware failure detected.
+        const C1_FAILURE = 1 << 10;
+        /// Channel 2 hardware failure detected.
+        const C2_FAILURE = 1 << 11;
+        /// Channel 3 hardware failure detected.
+        const C3_FAILURE = 1 << 12;
+        /// Channel 4 hardware failure detected.
+        const C4_FAILURE = 1 << 13;
+        /// Trigger hardware failure detected.
+        const TRIGGER_FAILURE = 1 << 14;
+        /// Timebase hardware failure detected.
+        const TIMEBASE_FAILURE = 1 << 15;
+    }
+}
+
+/// ## Device Error
+///
+/// An error code read from the CMR or EXR register, with its description
+/// from the remote control manual.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeviceError {
+    pub code: u8,
+    pub description: &'static str,
+}
+
+impl DeviceError {
+    /// ## Command Error
+    ///
+    /// Decode a CMR register value. Returns `None` if no error is reported.
+    ///
+    pub fn command_error(code: u8) -> Option<DeviceError> {
+        let description = match code {
+            0 => return None,
+            1 => "unrecognized command/query header",
+            2 => "illegal header path",
+            3 => "illegal number",
+            4 => "illegal number suffix",
+            5 => "unrecognized keyword",
+            6 => "string error",
+            7 => "GET embedded in another message",
+            10 => "arbitrary data block expected",
+            11 => "non-digit character in byte count field of arbitrary data block",
+            12 => "EOI detected during definite length data block transfer",
+            13 => "extra bytes detected during definite length data block transfer",
+            _ => "unknown command error",
+        };
+        Some(DeviceError { code, description })
+    }
+
+    /// ## Execution Error
+    ///
+    /// Decode an EXR register value. Returns `None` if no error is reported.
+    ///
+    pub fn execution_error(code: u8) -> Option<DeviceError> {
+        let description = match code {
+            0 => return None,
+            21 => "permission error, the command cannot be executed in local mode",
+            22 => "environment error, the instrument is not configured to process the command",
+            23 => "option error, the command applies to an option that is not installed",
+            24 => "unresolved parsing error",
+            25 => "parameter error, too many parameters specified",
+            26 => "non-implemented command",
+            27 => "parameter missing",
+            30 => "hex data error",
+            31 => "waveform error, the amount of data does not match the descriptor",
+            32 => "waveform descriptor error",
+            33 => "waveform text error",
+            34 => "waveform time error",
+            35 => "waveform data error",
+            36 => "panel setup error",
+            50 => "no mass storage present",
+            51 => "mass storage not formatted",
+            53 => "mass storage is write protected",
+            54 => "bad mass storage detected during formatting",
+            55 => "mass storage root directory full",
+            56 => "mass storage full",
+            57 => "mass storage file sequence numbers exhausted",
+            58 => "mass storage file not found",
+            59 => "requested directory not found",
+            61 => "mass storage filename not DOS compatible",
+            62 => "file already exists on mass storage",
+            _ => "unknown execution error",
+        };
+        Some(DeviceError { code, description })
+    }
+}
+
+/// ## Status Subsystem
+///
+/// Object for reading the status registers of the oscilloscope.
+///
+/// Reading the ESR, INR, CMR, EXR and DDR registers clears them on the device.
+///
+pub struct StatusSubsystem {
+    client: Arc<dyn Transport>,
+}
+
+impl StatusSubsystem {
+    /// ## Init
+    ///
+    /// Initialize a Status Subsystem object.
+    ///
+    pub fn init(client: &Arc<dyn Transport>) -> StatusSubsystem {
+        StatusSubsystem {
+            client: client.clone(),
+        }
+    }
+}
+
+impl StatusSubsystem {
+    // REGISTER QUERIES
+    // ==========
+
+    /// ## Get Status Byte
+    ///
+    /// Read the status byte register (`*STB?`).
+    ///
+    pub fn get_status_byte(&self) -> Result<StatusByte> {
+        let stb = parse_response::<u8>(&self.client.query("*STB?")?)?;
+        Ok(StatusByte::from_bits_retain(stb))
+    }
+
+    /// ## Get Standard Event Status
+    ///
+    /// Read and clear the standard event status register (`*ESR?`).
+    ///
+    pub fn get_standard_event_status(&self) -> Result<StandardEventStatus> {
+        let esr = parse_response::<u8>(&self.client.query("*ESR?")?)?;
+        Ok(StandardEventStatus::from_bits_retain(esr))
+    }
+
+    /// ## Get Internal State Change
+    ///
+    /// Read and clear the internal state change register (`INR?`).
+    ///
+    pub fn get_internal_state_change(&self) -> Result<InternalStateChange> {
+        let inr = parse_response::<u16>(&self.client.query("INR?")?)?;
+        Ok(InternalStateChange::from_bits_retain(inr))
+    }
+
+    /// ## Get Command Error
+    ///
+    /// Read and clear the command error register (`CMR?`). Returns `None` if
+    /// no command error occurred since the last read.
+    ///
+    pub fn get_command_error(&self) -> Result<Option<DeviceError>> {
+        let cmr = parse_response::<u8>(&self.client.query("CMR?")?)?;
+        Ok(DeviceError::command_error(cmr))
+    }
+
+    /// ## Get Execution Error
+    ///
+    /// Read and clear the execution error register (`EXR?`). Returns `None`
+    /// if no execution error occurred since the last read.
+    ///
+    pub fn get_execution_error(&self) -> Result<Option<DeviceError>> {
+        let exr = parse_response::<u8>(&self.client.query("EXR?")?)?;
+        Ok(DeviceError::execution_error(exr))
+    }
+
+    /// ## Get Device Dependent Error
+    ///
+    /// Read and clear the device dependent error register (`DDR?`).
+    ///
+    pub fn get_device_dependent_error(&self) -> Result<DeviceDependentError> {
+        let ddr = parse_response::<u16>(&self.client.query("DDR?")?)?;
+        Ok(DeviceDependentError::from_bits_retain(ddr))
+    }
+
+    /// ## Clear Status
+    ///
+    /// Clear all the status registers (`*CLS`).
+    ///
+    pub fn clear_status(&self) -> Result<()> {
+        self.client.command("*CLS")?;
+        Ok(())
+    }
+
+    // ENABLE MASKS
+    // ==========
+
+    /// ## Set Standard Event Enable
+    ///
+    /// Select which standard events are summarized in the ESB bit of the
+    /// status byte (`*ESE`).
+    ///
+    pub fn set_standard_event_enable(&self, mask: StandardEventStatus) -> Result<()> {
+        let cmd = format!("*ESE {}", mask.bits());
+        self.client.command(&cmd)?;
+        Ok(())
+    }
+
+    /// ## Get Standard Event Enable
+    ///
+    /// Read the standard event enable mask (`*ESE?`).
+    ///
+    pub fn get_standard_event_enable(&self) -> Result<StandardEventStatus> {
+        let ese = parse_response::<u8>(&self.client.query("*ESE?")?)?;
+        Ok(StandardEventStatus::from_bits_retain(ese))
+    }
+
+    /// ## Set Internal State Enable
+    ///
+    /// Select which internal state changes are summarized in the INB bit of
+    /// the status byte (`INE`).
+    ///
+    pub fn set_internal_state_enable(&self, mask: InternalStateChange) -> Result<()> {
+        let cmd = format!("INE {}", mask.bits());
+        self.client.command(&cmd)?;
+        Ok(())
+    }
+
+    /// ## Get Internal State Enable
+    ///
+    /// Read the internal state enable mask (`INE?`).
+    ///
+    pub fn get_internal_state_enable(&self) -> Result<InternalStateChange> {
+        let ine = parse_response::<u16>(&self.client.query("INE?")?)?;
+        Ok(InternalStateChange::from_bits_retain(ine))
+    }
+
+    /// ## Set Service Request Enable
+    ///
+    /// Select which bits of the status byte assert a service request (`*SRE`).
+    ///
+    pub fn set_service_request_enable(&self, mask: StatusByte) -> Result<()> {
+        let cmd = format!("*SRE {}", mask.bits());
+        self.client.command(&cmd)?;
+        Ok(())
+    }
+
+    /// ## Get Service Request Enable
+    ///
+    /// Read the service request enable mask (`*SRE?`).
+    ///
+    pub fn get_service_request_enable(&self) -> Result<StatusByte> {
+        let sre = parse_response::<u8>(&self.client.query("*SRE?")?)?;
+        Ok(StatusByte::from_bits_retain(sre))
+    }
+
+    // EVENT WAITS
+    // ==========
+
+    /// ## Wait For Internal State
+    ///
+    /// Block until one of the internal state changes in `events` occurs, and
+    /// return all the changes read from the INR register.
+    ///
+    /// Changes latched in INR before the call are discarded, so that only
+    /// changes occurring during the wait are returned. The INE and SRE masks
+    /// are set so that only `events` assert a service request, and the
+    /// transport waits for that request instead of polling the scope. Both
+    /// masks are restored before returning. Reading INR clears it, including
+    /// changes outside of `events`.
+    ///
+    /// Fails with [`MauiError::Timeout`] once the deadline of `options` has
+    /// passed, or with [`MauiError::Cancelled`] if its token is cancelled.
+    ///
+    pub fn wait_for_internal_state(
+        &self,
+        events: InternalStateChange,
+        options: &WaitOptions,
+    ) -> Result<InternalStateChange> {
+        let inr = self.wait_for_internal_state_after(events, options, || Ok(false))?;
+        Ok(inr.unwrap_or(InternalStateChange::empty()))
+    }
+
+    /// Like [`StatusSubsystem::wait_for_internal_state`], but run `start` once
+    /// the stale changes are discarded and before waiting, so that changes
+    /// caused by `start`, such as arming the trigger, are not missed. Returns
+    /// `None` without waiting if `start` reports that there is nothing to
+    /// wait for.
+    pub(crate) fn wait_for_internal_state_after(
+        &self,
+        events: InternalStateChange,
+        options: &WaitOptions,
+        start: impl FnOnce() -> Result<bool>,
+    ) -> Result<Option<InternalStateChange>> {
+        let ine = self.get_internal_state_enable()?;
+        let sre = self.get_service_request_enable()?;
+
+        let result = self
+            .set_internal_state_enable(events)
+            .and_then(|_| self.set_service_request_enable(StatusByte::INTERNAL_STATE_CHANGE))
+            // forget about changes latched before the wait
+            .and_then(|_| self.get_internal_state_change())
+            .and_then(|_| start())
+            .and_then(|done| match done {
+                true => Ok(None),
+                false => self.await_internal_state(events, options).map(Some),
+            });
+
+        // restore both masks whatever happened, but report the wait first
+        let restore_ine = self.set_internal_state_enable(ine);
+        let restore_sre = self.set_service_request_enable(sre);
+        let inr = result?;
+        restore_ine?;
+        restore_sre?;
+        Ok(inr)
+    }
+
+    /// ## Wait For Acquisition
+    ///
+    /// Block until a new signal has been acquired. Acquisitions completed
+    /// before the call are ignored, use
+    /// [`AcquisitionSubsystem::arm_and_wait_for_acquisition`] to arm the
+    /// trigger without missing an acquisition that completes right away.
+    ///
+    /// [`AcquisitionSubsystem::arm_and_wait_for_acquisition`]: crate::subsystems::acquisition::AcquisitionSubsystem::arm_and_wait_for_acquisition
+    ///
+    pub fn wait_for_acquisition(&self, options: &WaitOptions) -> Result<()> {
+        self.wait_for_internal_state(InternalStateChange::NEW_SIGNAL_ACQUIRED, options)?;
+        Ok(())
+    }
+
+    /// ## Wait For Waveform Processed
+    ///
+    /// Block until waveform processing has terminated in one of the function
+    /// traces.
+    ///
+    pub fn wait_for_waveform_processed(&self, options: &WaitOptions) -> Result<()> {
+        self.wait_for_internal_state(InternalStateChange::WAVEFORM_PROCESSED, options)?;
+        Ok(())
+    }
+
+    /// ## Wait Operation Complete
+    ///
+    /// Block until all pending operations of the scope are complete.
+    ///
+    /// `*OPC` makes the scope set the OPC bit of the ESR register once it is
+    /// done, which is routed to a service request through the ESE and SRE
+    /// masks. Both masks are restored before returning. Events already in the
+    /// ESR register are cleared before waiting.
+    ///
+    /// Fails with [`MauiError::Timeout`] once the deadline of `options` has
+    /// passed, or with [`MauiError::Cancelled`] if its token is cancelled.
+    ///
+    pub fn wait_operation_complete(&self, options: &WaitOptions) -> Result<()> {
+        let ese = self.get_standard_event_enable()?;
+        let sre = self.get_service_request_enable()?;
+
+        let result = self
+            .set_standard_event_enable(ese | StandardEventStatus::OPERATION_COMPLETE)
+            .and_then(|_| self.set_service_request_enable(StatusByte::EVENT_STATUS))
+            .and_then(|_| self.await_operation_complete(options));
+
+        // restore both masks whatever happened, but report the wait first
+        let restore_ese = self.set_standard_event_enable(ese);
+        let restore_sre = self.set_service_request_enable(sre);
+        result?;
+        restore_ese?;
+        restore_sre?;
+        Ok(())
+    }
+
+    /// Read INR after every service request until one of `events` is set.
+    fn await_internal_state(
+        &self,
+        events: InternalStateChange,
+        options: &WaitOptions,
+    ) -> Result<InternalStateChange> {
+        loop {
+            let inr = self.get_internal_state_change()?;
+            if inr.intersects(events) {
+                return Ok(inr);
+            }
+            options.check("internal state change")?;
+            self.wait_for_srq_slice(options)?;
+        }
+    }
+
+    /// Send `*OPC` and read ESR after every service request until OPC is set.
+    fn await_operation_complete(&self, options: &WaitOptions) -> Result<()> {
+        self.get_standard_event_status()?;
+        self.client.command("*OPC")?;
+        loop {
+            let esr = self.get_standard_event_status()?;
+            if esr.contains(StandardEventStatus::OPERATION_COMPLETE) {
+                return Ok(());
+            }
+            options.check("operation complete")?;
+            self.wait_for_srq_slice(options)?;
+        }
+    }
+
+    /// Wait for a service request for at most one slice, so that the caller
+    /// can check the deadline and the cancellation token again.
+    fn wait_for_srq_slice(&self, options: &WaitOptions) -> Result<()> {
+        match self.client.wait_for_srq(options.slice(SRQ_WAIT_SLICE)) {
+            Ok(()) | Err(MauiError::Timeout(_)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transports::record::{Exchange, ReplayTransport};
+
+    /// A status subsystem answering each register query with `responses`.
+    fn replay(responses: &[(&str, &str)]) -> (Arc<ReplayTransport>, StatusSubsystem) {
+        let exchanges = responses
+            .iter()
+            .map(|(command, response)| Exchange::Query {
+                command: command.to_string(),
+                response: Some(response.to_string()),
+                error: None,
+            })
+            .collect();
+        let replay = Arc::new(ReplayTransport::from_exchanges(exchanges));
+        let transport: Arc<dyn Transport> = replay.clone();
+        (replay, StatusSubsystem::init(&transport))
+    }
+
+    #[test]
+    fn registers_are_decoded() {
+        let (replay, status) = replay(&[
+            ("*STB?", "69"),
+            ("*ESR?", "49"),
+            ("INR?", "8193"),
+            ("DDR?", "33793"),
+        ]);
+
+        assert_eq!(
+            status.get_status_byte().unwrap(),
+            StatusByte::REQUEST_SERVICE
+                | StatusByte::VALUE_ADAPTED
+                | StatusByte::INTERNAL_STATE_CHANGE
+        );
+        assert_eq!(
+            status.get_standard_event_status().unwrap(),
+            StandardEventStatus::COMMAND_ERROR
+                | StandardEventStatus::EXECUTION_ERROR
+                | StandardEventStatus::OPERATION_COMPLETE
+        );
+        assert_eq!(
+            status.get_internal_state_change().unwrap(),
+            InternalStateChange::TRIGGER_READY | InternalStateChange::NEW_SIGNAL_ACQUIRED
+        );
+        assert_eq!(
+            status.get_device_dependent_error().unwrap(),
+            DeviceDependentError::TIMEBASE_FAILURE
+                | DeviceDependentError::C1_FAILURE
+                | DeviceDependentError::C1_OVERLOAD
+        );
+        replay.finish().unwrap();
+    }
+
+    #[test]
+    fn unknown_bits_are_kept() {
+        let (_, status) = replay(&[("INR?", "32"), ("DDR?", "16")]);
+        assert_eq!(status.get_internal_state_change().unwrap().bits(), 32);
+        assert_eq!(status.get_device_dependent_error().unwrap().bits(), 16);
+        assert!(InternalStateChange::WAVEFORM_PROCESSED.contains(InternalStateChange::F3_PROCESSED));
+    }
+
+    #[test]
+    fn error_codes_are_described() {
+        let (replay, status) =
+            replay(&[("CMR?", "0"), ("CMR?", "5"), ("EXR?", "58"), ("EXR?", "0")]);
+        assert_eq!(status.get_command_error().unwrap(), None);
+        assert_eq!(
+            status.get_command_error().unwrap(),
+            Some(DeviceError {
+                code: 5,
+                description: "unrecognized keyword"
+            })
+        );
+        assert_eq!(
+            status.get_execution_error().unwrap().unwrap().description,
+            "mass storage file not found"
+        );
+        assert_eq!(status.get_execution_error().unwrap(), None);
+        replay.finish().unwrap();
+
+        assert_eq!(
+            DeviceError::command_error(1).unwrap().description,
+            "unrecognized command/query header"
+        );
+        assert_eq!(
+            DeviceError::command_error(99).unwrap().description,
+            "unknown command error"
+        );
+        assert_eq!(DeviceError::execution_error(21).unwrap().code, 21);
+        assert_eq!(
+            DeviceError::execution_error(1).unwrap().description,
+            "unknown execution error"
+        );
+    }
+
+    #[test]
+    fn malformed_register_is_a_parse_error() {
+        let (_, status) = replay(&[("*STB?", "256")]);
+        assert!(matches!(
+            status.get_status_byte(),
+            Err(MauiError::Parse { .. })
+        ));
+    }
+}
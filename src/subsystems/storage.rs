@@ -2,14 +2,516 @@
 //!
 //! Subsystem for managing deleting and transfering files from storage.
 //!
+//! Directory listings are read with `DIRECTORY? DISK,HDD,ACTION,LIST,'<dir>'`,
+//! which answers with a quoted text listing of the form
+//!
+//! ```text
+//! DIR DISK,HDD,"
+//! Directory of D:\WAVEFORMS\
+//!
+//! SUB                  <DIR>  17-Jan-2024 15:31:58
+//! C1Trace00000.trc      3186  17-Jan-2024 15:31:58
+//!      1 File(s)  123456789 bytes free
+//! "
+//! ```
+//!
+
+use crate::data::wavedesc::TriggerTime;
+use crate::path::check_component;
+use crate::subsystems::waveform::TransferProgress;
+use crate::utils::{crc32, glob_match, parse_block};
+use crate::{DevicePath, IntoDevicePath, MauiError, Result, Transport};
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{io::Write, sync::Arc};
+
+/// The month abbreviations used in directory listings.
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// ## Entry Kind
+/// 
+/// Whether an entry of a directory listing is a file or a directory.
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+/// ## File Time
+/// 
+/// The modification time of an entry of a directory listing. The scope clock
+/// carries no time zone, so the time is in the time zone the scope is set to.
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// ## Directory Entry
+/// 
+/// A file or directory found in a directory on the device.
+/// 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    pub name: String,
+    /// The size in bytes, 0 for directories.
+    pub size: u64,
+    pub kind: EntryKind,
+    pub modified: FileTime,
+}
+
+impl FileTime {
+    /// ## To System Time
+    /// 
+    /// Return the time as if the scope clock was set to UTC. Pulled files are
+    /// stamped with this time, so that it can be compared with their
+    /// modification time on the controller.
+    /// 
+    pub fn to_system_time(&self) -> SystemTime {
+        let time = TriggerTime {
+            seconds: self.second as f64,
+            minutes: self.minute,
+            hours: self.hour,
+            days: self.day,
+            months: self.month,
+            year: self.year,
+        };
+        UNIX_EPOCH + Duration::from_secs(time.to_unix_seconds().max(0.0) as u64)
+    }
+}
+
+impl DirectoryEntry {
+    /// ## Is Directory
+    /// 
+    /// Return whether the entry is a directory.
+    /// 
+    pub fn is_directory(&self) -> bool {
+        self.kind == EntryKind::Directory
+    }
+}
+
+/// ## Sync Compare
+/// 
+/// How a directory sync decides that a file is unchanged and skips it.
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncCompare {
+    /// The copies have the same size and modification time. Pulled files are
+    /// stamped with the time of the scope, while a pushed file is unchanged if
+    /// the copy on the scope is not older than the controller copy.
+    ///
+    /// The clock of the scope carries no time zone, so pushed files are only
+    /// compared by time once the UTC offset of the scope is set with
+    /// [`SyncOptions::with_device_utc_offset`]. Without it they are compared
+    /// by size and CRC, like [`SyncCompare::Crc`].
+    #[default]
+    SizeAndTime,
+    /// The copies have the same CRC32. The scope has no command returning the
+    /// CRC of a file, so the file is read from the scope to compare it, and
+    /// only the write of the other copy is saved.
+    Crc,
+    /// Every file is transferred.
+    Always,
+}
+
+/// ## File Transfer Options
+/// 
+/// How a file is moved by the transfers of the storage subsystem. By default
+/// the data is sent in pieces of 1 MiB and a failed transfer is retried 3
+/// times.
+/// 
+/// The scope cannot resume a `TRANSFER_FILE`, so a retried transfer starts
+/// over, after the connection was brought back in sync.
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileTransferOptions {
+    chunk_size: usize,
+    retries: u32,
+}
+
+impl Default for FileTransferOptions {
+    fn default() -> Self {
+        FileTransferOptions {
+            chunk_size: 1024 * 1024,
+            retries: 3,
+        }
+    }
+}
+
+impl FileTransferOptions {
+    /// ## New
+    /// 
+    /// Pieces of 1 MiB, retried 3 times.
+    /// 
+    pub fn new() -> FileTransferOptions {
+        FileTransferOptions::default()
+    }
+
+    /// ## With Chunk Size
+    /// 
+    /// Send at most `bytes` bytes at a time, reporting the progress in between.
+    /// 
+    pub fn with_chunk_size(mut self, bytes: usize) -> FileTransferOptions {
+        self.chunk_size = bytes;
+        self
+    }
+
+    /// ## With Retries
+    /// 
+    /// Start a failed transfer again up to `retries` times before giving up.
+    /// 
+    pub fn with_retries(mut self, retries: u32) -> FileTransferOptions {
+        self.retries = retries;
+        self
+    }
+}
+
+/// ## Sync Options
+/// 
+/// Which files a directory sync transfers. By default every file is
+/// transferred unless its size and modification time are unchanged, and
+/// nothing is deleted. Pushed files are compared by size and CRC instead,
+/// unless the UTC offset of the scope clock is set.
+/// 
+/// Glob patterns are matched, ignoring case, against the path of a file
+/// relative to the synced directory with `/` separators. `?` matches one
+/// character and `*` any number of characters within a component, while `**`
+/// also crosses `/`. A pattern without a `/` is matched against the file name
+/// only.
+/// 
+/// ```no_run
+/// # use rs_maui::MauiOscilloscope;
+/// # use rs_maui::subsystems::storage::SyncOptions;
+/// # let scope = MauiOscilloscope::connect("192.168.1.10")?;
+/// // mirror the traces of the overnight run, freeing the disk of the scope
+/// let options = SyncOptions::new()
+///     .with_include("*.trc")
+///     .with_delete_after_pull(true);
+/// let report = scope.storage.pull_directory("D:\\Waveforms", "/mnt/nas/run", &options)?;
+/// println!("{} files pulled", report.transferred.len());
+/// # Ok::<(), rs_maui::MauiError>(())
+/// ```
+/// 
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SyncOptions {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    compare: SyncCompare,
+    delete_after_pull: bool,
+    device_utc_offset: Option<i32>,
+}
+
+impl SyncOptions {
+    /// ## New
+    /// 
+    /// Sync every file, skipping unchanged sizes and modification times.
+    /// 
+    pub fn new() -> SyncOptions {
+        SyncOptions::default()
+    }
+
+    /// ## With Include
+    /// 
+    /// Only sync the files matching one of the included glob patterns.
+    /// 
+    pub fn with_include(mut self, pattern: &str) -> SyncOptions {
+        self.include.push(pattern.to_string());
+        self
+    }
+
+    /// ## With Exclude
+    /// 
+    /// Never sync the files matching an excluded glob pattern.
+    /// 
+    pub fn with_exclude(mut self, pattern: &str) -> SyncOptions {
+        self.exclude.push(pattern.to_string());
+        self
+    }
+
+    /// ## With Compare
+    /// 
+    /// Set how unchanged files are detected.
+    /// 
+    pub fn with_compare(mut self, compare: SyncCompare) -> SyncOptions {
+        self.compare = compare;
+        self
+    }
+
+    /// ## With Delete After Pull
+    /// 
+    /// Delete each file from the scope once it is on the controller, whether
+    /// it was transferred or found unchanged.
+    /// 
+    pub fn with_delete_after_pull(mut self, delete: bool) -> SyncOptions {
+        self.delete_after_pull = delete;
+        self
+    }
+
+    /// ## With Device UTC Offset
+    /// 
+    /// Set the offset of the clock of the scope from UTC, in seconds, such as
+    /// 3600 for a scope set to UTC+1. Pulled files are then stamped with their
+    /// time in UTC, and pushed files can be compared by modification time.
+    /// 
+    pub fn with_device_utc_offset(mut self, seconds: i32) -> SyncOptions {
+        self.device_utc_offset = Some(seconds);
+        self
+    }
+
+    /// Return the time of a file of the scope on the clock of the controller.
+    fn device_time(&self, time: &FileTime) -> SystemTime {
+        let local = time.to_system_time();
+        let offset = self.device_utc_offset.unwrap_or(0);
+        let shift = Duration::from_secs(offset.unsigned_abs() as u64);
+        match offset >= 0 {
+            true => local.checked_sub(shift).unwrap_or(UNIX_EPOCH),
+            false => local + shift,
+        }
+    }
+
+    /// Return whether a relative path passes the include and exclude patterns.
+    fn matches(&self, path: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| glob_match(p, path)))
+            && !self.exclude.iter().any(|p| glob_match(p, path))
+    }
+}
+
+/// ## Sync Report
+/// 
+/// The files handled by a directory sync, as paths relative to the synced
+/// directory with `/` separators.
+/// 
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SyncReport {
+    pub transferred: Vec<String>,
+    pub unchanged: Vec<String>,
+    /// The files deleted from the scope after being pulled.
+    pub deleted: Vec<String>,
+    /// The number of bytes written by the transfers.
+    pub bytes: u64,
+}
+
+/// ## Hardcopy Format
+/// 
+/// The image format of a screen capture.
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HardcopyFormat {
+    Bmp,
+    #[default]
+    Jpeg,
+    Png,
+    Tiff,
+}
 
-use anyhow::Result;
-use instrument_ctl::Instrument;
-use std::fs::OpenOptions;
-use std::{
-    io::{Read, Write},
-    sync::Arc,
-};
+impl HardcopyFormat {
+    /// ## Extension
+    /// 
+    /// Return the usual file extension of the format, without the dot.
+    /// 
+    pub fn extension(&self) -> &'static str {
+        match self {
+            HardcopyFormat::Bmp => "bmp",
+            HardcopyFormat::Jpeg => "jpeg",
+            HardcopyFormat::Png => "png",
+            HardcopyFormat::Tiff => "tiff",
+        }
+    }
+
+    fn keyword(&self) -> &'static str {
+        match self {
+            HardcopyFormat::Bmp => "BMP",
+            HardcopyFormat::Jpeg => "JPEG",
+            HardcopyFormat::Png => "PNG",
+            HardcopyFormat::Tiff => "TIFF",
+        }
+    }
+}
+
+/// ## Hardcopy Background
+/// 
+/// The background colour of a screen capture.
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HardcopyBackground {
+    #[default]
+    Black,
+    White,
+}
+
+/// ## Hardcopy Area
+/// 
+/// The part of the screen that is captured.
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HardcopyArea {
+    /// Only the grids holding the traces.
+    GridAreaOnly,
+    /// The window of the oscilloscope application, with its menus.
+    DsoWindow,
+    /// The whole screen.
+    #[default]
+    FullScreen,
+}
+
+/// ## Hardcopy Orientation
+/// 
+/// The orientation of a screen capture.
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HardcopyOrientation {
+    Portrait,
+    #[default]
+    Landscape,
+}
+
+/// ## Hardcopy Config
+/// 
+/// How the screen is captured, as set with `HARDCOPY_SETUP`. By default the
+/// full screen is captured in landscape as a JPEG on a black background, and
+/// sent to the controller.
+/// 
+/// ```no_run
+/// # use rs_maui::MauiOscilloscope;
+/// # use rs_maui::subsystems::storage::{
+/// #     HardcopyArea, HardcopyBackground, HardcopyConfig, HardcopyFormat,
+/// # };
+/// # let scope = MauiOscilloscope::connect("192.168.1.10")?;
+/// // a PNG of the grids for a report, without touching the disk
+/// let config = HardcopyConfig::new()
+///     .with_format(HardcopyFormat::Png)
+///     .with_background(HardcopyBackground::White)
+///     .with_area(HardcopyArea::GridAreaOnly);
+/// let png = scope.storage.capture_screen(&config)?;
+/// # Ok::<(), rs_maui::MauiError>(())
+/// ```
+/// 
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HardcopyConfig {
+    format: HardcopyFormat,
+    background: HardcopyBackground,
+    area: HardcopyArea,
+    orientation: HardcopyOrientation,
+    disk: Option<(DevicePath, String)>,
+}
+
+impl HardcopyConfig {
+    /// ## New
+    /// 
+    /// A landscape JPEG of the full screen on a black background.
+    /// 
+    pub fn new() -> HardcopyConfig {
+        HardcopyConfig::default()
+    }
+
+    /// ## With Format
+    /// 
+    /// Set the image format.
+    /// 
+    pub fn with_format(mut self, format: HardcopyFormat) -> HardcopyConfig {
+        self.format = format;
+        self
+    }
+
+    /// ## With Background
+    /// 
+    /// Set the background colour.
+    /// 
+    pub fn with_background(mut self, background: HardcopyBackground) -> HardcopyConfig {
+        self.background = background;
+        self
+    }
+
+    /// ## With Area
+    /// 
+    /// Set the part of the screen that is captured.
+    /// 
+    pub fn with_area(mut self, area: HardcopyArea) -> HardcopyConfig {
+        self.area = area;
+        self
+    }
+
+    /// ## With Orientation
+    /// 
+    /// Set the orientation of the image.
+    /// 
+    pub fn with_orientation(mut self, orientation: HardcopyOrientation) -> HardcopyConfig {
+        self.orientation = orientation;
+        self
+    }
+
+    /// ## With Print to Disk
+    /// 
+    /// Save the captures to `directory` on the device instead of sending them
+    /// to the controller. The scope names the files after `file_name`, which
+    /// is checked when the setup is sent.
+    /// 
+    pub fn with_print_to_disk(mut self, directory: DevicePath, file_name: &str) -> HardcopyConfig {
+        self.disk = Some((directory, file_name.to_string()));
+        self
+    }
+
+    /// ## Format
+    /// 
+    /// Return the image format.
+    /// 
+    pub fn format(&self) -> HardcopyFormat {
+        self.format
+    }
+
+    /// Build the `HARDCOPY_SETUP` command, sending the captures to the
+    /// controller when `remote` is set.
+    fn command(&self, remote: bool) -> Result<String> {
+        let background = match self.background {
+            HardcopyBackground::Black => "BLACK",
+            HardcopyBackground::White => "WHITE",
+        };
+        let area = match self.area {
+            HardcopyArea::GridAreaOnly => "GRIDAREAONLY",
+            HardcopyArea::DsoWindow => "DSOWINDOW",
+            HardcopyArea::FullScreen => "FULLSCREEN",
+        };
+        let orientation = match self.orientation {
+            HardcopyOrientation::Portrait => "PORTRAIT",
+            HardcopyOrientation::Landscape => "LANDSCAPE",
+        };
+
+        let destination = match (&self.disk, remote) {
+            (Some((directory, file_name)), false) => {
+                if file_name.is_empty() {
+                    return Err(MauiError::InvalidArgument("hardcopy file name is empty".into()));
+                }
+                check_component(file_name).map_err(|reason| {
+                    MauiError::InvalidArgument(format!("invalid hardcopy file name: {}", reason))
+                })?;
+                format!(
+                    "FILE,DIR,\"{}\",FILE,\"{}\"",
+                    directory.to_directory_string(),
+                    file_name
+                )
+            }
+            _ => "REMOTE".to_string(),
+        };
+
+        Ok(format!(
+            "HCSU DEV,{},FORMAT,{},BCKG,{},DEST,{},AREA,{}",
+            self.format.keyword(),
+            orientation,
+            background,
+            destination,
+            area
+        ))
+    }
+}
 
 /// ## Storage Subsystem
 /// 
@@ -17,10 +519,13 @@ use std::{
 /// This includes:
 /// - transferring files to and from the scope
 /// - creating directories on the scope
+/// - listing directories and checking for files on the scope
+/// - syncing directories between the controller and the scope
 /// - deleting files on the scope
+/// - capturing the screen of the scope
 /// 
 pub struct StorageSubsystem {
-    client: Arc<Instrument>,
+    client: Arc<dyn Transport>,
 }
 
 impl StorageSubsystem {
@@ -28,7 +533,7 @@ impl StorageSubsystem {
     /// 
     /// Initialize a Storage System object.
     /// 
-    pub fn init(client: &Arc<Instrument>) -> StorageSubsystem {
+    pub fn init(client: &Arc<dyn Transport>) -> StorageSubsystem {
         StorageSubsystem {
             client: client.clone(),
         }
@@ -40,9 +545,8 @@ impl StorageSubsystem {
     ///
     /// Delete a specified file on the device.
     ///
-    pub fn delete_file_on_device(&self, filepath: String) -> Result<()> {
-        // make sure the filepath uses \ instead of /
-        let device_filepath: String = filepath.replace("/", "\\");
+    pub fn delete_file_on_device(&self, filepath: impl IntoDevicePath) -> Result<()> {
+        let device_filepath = filepath.into_device_path()?;
 
         // setup the command
         let cmd: String = format!("DELETE_FILE DISK,HDD,FILE,'{}'", device_filepath);
@@ -59,82 +563,202 @@ impl StorageSubsystem {
     ///
     pub fn transfer_file_to_device(
         &self,
-        device_filepath: String,
-        controller_filepath: String,
+        device_filepath: impl IntoDevicePath,
+        controller_filepath: impl AsRef<Path>,
     ) -> Result<()> {
-        // get the data on the computer
-        let mut file = OpenOptions::new().read(true).open(controller_filepath)?;
-        let mut data: String = String::new();
-        file.read_to_string(&mut data)?;
-
-        // add the CRC and the bytes count
-        data += "ffffffff";
-        let data_size = data.len();
-        data += &format!("#9{:0>9}", data_size);
+        let device_filepath = device_filepath.into_device_path()?;
 
-        // make sure the device filepath has a DOS filepath
-        let device_filepath = device_filepath.replace("/", "\\");
-
-        // setup the command
-        let cmd = format!("TRANSFER_FILE DISK,HDD,FILE,'{}',{}", device_filepath, data);
-        // execute the command
-        self.client.command(&cmd)?;
+        // get the data on the computer
+        let data = fs::read(controller_filepath)?;
 
-        Ok(())
+        self.write_file_on_device(device_filepath, &data)
     }
 
     /// ### Transfer File from Device
     ///
-    /// Transfer a file from the device to the controller.
+    /// Transfer a file from the device to the controller. The controller file
+    /// must not exist yet, and is only created once the whole file was received.
     ///
     pub fn transfer_file_from_device(
         &self,
-        device_filepath: String,
-        controller_filepath: String,
+        device_filepath: impl IntoDevicePath,
+        controller_filepath: impl AsRef<Path>,
     ) -> Result<()> {
-        // open the controller filepath
+        let device_filepath = device_filepath.into_device_path()?;
+
+        // get the data on the device first, so that a failed transfer leaves
+        // no empty file behind
+        let data = self.read_file_on_device(device_filepath)?;
+
+        // save the data to a new file
         let mut file = OpenOptions::new()
             .create_new(true)
             .write(true)
             .open(controller_filepath)?;
+        file.write_all(&data)?;
+
+        Ok(())
+    }
 
-        // make sure the device filepath has a DOS filepath
-        let device_filepath = device_filepath.replace("/", "\\");
+    /// ### Write File on Device
+    ///
+    /// Write `data` to a file on the device. The data is sent as a binary block
+    /// followed by its CRC32, which the device verifies. The transfer is split
+    /// and retried as set by the default `FileTransferOptions`.
+    ///
+    /// Data that is not valid UTF-8 needs a transport that can send binary
+    /// commands, which a VISA address cannot.
+    ///
+    pub fn write_file_on_device(
+        &self,
+        device_filepath: impl IntoDevicePath,
+        data: &[u8],
+    ) -> Result<()> {
+        self.write_file_on_device_with(device_filepath, data, &FileTransferOptions::new(), |_| {})
+    }
+
+    /// ### Write File on Device With
+    ///
+    /// Write `data` to a file on the device like `write_file_on_device`, sending
+    /// it in pieces of `options.chunk_size` bytes. `progress` is called after each
+    /// piece with the bytes of `data` sent so far and its length.
+    ///
+    /// A transfer that fails with a timeout or a transport error is sent again,
+    /// up to `options.retries` times.
+    ///
+    pub fn write_file_on_device_with(
+        &self,
+        device_filepath: impl IntoDevicePath,
+        data: &[u8],
+        options: &FileTransferOptions,
+        mut progress: impl FnMut(TransferProgress),
+    ) -> Result<()> {
+        let device_filepath = device_filepath.into_device_path()?;
+
+        // the block holds the data followed by its CRC as 8 hex digits
+        let crc = format!("{:08x}", crc32(data));
+        let block_size = data.len() + crc.len();
+
+        // setup the command
+        let mut cmd = format!(
+            "TRANSFER_FILE DISK,HDD,FILE,'{}',#9{:0>9}",
+            device_filepath, block_size
+        )
+        .into_bytes();
+        let header_length = cmd.len();
+        cmd.extend_from_slice(data);
+        cmd.extend_from_slice(crc.as_bytes());
+
+        // execute the command, reporting the progress in bytes of data
+        let mut report = |sent: usize, _| {
+            progress(TransferProgress {
+                received: sent.saturating_sub(header_length).min(data.len()),
+                total: data.len(),
+            })
+        };
+        self.retry_transfer(options.retries, || {
+            self.client
+                .command_raw_with_progress(&cmd, options.chunk_size, &mut report)
+        })
+    }
+
+    /// ### Read File on Device
+    ///
+    /// Read the content of a file on the device, verifying the CRC32 sent with it.
+    /// The transfer is retried as set by the default `FileTransferOptions`.
+    ///
+    pub fn read_file_on_device(&self, device_filepath: impl IntoDevicePath) -> Result<Vec<u8>> {
+        self.read_file_on_device_with(device_filepath, &FileTransferOptions::new(), |_| {})
+    }
+
+    /// ### Read File on Device With
+    ///
+    /// Read the content of a file on the device like `read_file_on_device`.
+    /// `progress` is called as the response arrives, with the bytes of the block
+    /// received so far and the length announced by its header, which includes
+    /// the 8 digits of the CRC.
+    ///
+    /// A transfer that fails with a timeout, a transport error, a malformed
+    /// block or a CRC mismatch is requested again, up to `options.retries` times.
+    ///
+    pub fn read_file_on_device_with(
+        &self,
+        device_filepath: impl IntoDevicePath,
+        options: &FileTransferOptions,
+        mut progress: impl FnMut(TransferProgress),
+    ) -> Result<Vec<u8>> {
+        let device_filepath = device_filepath.into_device_path()?;
 
         // setup the command
         let cmd = format!("TRANSFER_FILE? DISK,HDD,FILE,'{}'", device_filepath);
+
         // execute the command
-        let data = self.client.query(&cmd)?;
+        let mut report = |received, total| progress(TransferProgress { received, total });
+        self.retry_transfer(options.retries, || {
+            let resp = self.client.query_raw_with_progress(&cmd, &mut report)?;
+            Self::verify_file_block(&device_filepath, &resp)
+        })
+    }
 
-        // remove the byte count and CRC string
-        let data = data[11..data.len() - 8].to_owned();
+    /// Split the data of a `TRANSFER_FILE?` response from its CRC and verify it.
+    fn verify_file_block(device_filepath: &DevicePath, resp: &[u8]) -> Result<Vec<u8>> {
+        let block = parse_block(resp)?;
 
-        // save the data to the file
-        file.write_all(&data.as_bytes())?;
+        // split the CRC from the data
+        if block.len() < 8 {
+            return Err(MauiError::parse(
+                String::from_utf8_lossy(block),
+                "file transfer block is too short to hold a CRC",
+            ));
+        }
+        let (data, crc) = block.split_at(block.len() - 8);
+        let crc = std::str::from_utf8(crc)
+            .ok()
+            .and_then(|crc| u32::from_str_radix(crc, 16).ok())
+            .ok_or_else(|| {
+                MauiError::parse(String::from_utf8_lossy(crc), "invalid file transfer CRC")
+            })?;
 
-        Ok(())
+        // verify the data
+        let computed = crc32(data);
+        if crc != computed {
+            return Err(MauiError::Transport(format!(
+                "file '{}' was corrupted in transfer, CRC {:08x} was sent but {:08x} was received",
+                device_filepath, crc, computed
+            )));
+        }
+
+        Ok(data.to_vec())
+    }
+
+    /// Run a file transfer, bringing the connection back in sync and starting
+    /// over after the failures worth retrying.
+    fn retry_transfer<T>(&self, retries: u32, mut transfer: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            match transfer() {
+                Err(MauiError::Timeout(_) | MauiError::Transport(_) | MauiError::Parse { .. })
+                    if attempt < retries =>
+                {
+                    attempt += 1;
+                    self.client.clear()?;
+                }
+                result => return result,
+            }
+        }
     }
 
     /// ## Create Directory on Device
     ///
     /// Create a directory on the device.
     ///
-    pub fn create_directory_on_device(&self, directory: &str) -> Result<()> {
-        // replace the / with \
-        let mut directory = directory.replace("/", "\\");
-        // add a backslash to the end if it isn't already there
-        if !directory.ends_with("\\") {
-            directory.push('\\');
-        }
-        // add a backslash to the start if it isn't already there
-        if !directory.starts_with("\\") {
-            directory.insert(0, '\\');
-        }
+    pub fn create_directory_on_device(&self, directory: impl IntoDevicePath) -> Result<()> {
+        let directory = directory.into_device_path()?;
 
         // setup the command
         let set_dir_cmd: String = format!(
-            "VBS 'app.SaveRecall.Utilities.Directory=\"{}\"'",
-            directory
+            "VBS 'app.SaveRecall.Utilities.Directory={}'",
+            directory.to_vbs_string(true)
         );
         // let create_dir_cmd: String = "VBS 'app.SaveRecall.Utilities.CreateDir'".into();
         let create_dir_cmd: String = "VBS 'app.SaveRecall.Utilities.CreateDir';".into();
@@ -150,22 +774,16 @@ impl StorageSubsystem {
     ///
     /// Deletes all the files inside a directory on the device.
     ///
-    pub fn delete_all_files_in_directory_on_device(&self, directory: &str) -> Result<()> {
-        // replace the / with \
-        let mut directory = directory.replace("/", "\\");
-        // add a backslash to the end if it isn't already there
-        if !directory.ends_with("\\") {
-            directory.push('\\');
-        }
-        // add a backslash to the start if it isn't already there
-        if !directory.starts_with("\\") {
-            directory.insert(0, '\\');
-        }
+    pub fn delete_all_files_in_directory_on_device(
+        &self,
+        directory: impl IntoDevicePath,
+    ) -> Result<()> {
+        let directory = directory.into_device_path()?;
 
         // setup the command
         let set_dir_cmd: String = format!(
-            "VBS 'app.SaveRecall.Utilities.Directory=\"{}\"'",
-            directory
+            "VBS 'app.SaveRecall.Utilities.Directory={}'",
+            directory.to_vbs_string(true)
         );
         let del_all_cmd: String = "VBS 'app.SaveRecall.Utilities.DeleteAll'".into();
 
@@ -176,29 +794,715 @@ impl StorageSubsystem {
         Ok(())
     }
 
+    /// ## List Directory
+    ///
+    /// Return the files and directories inside a directory on the device. A
+    /// directory that does not exist has no entries.
+    ///
+    pub fn list_directory(&self, directory: impl IntoDevicePath) -> Result<Vec<DirectoryEntry>> {
+        let (entries, _) = self.query_directory(&directory.into_device_path()?)?;
+        Ok(entries)
+    }
+
+    /// ## Exists
+    ///
+    /// Return whether a file or directory exists on the device.
+    ///
+    pub fn exists(&self, path: impl IntoDevicePath) -> Result<bool> {
+        // the root of a drive always exists
+        let path = path.into_device_path()?;
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return Ok(true);
+        };
+
+        // a missing parent is listed without entries
+        let entries = self.list_directory(parent)?;
+
+        Ok(entries.iter().any(|e| e.name.eq_ignore_ascii_case(name)))
+    }
+
+    /// ## Free Space
+    ///
+    /// Return the number of bytes free on the `D:` drive of the device.
+    ///
+    pub fn free_space(&self) -> Result<u64> {
+        let (_, free) = self.query_directory(&DevicePath::root(Some('D')))?;
+        Ok(free)
+    }
+
+    /// Query the listing of a directory, returning its entries and the free
+    /// space of the drive.
+    fn query_directory(&self, directory: &DevicePath) -> Result<(Vec<DirectoryEntry>, u64)> {
+        // setup the command
+        let cmd = format!("DIRECTORY? DISK,HDD,ACTION,LIST,'{}'", directory);
+        // run the command
+        let resp = self.client.query(&cmd)?;
+
+        parse_directory_listing(&resp)
+    }
+
+    /// ## Pull Directory
+    ///
+    /// Mirror a directory of the device and its subdirectories into a directory
+    /// of the controller, creating it if needed. Files on the controller that
+    /// are not on the device are left alone.
+    ///
+    pub fn pull_directory(
+        &self,
+        device_directory: impl IntoDevicePath,
+        controller_directory: impl AsRef<Path>,
+        options: &SyncOptions,
+    ) -> Result<SyncReport> {
+        let device_directory = device_directory.into_device_path()?;
+        let controller_directory = controller_directory.as_ref();
+        let mut report = SyncReport::default();
+        self.pull_files(&device_directory, controller_directory, "", options, &mut report)?;
+        Ok(report)
+    }
+
+    /// ## Push Directory
+    ///
+    /// Mirror a directory of the controller and its subdirectories into a
+    /// directory of the device, creating directories on the device as needed.
+    /// Files on the device that are not on the controller are left alone.
+    ///
+    /// Unless the UTC offset of the scope is set in `options`, files are
+    /// compared by size and CRC, which reads every file of the same size back
+    /// from the device.
+    ///
+    pub fn push_directory(
+        &self,
+        controller_directory: impl AsRef<Path>,
+        device_directory: impl IntoDevicePath,
+        options: &SyncOptions,
+    ) -> Result<SyncReport> {
+        let device_directory = device_directory.into_device_path()?;
+        let controller_directory = controller_directory.as_ref();
+        let mut report = SyncReport::default();
+        self.push_files(controller_directory, &device_directory, "", options, &mut report)?;
+        Ok(report)
+    }
+
+    /// Pull the files of a device directory, `relative` to the synced directory.
+    fn pull_files(
+        &self,
+        device_directory: &DevicePath,
+        controller_directory: &Path,
+        relative: &str,
+        options: &SyncOptions,
+        report: &mut SyncReport,
+    ) -> Result<()> {
+        for entry in self.list_directory(device_directory)? {
+            let device_filepath = device_directory.join(&entry.name)?;
+            let controller_filepath = controller_directory.join(&entry.name);
+            let relative = join_relative_path(relative, &entry.name);
+
+            if entry.is_directory() {
+                self.pull_files(
+                    &device_filepath,
+                    &controller_filepath,
+                    &relative,
+                    options,
+                    report,
+                )?;
+                continue;
+            }
+            if !options.matches(&relative) {
+                continue;
+            }
+
+            let modified = options.device_time(&entry.modified);
+            let unchanged = options.compare == SyncCompare::SizeAndTime
+                && fs::metadata(&controller_filepath).is_ok_and(|m| {
+                    m.len() == entry.size && m.modified().ok() == Some(modified)
+                });
+
+            if unchanged {
+                report.unchanged.push(relative.clone());
+            } else {
+                let data = self.read_file_on_device(&device_filepath)?;
+                let unchanged = options.compare == SyncCompare::Crc
+                    && fs::read(&controller_filepath).is_ok_and(|d| crc32(&d) == crc32(&data));
+
+                if unchanged {
+                    report.unchanged.push(relative.clone());
+                } else {
+                    fs::create_dir_all(controller_directory)?;
+                    fs::write(&controller_filepath, &data)?;
+                    // stamp the file with the time of the scope for the next sync
+                    OpenOptions::new()
+                        .write(true)
+                        .open(&controller_filepath)?
+                        .set_modified(modified)?;
+                    report.transferred.push(relative.clone());
+                    report.bytes += data.len() as u64;
+                }
+            }
+
+            if options.delete_after_pull {
+                self.delete_file_on_device(&device_filepath)?;
+                report.deleted.push(relative);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Push the files of a controller directory, `relative` to the synced
+    /// directory.
+    fn push_files(
+        &self,
+        controller_directory: &Path,
+        device_directory: &DevicePath,
+        relative: &str,
+        options: &SyncOptions,
+        report: &mut SyncReport,
+    ) -> Result<()> {
+        // a directory missing on the device is listed without entries
+        let mut device_directory_exists = self.exists(device_directory)?;
+        let device_entries = match device_directory_exists {
+            true => self.list_directory(device_directory)?,
+            false => Vec::new(),
+        };
+
+        let mut entries = fs::read_dir(controller_directory)?
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let name = entry.file_name().into_string().map_err(|name| {
+                MauiError::InvalidArgument(format!("file name {:?} is not valid UTF-8", name))
+            })?;
+            let device_filepath = device_directory.join(&name)?;
+            let relative = join_relative_path(relative, &name);
+
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                self.push_files(&entry.path(), &device_filepath, &relative, options, report)?;
+                continue;
+            }
+            if !options.matches(&relative) {
+                continue;
+            }
+
+            let device_entry = device_entries
+                .iter()
+                .find(|e| !e.is_directory() && e.name.eq_ignore_ascii_case(&name));
+            // without the offset of the scope clock, times cannot be compared
+            let compare = match (options.compare, options.device_utc_offset) {
+                (SyncCompare::SizeAndTime, None) => SyncCompare::Crc,
+                (compare, _) => compare,
+            };
+            let unchanged = match (compare, device_entry) {
+                (SyncCompare::SizeAndTime, Some(device_entry)) => {
+                    device_entry.size == metadata.len()
+                        && metadata.modified().is_ok_and(|modified| {
+                            // listings of the scope are only precise to the second
+                            let modified = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+                            options.device_time(&device_entry.modified)
+                                >= UNIX_EPOCH + Duration::from_secs(modified.as_secs())
+                        })
+                }
+                (SyncCompare::Crc, Some(device_entry)) if device_entry.size == metadata.len() => {
+                    let data = fs::read(entry.path())?;
+                    crc32(&self.read_file_on_device(&device_filepath)?) == crc32(&data)
+                }
+                _ => false,
+            };
+            if unchanged {
+                report.unchanged.push(relative);
+                continue;
+            }
+
+            if !device_directory_exists {
+                self.create_directory_on_device(device_directory)?;
+                device_directory_exists = true;
+            }
+            let data = fs::read(entry.path())?;
+            self.write_file_on_device(&device_filepath, &data)?;
+            report.transferred.push(relative);
+            report.bytes += data.len() as u64;
+        }
+
+        Ok(())
+    }
+
     /// ## Get Screen Capture
     ///
     /// Get a screen shot of the current screen and save it to the specified file path.
     ///
     pub fn get_screen_capture(&self, filepath: &str) -> Result<()> {
+        self.get_screen_capture_with(filepath, &HardcopyConfig::new())
+    }
+
+    /// ## Get Screen Capture With
+    ///
+    /// Capture the screen as set by `config` and save it to the specified file
+    /// path, adding the extension of the image format if it is missing. The file
+    /// must not exist yet, and is only created once the image was received.
+    ///
+    pub fn get_screen_capture_with(&self, filepath: &str, config: &HardcopyConfig) -> Result<()> {
+        let extension = format!(".{}", config.format.extension());
         let mut filepath: String = filepath.into();
-        if !filepath.ends_with(".jpeg") {
-            filepath.push_str(".jpeg")
+        if !filepath.ends_with(&extension) {
+            filepath.push_str(&extension)
         }
 
-        // open the file
+        // get the data first, so that a failed capture leaves no empty file
+        // behind
+        let data = self.capture_screen(config)?;
+
+        // save the data to a new file
         let mut file = OpenOptions::new()
             .create_new(true)
             .write(true)
             .open(filepath)?;
+        file.write_all(&data)?;
+
+        Ok(())
+    }
 
-        // setup the screen dump 
-        self.client.command("HCSU DEV,JPEG,FORMAT,LANDSCAPE,BCKG,BLACK,DEST,REMOTE,AREA,FULLSCREEN")?;
-        // get the data?
+    /// ## Capture Screen
+    ///
+    /// Capture the screen as set by `config` and return the image in memory.
+    /// The image is always sent to the controller, even if `config` prints to
+    /// disk.
+    ///
+    pub fn capture_screen(&self, config: &HardcopyConfig) -> Result<Vec<u8>> {
+        // setup the screen dump
+        self.client.command(&config.command(true)?)?;
+        // get the data
         let data = self.client.query_raw("SCDP?")?;
-        // write the data
-        file.write_all(&data)?;
+        if data.is_empty() {
+            return Err(MauiError::parse("", "screen dump is empty"));
+        }
+
+        Ok(data)
+    }
+
+    /// ## Print Screen to Disk
+    ///
+    /// Capture the screen as set by `config` and save the image on the disk of
+    /// the device. `config` must be set up with
+    /// [`HardcopyConfig::with_print_to_disk`].
+    ///
+    pub fn print_screen_to_disk(&self, config: &HardcopyConfig) -> Result<()> {
+        if config.disk.is_none() {
+            return Err(MauiError::InvalidArgument(
+                "hardcopy config does not print to disk".into(),
+            ));
+        }
+
+        // setup the screen dump
+        self.client.command(&config.command(false)?)?;
+        // print the screen
+        self.client.command("SCDP")?;
 
         Ok(())
     }
+
+    /// ## Set Hardcopy Config
+    ///
+    /// Send `config` to the device, to be used by the hardcopy button of the
+    /// front panel.
+    ///
+    pub fn set_hardcopy_config(&self, config: &HardcopyConfig) -> Result<()> {
+        self.client.command(&config.command(false)?)
+    }
+}
+
+/// Parse a `DIRECTORY?` response into its entries and the free space of the
+/// drive.
+fn parse_directory_listing(resp: &str) -> Result<(Vec<DirectoryEntry>, u64)> {
+    // the listing is the quoted part of the response
+    let listing = resp
+        .find('"')
+        .zip(resp.rfind('"'))
+        .filter(|(start, end)| start < end)
+        .map(|(start, end)| &resp[start + 1..end])
+        .ok_or_else(|| MauiError::parse(resp, "directory listing is not quoted"))?;
+
+    let mut free = None;
+    let mut entries = Vec::new();
+    for line in listing.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(summary) = line.strip_suffix("bytes free") {
+            free = summary
+                .split_whitespace()
+                .last()
+                .and_then(|f| f.replace(',', "").parse::<u64>().ok());
+        } else if let Some(entry) = parse_directory_entry(line) {
+            entries.push(entry);
+        }
+    }
+    let free = free.ok_or_else(|| MauiError::parse(resp, "directory listing has no free space"))?;
+
+    Ok((entries, free))
+}
+
+/// Parse a `<name> <size|<DIR>> <dd-Mon-yyyy> <hh:mm:ss>` line of a directory
+/// listing, reading from the end so that names may hold spaces. The `.` and
+/// `..` entries of a subdirectory are skipped.
+fn parse_directory_entry(line: &str) -> Option<DirectoryEntry> {
+    let (rest, time) = split_last_word(line)?;
+    let (rest, date) = split_last_word(rest)?;
+    let (name, size) = split_last_word(rest)?;
+    let name = name.trim();
+    if name.is_empty() || name == "." || name == ".." {
+        return None;
+    }
+
+    let (kind, size) = if size.eq_ignore_ascii_case("<DIR>") {
+        (EntryKind::Directory, 0)
+    } else {
+        (EntryKind::File, size.replace(',', "").parse::<u64>().ok()?)
+    };
+
+    let mut date = date.split('-');
+    let day = date.next()?.parse::<u8>().ok()?;
+    let month = date.next()?;
+    let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month))? as u8 + 1;
+    let year = date.next()?.parse::<u16>().ok()?;
+    let mut time = time.split(':').map(|t| t.parse::<u8>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+
+    Some(DirectoryEntry {
+        name: name.to_string(),
+        size,
+        kind,
+        modified: FileTime { year, month, day, hour, minute, second },
+    })
+}
+
+/// Join a name to a path relative to a synced directory.
+fn join_relative_path(relative: &str, name: &str) -> String {
+    match relative.is_empty() {
+        true => name.to_string(),
+        false => format!("{}/{}", relative, name),
+    }
+}
+
+/// Split the last whitespace separated word from a line.
+fn split_last_word(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_end();
+    let start = line.rfind(char::is_whitespace)?;
+    let (rest, word) = line.split_at(start);
+    Some((rest, word.trim_start()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transports::simulated::SimulatedScope;
+    use std::sync::Mutex;
+
+    /// A simulated scope whose first raw transfers time out.
+    struct Flaky {
+        scope: SimulatedScope,
+        failures: Mutex<u32>,
+        clears: Mutex<u32>,
+    }
+
+    impl Flaky {
+        fn new(failures: u32) -> Arc<Flaky> {
+            Arc::new(Flaky {
+                scope: SimulatedScope::new(),
+                failures: Mutex::new(failures),
+                clears: Mutex::new(0),
+            })
+        }
+
+        fn fail(&self) -> Result<()> {
+            let mut failures = self.failures.lock().unwrap();
+            match *failures {
+                0 => Ok(()),
+                _ => {
+                    *failures -= 1;
+                    Err(MauiError::Timeout("injected".into()))
+                }
+            }
+        }
+    }
+
+    impl Transport for Flaky {
+        fn command(&self, cmd: &str) -> Result<()> {
+            self.scope.command(cmd)
+        }
+
+        fn command_raw(&self, cmd: &[u8]) -> Result<()> {
+            self.fail()?;
+            self.scope.command_raw(cmd)
+        }
+
+        fn query(&self, cmd: &str) -> Result<String> {
+            self.scope.query(cmd)
+        }
+
+        fn query_raw(&self, cmd: &str) -> Result<Vec<u8>> {
+            self.fail()?;
+            self.scope.query_raw(cmd)
+        }
+
+        fn set_timeout(&self, duration: Duration) {
+            self.scope.set_timeout(duration)
+        }
+
+        fn clear(&self) -> Result<()> {
+            *self.clears.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    fn storage(transport: &Arc<Flaky>) -> StorageSubsystem {
+        let transport: Arc<dyn Transport> = transport.clone();
+        StorageSubsystem::init(&transport)
+    }
+
+    #[test]
+    fn read_file_is_retried_after_clear() {
+        let flaky = Flaky::new(2);
+        flaky.scope.insert_file("D:\\data.bin", &[7; 100]);
+
+        let mut reports = Vec::new();
+        let data = storage(&flaky)
+            .read_file_on_device_with("D:\\data.bin", &FileTransferOptions::new(), |p| {
+                reports.push(p)
+            })
+            .unwrap();
+
+        assert_eq!(data, [7; 100]);
+        assert_eq!(*flaky.clears.lock().unwrap(), 2);
+        // the block holds the data and its 8 digit CRC
+        assert_eq!(reports.last(), Some(&TransferProgress { received: 108, total: 108 }));
+    }
+
+    #[test]
+    fn write_file_is_retried_after_clear() {
+        let flaky = Flaky::new(1);
+        let mut reports = Vec::new();
+        storage(&flaky)
+            .write_file_on_device_with("D:\\data.bin", &[1, 2, 3], &FileTransferOptions::new(), |p| {
+                reports.push(p)
+            })
+            .unwrap();
+
+        assert_eq!(flaky.scope.file("D:\\data.bin").unwrap(), [1, 2, 3]);
+        assert_eq!(*flaky.clears.lock().unwrap(), 1);
+        assert_eq!(reports.last(), Some(&TransferProgress { received: 3, total: 3 }));
+    }
+
+    #[test]
+    fn transfer_gives_up_after_retries() {
+        let flaky = Flaky::new(3);
+        flaky.scope.insert_file("D:\\data.bin", b"data");
+        let options = FileTransferOptions::new().with_retries(2);
+        let result = storage(&flaky).read_file_on_device_with("D:\\data.bin", &options, |_| {});
+
+        assert!(matches!(result, Err(MauiError::Timeout(_))));
+        assert_eq!(*flaky.clears.lock().unwrap(), 2);
+    }
+
+    /// A transport that can only send text, like a VISA address.
+    struct TextOnly(SimulatedScope);
+
+    impl Transport for TextOnly {
+        fn command(&self, cmd: &str) -> Result<()> {
+            self.0.command(cmd)
+        }
+
+        fn query(&self, cmd: &str) -> Result<String> {
+            self.0.query(cmd)
+        }
+
+        fn query_raw(&self, cmd: &str) -> Result<Vec<u8>> {
+            self.0.query_raw(cmd)
+        }
+
+        fn set_timeout(&self, duration: Duration) {
+            self.0.set_timeout(duration)
+        }
+    }
+
+    #[test]
+    fn text_only_transport_rejects_binary_files() {
+        let text_only = Arc::new(TextOnly(SimulatedScope::new()));
+        let transport: Arc<dyn Transport> = text_only.clone();
+        let storage = StorageSubsystem::init(&transport);
+
+        storage.write_file_on_device("D:\\setup.lss", b"TDIV 1E-3").unwrap();
+        assert_eq!(text_only.0.file("D:\\setup.lss").unwrap(), b"TDIV 1E-3");
+        assert!(matches!(
+            storage.write_file_on_device("D:\\data.bin", &[0xff, 0xfe, 0x00]),
+            Err(MauiError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn failed_download_creates_no_file() {
+        let flaky = Flaky::new(0);
+        let local = std::env::temp_dir().join(format!("rs-maui-missing-{}.bin", std::process::id()));
+        let result = storage(&flaky).transfer_file_from_device("D:\\missing.bin", &local);
+
+        assert!(result.is_err());
+        assert!(!local.exists());
+    }
+
+    #[test]
+    fn hardcopy_command_covers_each_setting() {
+        let command = |config: HardcopyConfig| config.command(true).unwrap();
+        assert_eq!(
+            command(HardcopyConfig::new()),
+            "HCSU DEV,JPEG,FORMAT,LANDSCAPE,BCKG,BLACK,DEST,REMOTE,AREA,FULLSCREEN"
+        );
+
+        for (format, keyword) in [
+            (HardcopyFormat::Bmp, "BMP"),
+            (HardcopyFormat::Jpeg, "JPEG"),
+            (HardcopyFormat::Png, "PNG"),
+            (HardcopyFormat::Tiff, "TIFF"),
+        ] {
+            assert_eq!(
+                command(HardcopyConfig::new().with_format(format)),
+                format!(
+                    "HCSU DEV,{},FORMAT,LANDSCAPE,BCKG,BLACK,DEST,REMOTE,AREA,FULLSCREEN",
+                    keyword
+                )
+            );
+        }
+        for (area, keyword) in [
+            (HardcopyArea::GridAreaOnly, "GRIDAREAONLY"),
+            (HardcopyArea::DsoWindow, "DSOWINDOW"),
+            (HardcopyArea::FullScreen, "FULLSCREEN"),
+        ] {
+            assert_eq!(
+                command(HardcopyConfig::new().with_area(area)),
+                format!("HCSU DEV,JPEG,FORMAT,LANDSCAPE,BCKG,BLACK,DEST,REMOTE,AREA,{}", keyword)
+            );
+        }
+        assert_eq!(
+            command(HardcopyConfig::new().with_background(HardcopyBackground::White)),
+            "HCSU DEV,JPEG,FORMAT,LANDSCAPE,BCKG,WHITE,DEST,REMOTE,AREA,FULLSCREEN"
+        );
+        assert_eq!(
+            command(HardcopyConfig::new().with_orientation(HardcopyOrientation::Portrait)),
+            "HCSU DEV,JPEG,FORMAT,PORTRAIT,BCKG,BLACK,DEST,REMOTE,AREA,FULLSCREEN"
+        );
+    }
+
+    #[test]
+    fn hardcopy_destination_follows_the_caller() {
+        let directory = DevicePath::parse("D:\\Hardcopy").unwrap();
+        let config = HardcopyConfig::new().with_print_to_disk(directory, "bench");
+
+        assert_eq!(
+            config.command(false).unwrap(),
+            "HCSU DEV,JPEG,FORMAT,LANDSCAPE,BCKG,BLACK,DEST,FILE,DIR,\"D:\\Hardcopy\\\",\
+             FILE,\"bench\",AREA,FULLSCREEN"
+        );
+        // captures sent to the controller ignore the directory
+        assert!(config.command(true).unwrap().contains(",DEST,REMOTE,"));
+        // without a directory the captures are always sent to the controller
+        assert!(HardcopyConfig::new().command(false).unwrap().contains(",DEST,REMOTE,"));
+    }
+
+    #[test]
+    fn hardcopy_file_name_is_checked() {
+        for file_name in ["CON", "lpt1.png", "a\\b", "shot?", ""] {
+            let directory = DevicePath::parse("D:\\Hardcopy").unwrap();
+            let config = HardcopyConfig::new().with_print_to_disk(directory, file_name);
+            assert!(
+                matches!(config.command(false), Err(MauiError::InvalidArgument(_))),
+                "{:?} was accepted",
+                file_name
+            );
+        }
+    }
+
+    #[test]
+    fn failed_screen_capture_leaves_no_file() {
+        let flaky = Flaky::new(1);
+        let local = local_directory("capture");
+        let filepath = local.join("screen");
+
+        let result = storage(&flaky).get_screen_capture(filepath.to_str().unwrap());
+        let created = local.join("screen.jpeg").exists();
+        fs::remove_dir_all(&local).unwrap();
+
+        assert!(matches!(result, Err(MauiError::Timeout(_))));
+        assert!(!created);
+    }
+
+    #[test]
+    fn directory_listing_is_parsed() {
+        let resp = "DIR DISK,HDD,\"\r\nDirectory of D:\\WAVEFORMS\\\r\n\r\n\
+                    .                    <DIR>  17-Jan-2024 15:30:00\r\n\
+                    ..                   <DIR>  17-Jan-2024 15:30:00\r\n\
+                    SUB                  <DIR>  17-Jan-2024 15:31:58\r\n\
+                    C1 run 2.trc     1,003,186  07-Feb-2024 09:05:01\r\n\
+                    setup.lss              512  31-dec-2023 23:59:59\r\n\
+                    \x20    2 File(s)   1,234,567,890 bytes free\r\n\"";
+        let (entries, free) = parse_directory_listing(resp).unwrap();
+
+        assert_eq!(free, 1_234_567_890);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].name, "SUB");
+        assert_eq!(entries[0].kind, EntryKind::Directory);
+        assert_eq!(entries[0].size, 0);
+        assert_eq!(entries[1].name, "C1 run 2.trc");
+        assert_eq!(entries[1].size, 1_003_186);
+        assert_eq!(
+            entries[1].modified,
+            FileTime { year: 2024, month: 2, day: 7, hour: 9, minute: 5, second: 1 }
+        );
+        assert_eq!(entries[2].kind, EntryKind::File);
+        assert_eq!(entries[2].modified.month, 12);
+
+        assert!(parse_directory_listing("DIR DISK,HDD,").is_err());
+        // a listing without free space is truncated
+        let truncated = "DIR DISK,HDD,\"\r\nSUB <DIR> 17-Jan-2024 15:31:58\r\n\"";
+        assert!(parse_directory_listing(truncated).is_err());
+    }
+
+    /// Create an empty controller directory for a sync test.
+    fn local_directory(name: &str) -> std::path::PathBuf {
+        let local = std::env::temp_dir().join(format!("rs-maui-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&local);
+        fs::create_dir_all(&local).unwrap();
+        local
+    }
+
+    #[test]
+    fn push_compares_by_crc_without_utc_offset() {
+        let flaky = Flaky::new(0);
+        let storage = storage(&flaky);
+        let local = local_directory("push-crc");
+        fs::write(local.join("a.trc"), b"abc").unwrap();
+
+        let options = SyncOptions::new();
+        let first = storage.push_directory(&local, "D:\\Push", &options).unwrap();
+        let second = storage.push_directory(&local, "D:\\Push", &options).unwrap();
+        // same size and no newer local time, only the CRC tells them apart
+        fs::write(local.join("a.trc"), b"xyz").unwrap();
+        let third = storage.push_directory(&local, "D:\\Push", &options).unwrap();
+        fs::remove_dir_all(&local).unwrap();
+
+        assert_eq!(first.transferred, ["a.trc"]);
+        assert_eq!(second.unchanged, ["a.trc"]);
+        assert_eq!(third.transferred, ["a.trc"]);
+        assert_eq!(flaky.scope.file("D:\\Push\\a.trc").unwrap(), b"xyz");
+    }
+
+    #[test]
+    fn push_keeps_existing_empty_directory() {
+        let flaky = Flaky::new(0);
+        let storage = storage(&flaky);
+        storage.create_directory_on_device("D:\\Empty").unwrap();
+        let local = local_directory("push-empty");
+        fs::write(local.join("a.trc"), b"abc").unwrap();
+
+        let report = storage.push_directory(&local, "D:\\Empty", &SyncOptions::new()).unwrap();
+        fs::remove_dir_all(&local).unwrap();
+
+        let created = flaky.scope.history().iter().filter(|c| c.contains("CreateDir")).count();
+        assert_eq!(report.transferred, ["a.trc"]);
+        assert_eq!(created, 1);
+    }
 }
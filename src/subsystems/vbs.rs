@@ -1,8 +1,7 @@
 //! # VBS Subsystem
 //!
 
-use anyhow::Result;
-use instrument_ctl::Instrument;
+use crate::{Result, Transport};
 use std::sync::Arc;
 
 /// ## VBS Subsystem
@@ -10,7 +9,7 @@ use std::sync::Arc;
 /// Object for sending VBS commands and queries to the oscilloscope.
 /// 
 pub struct VbsSubsystem {
-    client: Arc<Instrument>,
+    client: Arc<dyn Transport>,
 }
 
 impl VbsSubsystem {
@@ -18,7 +17,7 @@ impl VbsSubsystem {
     /// 
     /// Initialize a VBS Subsystem object.
     /// 
-    pub fn init(client: &Arc<Instrument>) -> VbsSubsystem {
+    pub fn init(client: &Arc<dyn Transport>) -> VbsSubsystem {
         VbsSubsystem {
             client: client.clone(),
         }
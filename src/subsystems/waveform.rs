@@ -1,20 +1,151 @@
 //! ## Waveform Module
 //!
 
-use anyhow::{anyhow, Result};
-use instrument_ctl::Instrument;
+use crate::data::raw::RawWaveform;
+use crate::data::wavedesc::WaveDesc;
+use crate::data::waveform::Waveform;
+use crate::path::check_component;
+use crate::subsystems::status::{InternalStateChange, StatusSubsystem};
+use crate::utils::parse_block;
+use crate::{IntoDevicePath, MauiError, Result, Transport, WaitOptions};
 use std::sync::Arc;
 
-use std::thread;
-use std::time::Duration;
 
+/// ## Transfer Options
+/// 
+/// The points of a trace sent by `WAVEFORM?`, as set with `WAVEFORM_SETUP`. By
+/// default every point of every segment is sent.
+/// 
+/// ```ignore
+/// // every 10th point of the first 100 000 points of segment 3
+/// let options = TransferOptions::new()
+///     .with_points(10_000)
+///     .with_sparsing(10)
+///     .with_segment(3);
+/// let waveform = scope.waveform.read_waveform_with("C1", &options)?;
+/// ```
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TransferOptions {
+    sparsing: u32,
+    points: u32,
+    first_point: u32,
+    segment: u32,
+}
+
+impl TransferOptions {
+    /// ## New
+    /// 
+    /// Send every point of every segment.
+    /// 
+    pub fn new() -> TransferOptions {
+        TransferOptions::default()
+    }
+
+    /// ## With Sparsing
+    /// 
+    /// Send one point out of every `sparsing` points. 0 and 1 send every point.
+    /// 
+    pub fn with_sparsing(mut self, sparsing: u32) -> TransferOptions {
+        self.sparsing = sparsing;
+        self
+    }
+
+    /// ## With Points
+    /// 
+    /// Send at most `points` points, after sparsing. 0 sends all points.
+    /// 
+    pub fn with_points(mut self, points: u32) -> TransferOptions {
+        self.points = points;
+        self
+    }
+
+    /// ## With First Point
+    /// 
+    /// Start at the point at index `first_point` of each segment.
+    /// 
+    pub fn with_first_point(mut self, first_point: u32) -> TransferOptions {
+        self.first_point = first_point;
+        self
+    }
+
+    /// ## With Segment
+    /// 
+    /// Send only the segment numbered `segment`, starting at 1, of a sequence
+    /// acquisition. 0 sends all segments.
+    /// 
+    pub fn with_segment(mut self, segment: u32) -> TransferOptions {
+        self.segment = segment;
+        self
+    }
+}
+
+/// ## Chunk Options
+/// 
+/// How a waveform is split into chunks by the chunked downloads. By default a
+/// chunk holds up to 1 000 000 points and a failed chunk is retried 3 times.
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkOptions {
+    chunk_points: u32,
+    retries: u32,
+}
+
+impl Default for ChunkOptions {
+    fn default() -> Self {
+        ChunkOptions {
+            chunk_points: 1_000_000,
+            retries: 3,
+        }
+    }
+}
+
+impl ChunkOptions {
+    /// ## New
+    /// 
+    /// Chunks of 1 000 000 points, retried 3 times.
+    /// 
+    pub fn new() -> ChunkOptions {
+        ChunkOptions::default()
+    }
+
+    /// ## With Chunk Points
+    /// 
+    /// Download at most `points` points per transfer.
+    /// 
+    pub fn with_chunk_points(mut self, points: u32) -> ChunkOptions {
+        self.chunk_points = points;
+        self
+    }
+
+    /// ## With Retries
+    /// 
+    /// Request a failed chunk again up to `retries` times before giving up.
+    /// 
+    pub fn with_retries(mut self, retries: u32) -> ChunkOptions {
+        self.retries = retries;
+        self
+    }
+}
+
+/// ## Transfer Progress
+/// 
+/// The progress of a chunked transfer, in bytes. Downloads count the bytes of
+/// the response blocks, as announced by their headers. For uploads, `received`
+/// counts the bytes of data sent.
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferProgress {
+    pub received: usize,
+    pub total: usize,
+}
 
 /// ## Waveform Subsystem
 /// 
 /// Object for controlling the saving of waveform data.
 /// 
 pub struct WaveformSubsystem {
-    client: Arc<Instrument>,
+    client: Arc<dyn Transport>,
 }
 
 impl WaveformSubsystem {
@@ -22,7 +153,7 @@ impl WaveformSubsystem {
     /// 
     /// Initialize a Waveform Subsystem object.
     /// 
-    pub fn init(client: &Arc<Instrument>) -> WaveformSubsystem {
+    pub fn init(client: &Arc<dyn Transport>) -> WaveformSubsystem {
         WaveformSubsystem {
             client: client.clone(),
         }
@@ -43,6 +174,319 @@ impl WaveformSubsystem {
         "ALL_DISPLAYED",
     ];
 
+    /// The traces that can be downloaded.
+    const WAVEFORM_TRACES: [&'static str; 12] = [
+        "C1",
+        "C2",
+        "C3",
+        "C4",
+        "F1",
+        "F2",
+        "F3",
+        "F4",
+        "M1",
+        "M2",
+        "M3",
+        "M4",
+    ];
+
+    /// Fail if `trace` cannot be downloaded.
+    pub(crate) fn verify_waveform_trace(trace: &str) -> Result<()> {
+        if !Self::WAVEFORM_TRACES.contains(&trace) {
+            return Err(MauiError::InvalidArgument(format!(
+                "'{}' is not a valid trace\nvalid traces: {:?}",
+                trace,
+                Self::WAVEFORM_TRACES
+            )));
+        }
+        Ok(())
+    }
+
+    /// ## Read Waveform
+    /// 
+    /// Download the last acquisition of a trace (channel, function or memory)
+    /// directly over the connection, and return it scaled to volts and seconds.
+    /// 
+    /// The transfer is set up for 16 bit little endian samples and all points of
+    /// all segments, in a single transfer. Use `read_waveform_chunked` for records
+    /// too long to be sent within the timeout of the connection.
+    /// 
+    pub fn read_waveform(&self, trace: &str) -> Result<Waveform> {
+        self.read_waveform_with(trace, &TransferOptions::new())
+    }
+
+    /// ## Read Waveform With
+    /// 
+    /// Download part of the last acquisition of a trace, as selected by `options`,
+    /// and return it scaled to volts and seconds. Only the selected points are sent
+    /// by the device.
+    /// 
+    pub fn read_waveform_with(&self, trace: &str, options: &TransferOptions) -> Result<Waveform> {
+        Ok(self.read_raw_waveform_with(trace, options)?.to_waveform())
+    }
+
+    /// ## Read Raw Waveform
+    /// 
+    /// Download the last acquisition of a trace like `read_waveform`, but keep
+    /// the samples as ADC codes to be scaled on demand. Use this for long records
+    /// that would not fit in memory as volts.
+    /// 
+    pub fn read_raw_waveform(&self, trace: &str) -> Result<RawWaveform> {
+        self.read_raw_waveform_with(trace, &TransferOptions::new())
+    }
+
+    /// ## Read Raw Waveform With
+    /// 
+    /// Download part of the last acquisition of a trace, as selected by `options`,
+    /// keeping the samples as ADC codes. The transfer is set up for whole records
+    /// again afterwards.
+    /// 
+    pub fn read_raw_waveform_with(&self, trace: &str, options: &TransferOptions) -> Result<RawWaveform> {
+        let result = self.download(trace, options, &mut |_, _| {});
+
+        // leave the transfer set up for whole records again, even after a failure
+        if *options != TransferOptions::new() {
+            let reset = self.setup_transfer(&TransferOptions::new());
+            return result.and_then(|waveform| reset.map(|_| waveform));
+        }
+        result
+    }
+
+    /// Download the points selected by `options`, leaving the transfer set up.
+    fn download(
+        &self,
+        trace: &str,
+        options: &TransferOptions,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<RawWaveform> {
+        // verify the trace
+        Self::verify_waveform_trace(trace)?;
+
+        // setup the transfer
+        self.setup_transfer(options)?;
+
+        // download the waveform
+        let cmd = format!("{}:WAVEFORM? ALL", trace);
+        let resp = self.client.query_raw_with_progress(&cmd, progress)?;
+
+        RawWaveform::parse(parse_block(&resp)?)
+    }
+
+    /// ## Read Descriptor
+    /// 
+    /// Download only the WAVEDESC block of the last acquisition of a trace, to find
+    /// out the length of the record before transferring it.
+    /// 
+    pub fn read_descriptor(&self, trace: &str) -> Result<WaveDesc> {
+        // verify the trace
+        Self::verify_waveform_trace(trace)?;
+
+        // the descriptor describes the points selected by the transfer setup
+        self.setup_transfer(&TransferOptions::new())?;
+
+        let cmd = format!("{}:WAVEFORM? DESC", trace);
+        let resp = self.client.query_raw(&cmd)?;
+        let block = parse_block(&resp)?;
+
+        let start = WaveDesc::find(block)
+            .ok_or_else(|| MauiError::InvalidWaveform("no WAVEDESC block found".into()))?;
+        WaveDesc::parse(&block[start..])
+    }
+
+    /// ## Read Preview
+    /// 
+    /// Download a decimated copy of the last acquisition of a trace with at most
+    /// `max_points` points, such as a thumbnail of a long record. The record length
+    /// is read first from the descriptor, and the sparsing factor chosen from it.
+    /// 
+    pub fn read_preview(&self, trace: &str, max_points: u32) -> Result<Waveform> {
+        if max_points == 0 {
+            return Err(MauiError::InvalidArgument(
+                "a preview must have at least one point".into(),
+            ));
+        }
+
+        let descriptor = self.read_descriptor(trace)?;
+        let sparsing = descriptor.wave_array_count.div_ceil(max_points).max(1);
+        let options = TransferOptions::new()
+            .with_sparsing(sparsing)
+            .with_points(max_points);
+
+        self.read_waveform_with(trace, &options)
+    }
+
+    /// ## Read Waveform Chunked
+    /// 
+    /// Download the last acquisition of a trace in chunks, like
+    /// `read_raw_waveform_chunked`, and return it scaled to volts and seconds.
+    /// 
+    pub fn read_waveform_chunked(
+        &self,
+        trace: &str,
+        options: &ChunkOptions,
+        progress: impl FnMut(TransferProgress),
+    ) -> Result<Waveform> {
+        Ok(self.read_raw_waveform_chunked(trace, options, progress)?.to_waveform())
+    }
+
+    /// ## Read Raw Waveform Chunked
+    /// 
+    /// Download the last acquisition of a trace in chunks of at most
+    /// `options.chunk_points` points, so that no single transfer exceeds the timeout
+    /// of the connection. `progress` is called as the chunks arrive with the number
+    /// of bytes received so far and the total, taken from the block headers. Each
+    /// block holds the descriptor of its chunk as well as its samples.
+    /// 
+    /// A chunk that fails with a timeout, a transport error or malformed data is
+    /// requested again, up to `options.retries` times, without downloading the
+    /// chunks already received again. Segments of a sequence acquisition are
+    /// downloaded one at a time, and must all have the same length.
+    /// 
+    /// The acquisition should be stopped during the download, otherwise the chunks
+    /// may come from different triggers.
+    /// 
+    pub fn read_raw_waveform_chunked(
+        &self,
+        trace: &str,
+        options: &ChunkOptions,
+        mut progress: impl FnMut(TransferProgress),
+    ) -> Result<RawWaveform> {
+        let descriptor = self.read_descriptor(trace)?;
+        self.read_chunks(trace, descriptor, options, &mut progress)
+    }
+
+    /// Download the record described by `descriptor` in chunks.
+    fn read_chunks(
+        &self,
+        trace: &str,
+        mut descriptor: WaveDesc,
+        options: &ChunkOptions,
+        progress: &mut dyn FnMut(TransferProgress),
+    ) -> Result<RawWaveform> {
+        let segments = descriptor.subarray_count.max(1);
+        if !descriptor.wave_array_count.is_multiple_of(segments) {
+            return Err(MauiError::InvalidWaveform(format!(
+                "{} points cannot be split into {} segments of equal length",
+                descriptor.wave_array_count, segments
+            )));
+        }
+        let segment_points = descriptor.wave_array_count / segments;
+        let sample_size = descriptor.comm_type.sample_size();
+        let chunk_points = options.chunk_points.max(1);
+
+        // segment numbers start at 1, 0 selects the only segment
+        let transfers: Vec<TransferOptions> = (0..segments)
+            .flat_map(|segment| {
+                let segment_number = if segments > 1 { segment + 1 } else { 0 };
+                (0..segment_points)
+                    .step_by(chunk_points as usize)
+                    .map(move |first_point| {
+                        TransferOptions::new()
+                            .with_segment(segment_number)
+                            .with_first_point(first_point)
+                            .with_points(chunk_points.min(segment_points - first_point))
+                    })
+            })
+            .collect();
+
+        // the blocks of the chunks to come are expected to hold as many bytes
+        // besides their samples as the header of the first one announces
+        let sample_bytes = |transfer: &TransferOptions| transfer.points as usize * sample_size;
+        let mut overhead = None;
+        let mut completed = 0;
+        let mut waveform: Option<RawWaveform> = None;
+        for (index, transfer) in transfers.iter().enumerate() {
+            let mut announced = 0;
+            let mut report = |received: usize, length: usize| {
+                let overhead = *overhead.get_or_insert(length.saturating_sub(sample_bytes(transfer)));
+                let remaining: usize = transfers[index + 1..]
+                    .iter()
+                    .map(|transfer| overhead + sample_bytes(transfer))
+                    .sum();
+                announced = length;
+                progress(TransferProgress {
+                    received: completed + received,
+                    total: completed + length + remaining,
+                });
+            };
+            let chunk = self.read_chunk(trace, transfer, options.retries, &mut report)?;
+            completed += announced;
+
+            match waveform.as_mut() {
+                None => waveform = Some(chunk),
+                Some(waveform) => {
+                    // keep the trigger time of the first chunk of each segment
+                    if transfer.first_point == 0 {
+                        waveform.trigger_times.extend(chunk.trigger_times);
+                    }
+                    waveform.samples.append(chunk.samples);
+                    if let (Some(samples), Some(chunk)) =
+                        (waveform.samples_2.as_mut(), chunk.samples_2)
+                    {
+                        samples.append(chunk);
+                    }
+                }
+            }
+        }
+
+        // leave the transfer set up for whole records again
+        self.setup_transfer(&TransferOptions::new())?;
+
+        // describe the whole record rather than the first chunk
+        let mut waveform = match waveform {
+            Some(waveform) => waveform,
+            None => self.read_raw_waveform_with(trace, &TransferOptions::new())?,
+        };
+        descriptor.wave_array_count = waveform.samples.len() as u32;
+        descriptor.wave_array_1_length = (waveform.samples.len() * sample_size) as u32;
+        descriptor.wave_array_2_length = waveform
+            .samples_2
+            .as_ref()
+            .map_or(0, |samples| (samples.len() * sample_size) as u32);
+        descriptor.trigtime_array_length = (waveform.trigger_times.len() * 16) as u32;
+        descriptor.ris_time_array_length = (waveform.ris_times.len() * 8) as u32;
+        waveform.descriptor = descriptor;
+
+        Ok(waveform)
+    }
+
+    /// Download one chunk, retrying the failures worth retrying once the
+    /// connection is back in sync.
+    fn read_chunk(
+        &self,
+        trace: &str,
+        transfer: &TransferOptions,
+        retries: u32,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<RawWaveform> {
+        let mut attempt = 0;
+        loop {
+            match self.download(trace, transfer, progress) {
+                Err(
+                    MauiError::Timeout(_)
+                    | MauiError::Transport(_)
+                    | MauiError::InvalidWaveform(_)
+                    | MauiError::Parse { .. },
+                ) if attempt < retries => {
+                    attempt += 1;
+                    self.client.clear()?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn setup_transfer(&self, options: &TransferOptions) -> Result<()> {
+        self.client.command("COMM_FORMAT DEF9,WORD,BIN")?;
+        self.client.command("COMM_ORDER LO")?;
+        let cmd = format!(
+            "WAVEFORM_SETUP SP,{},NP,{},FP,{},SN,{}",
+            options.sparsing, options.points, options.first_point, options.segment
+        );
+        self.client.command(&cmd)?;
+        Ok(())
+    }
+
     /// ## Set Autosave Mode Fill
     /// 
     /// Set the autosave mode to fill. This will begin as soon
@@ -98,11 +542,11 @@ impl WaveformSubsystem {
     pub fn set_autosave_trace(&self, trace: &str) -> Result<()> {
         // verify the trace
         if !Self::TRACES.contains(&trace) {
-            return Err(anyhow!(
+            return Err(MauiError::InvalidArgument(format!(
                 "'{}' is not a valid trace\nvalid traces: {:?}",
                 trace,
                 Self::TRACES
-            ));
+            )));
         }
 
         // prepare the command
@@ -115,22 +559,21 @@ impl WaveformSubsystem {
 
     /// ## Set Autosave Path
     /// 
-    /// Set the directory and the trace title to save.
+    /// Set the directory and the trace title to save. The trace title starts
+    /// the name of every saved file, so it must be a valid file name.
     /// 
-    pub fn set_autosave_path(&self, directory: &str, trace_title: &str) -> Result<()> {
-        // replace the / with \
-        let mut directory = directory.replace("/", "\\");
-        // add a backslash to the end if it isn't already there
-        if !directory.ends_with("\\") {
-            directory.push('\\');
-        }
-        // add a backslash to the start if it isn't already there
-        if !directory.starts_with("\\") {
-            directory.insert(0, '\\');
-        }
+    pub fn set_autosave_path(
+        &self,
+        directory: impl IntoDevicePath,
+        trace_title: &str,
+    ) -> Result<()> {
+        let directory = directory.into_device_path()?;
+        check_component(trace_title).map_err(|reason| {
+            MauiError::InvalidArgument(format!("invalid trace title: {}", reason))
+        })?;
 
         let set_save_to_file: String = "VBS 'app.SaveRecall.Waveform.SaveTo=\"File\"'".into();
-        let set_save_directory = format!("VBS 'app.SaveRecall.Waveform.WaveformDir=\"{}\"'", directory);
+        let set_save_directory = format!("VBS 'app.SaveRecall.Waveform.WaveformDir={}'", directory.to_vbs_string(true));
         let set_trace_title = format!("VBS 'app.SaveRecall.Waveform.TraceTitle=\"{}\"'", trace_title);
 
         self.client.command(&set_save_to_file)?;
@@ -146,19 +589,167 @@ impl WaveformSubsystem {
     /// mode is switched to OFF or the scope cannot save more files
     /// to the directory.
     /// 
-    pub fn wait_fill_complete(&self) -> Result<()> {
+    /// The scope is not polled: the autosave mode is checked again after each
+    /// acquisition and once the storage is full, both signalled by a service
+    /// request. A mode switched to OFF from the front panel is therefore
+    /// noticed at the next acquisition.
+    /// 
+    /// Fails with a timeout error once the deadline of `options` has passed,
+    /// or with a cancelled error if its token is cancelled.
+    /// 
+    pub fn wait_fill_complete(&self, options: &WaitOptions) -> Result<()> {
+        let status = StatusSubsystem::init(&self.client);
+        let events = InternalStateChange::NEW_SIGNAL_ACQUIRED | InternalStateChange::STORAGE_FULL;
         loop {
-            // get the current mode
-            let resp: String= self.client.query("STST?")?;
-            let mode = resp.split(",").collect::<Vec<&str>>()[3];
+            // check the mode once stale events are discarded, so that none is missed
+            let inr = status.wait_for_internal_state_after(events, options, || {
+                Ok(self.get_autosave_mode()? == "OFF")
+            })?;
+            match inr {
+                Some(inr) if !inr.contains(InternalStateChange::STORAGE_FULL) => continue,
+                _ => break,
+            }
+        }
 
-            // check that the mode is off
-            if mode == "OFF"  { break; }
+        Ok(())
+    }
 
-            // sleep to not overload cpu
-            thread::sleep(Duration::from_millis(50));
+    /// Return the autosave mode read from `STORE_SETUP?`.
+    fn get_autosave_mode(&self) -> Result<String> {
+        let resp: String = self.client.query("STST?")?;
+        match resp.split(",").nth(3) {
+            Some(mode) => Ok(mode.trim().to_string()),
+            None => Err(MauiError::parse(resp, "missing autosave mode")),
         }
+    }
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transports::record::{Exchange, ReplayTransport};
+    use crate::transports::simulated::SimulatedScope;
+
+    fn subsystem(transport: Arc<dyn Transport>) -> WaveformSubsystem {
+        WaveformSubsystem::init(&transport)
+    }
+
+    #[test]
+    fn chunked_download_matches_single_transfer() {
+        let sim = Arc::new(SimulatedScope::new());
+        sim.command("ARM").unwrap();
+        let waveform = subsystem(sim);
+
+        let whole = waveform.read_raw_waveform("C1").unwrap();
+        let mut reports = Vec::new();
+        let options = ChunkOptions::new().with_chunk_points(300);
+        let chunked = waveform
+            .read_raw_waveform_chunked("C1", &options, |p| reports.push(p))
+            .unwrap();
+
+        assert_eq!(chunked.samples, whole.samples);
+        assert_eq!(chunked.descriptor.wave_array_count, whole.descriptor.wave_array_count);
+        // one report per chunk, each announcing the same total
+        assert_eq!(reports.len(), 4);
+        let total = reports[0].total;
+        assert!(reports.iter().all(|report| report.total == total));
+        assert!(reports.windows(2).all(|pair| pair[0].received < pair[1].received));
+        assert_eq!(reports.last().unwrap().received, total);
+    }
+
+    #[test]
+    fn single_transfer_skips_the_descriptor_query() {
+        let sim = Arc::new(SimulatedScope::new());
+        sim.command("ARM").unwrap();
+
+        subsystem(sim.clone()).read_raw_waveform("C1").unwrap();
+
+        let queries: Vec<String> = sim
+            .history()
+            .into_iter()
+            .filter(|command| command.contains("WAVEFORM?"))
+            .collect();
+        assert_eq!(queries, ["C1:WAVEFORM? ALL"]);
+    }
+
+    /// The `WAVEFORM_SETUP` commands received by the simulator, in order.
+    fn transfer_setups(sim: &SimulatedScope) -> Vec<String> {
+        sim.history()
+            .into_iter()
+            .filter(|command| command.starts_with("WAVEFORM_SETUP"))
+            .collect()
+    }
+
+    #[test]
+    fn transfer_options_are_sent_then_reset() {
+        let sim = Arc::new(SimulatedScope::new());
+        sim.command("ARM").unwrap();
+        let waveform = subsystem(sim.clone());
+
+        let options = TransferOptions::new()
+            .with_sparsing(2)
+            .with_points(100)
+            .with_first_point(10);
+        let window = waveform.read_waveform_with("C1", &options).unwrap();
+
+        assert_eq!(window.voltages.len(), 100);
+        assert_eq!(
+            transfer_setups(&sim),
+            [
+                "WAVEFORM_SETUP SP,2,NP,100,FP,10,SN,0",
+                "WAVEFORM_SETUP SP,0,NP,0,FP,0,SN,0",
+            ]
+        );
+    }
+
+    #[test]
+    fn preview_sparsing_follows_the_record_length() {
+        let sim = Arc::new(SimulatedScope::new());
+        sim.command("ARM").unwrap();
+        let waveform = subsystem(sim.clone());
+
+        let points = waveform.read_descriptor("C1").unwrap().wave_array_count;
+        let sparsing = points.div_ceil(100);
+        assert!(sparsing > 1);
+        let preview = waveform.read_preview("C1", 100).unwrap();
+
+        assert!(preview.voltages.len() <= 100);
+        assert_eq!(
+            transfer_setups(&sim)[1..],
+            [
+                "WAVEFORM_SETUP SP,0,NP,0,FP,0,SN,0".to_string(),
+                format!("WAVEFORM_SETUP SP,{},NP,100,FP,0,SN,0", sparsing),
+                "WAVEFORM_SETUP SP,0,NP,0,FP,0,SN,0".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn uneven_segments_are_rejected() {
+        let descriptor = WaveDesc {
+            wave_array_count: 1001,
+            subarray_count: 2,
+            ..WaveDesc::default()
+        };
+        let mut block = format!("#9{:09}", 346).into_bytes();
+        block.extend_from_slice(&descriptor.to_bytes());
+        let setup = |command: &str| Exchange::Command {
+            command: command.into(),
+            error: None,
+        };
+        let replay = Arc::new(ReplayTransport::from_exchanges(vec![
+            setup("COMM_FORMAT DEF9,WORD,BIN"),
+            setup("COMM_ORDER LO"),
+            setup("WAVEFORM_SETUP SP,0,NP,0,FP,0,SN,0"),
+            Exchange::QueryRaw {
+                command: "C1:WAVEFORM? DESC".into(),
+                response: Some(block.iter().map(|b| format!("{:02x}", b)).collect()),
+                error: None,
+            },
+        ]));
+
+        let result = subsystem(replay.clone()).read_raw_waveform_chunked("C1", &ChunkOptions::new(), |_| {});
+        assert!(matches!(result, Err(MauiError::InvalidWaveform(_))));
+        replay.finish().unwrap();
     }
 }
@@ -0,0 +1,249 @@
+//! ## Transport Module
+//!
+//! Abstraction over the link between the controller and the oscilloscope.
+//!
+
+use crate::utils::parse_response;
+use crate::{MauiError, Result};
+use instrument_ctl::Instrument;
+use std::net::Ipv6Addr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The MSS/RQS bit of the status byte.
+const STB_REQUEST_SERVICE: u8 = 1 << 6;
+
+/// ## Transport
+///
+/// Trait implemented by every connection that can carry remote commands to a
+/// MAUI oscilloscope. All subsystems talk to the device through an
+/// `Arc<dyn Transport>`, which makes it possible to swap the physical
+/// connection for a simulated or recorded one.
+///
+pub trait Transport: Send + Sync {
+    /// ## Command
+    ///
+    /// Send a command to the device.
+    ///
+    fn command(&self, cmd: &str) -> Result<()>;
+
+    /// ## Command Raw
+    ///
+    /// Send a command holding binary data, such as a definite length block, to
+    /// the device.
+    ///
+    /// Transports that can send arbitrary bytes override this. The default
+    /// implementation sends the command as text, and fails with
+    /// [`MauiError::Unsupported`] if it is not valid UTF-8.
+    ///
+    fn command_raw(&self, cmd: &[u8]) -> Result<()> {
+        match std::str::from_utf8(cmd) {
+            Ok(cmd) => self.command(cmd),
+            Err(_) => Err(MauiError::Unsupported(
+                "binary commands over this transport".into(),
+            )),
+        }
+    }
+
+    /// ## Query
+    ///
+    /// Send a command to the device and return the response as a string.
+    ///
+    fn query(&self, cmd: &str) -> Result<String>;
+
+    /// ## Query Raw
+    ///
+    /// Send a command to the device and return the response as a vector of bytes.
+    ///
+    fn query_raw(&self, cmd: &str) -> Result<Vec<u8>>;
+
+    /// ## Query Raw With Progress
+    ///
+    /// Send a command to the device and return the response as a vector of
+    /// bytes, calling `progress` with the number of bytes of the response
+    /// block received so far and the length announced by its header.
+    ///
+    /// Transports that receive responses in pieces override this. The default
+    /// implementation reports the progress once, after the whole response was
+    /// received.
+    ///
+    fn query_raw_with_progress(
+        &self,
+        cmd: &str,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<Vec<u8>> {
+        let resp = self.query_raw(cmd)?;
+        if let Some((received, total)) = block_progress(&resp) {
+            progress(received, total);
+        }
+        Ok(resp)
+    }
+
+    /// ## Command Raw With Progress
+    ///
+    /// Send a command holding binary data in pieces of at most `chunk_size`
+    /// bytes, calling `progress` with the number of bytes sent so far and the
+    /// length of the command.
+    ///
+    /// Transports that can send a message in pieces override this. The default
+    /// implementation sends the command with [`Transport::command_raw`] and
+    /// reports the progress once.
+    ///
+    fn command_raw_with_progress(
+        &self,
+        cmd: &[u8],
+        _chunk_size: usize,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        self.command_raw(cmd)?;
+        progress(cmd.len(), cmd.len());
+        Ok(())
+    }
+
+    /// ## Set Timeout
+    ///
+    /// Set a new timeout duration for the connection.
+    ///
+    fn set_timeout(&self, duration: Duration);
+
+    /// ## Clear
+    ///
+    /// Bring the connection back in sync after a failed transfer, discarding
+    /// any partial response, so that the transfer can be retried.
+    ///
+    /// Transports that can be left out of sync override this. The default
+    /// implementation does nothing.
+    ///
+    fn clear(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// ## Wait For SRQ
+    ///
+    /// Block until the device asserts a service request, or fail with
+    /// [`MauiError::Timeout`] once `timeout` has elapsed.
+    ///
+    /// Transports with a service request notification override this. The
+    /// default implementation polls the MSS bit of `*STB?` with a growing
+    /// interval, which does not clear the service request.
+    ///
+    fn wait_for_srq(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut interval = Duration::from_millis(1);
+        loop {
+            let stb = parse_response::<u8>(&self.query("*STB?")?)?;
+            if stb & STB_REQUEST_SERVICE != 0 {
+                return Ok(());
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(MauiError::Timeout(format!(
+                    "no service request within {:?}",
+                    timeout
+                )));
+            }
+            thread::sleep(interval.min(deadline - now));
+            interval = (interval * 2).min(Duration::from_millis(100));
+        }
+    }
+}
+
+/// `instrument_ctl` can only send text, so binary commands, such as the
+/// `TRANSFER_FILE` of a file that is not valid UTF-8, fail with
+/// [`MauiError::Unsupported`] over a VISA address. Connect with `vicp://`,
+/// `vxi11://` or `tcp://` to send them.
+///
+/// `instrument_ctl` does not expose VISA service request events either, so
+/// [`Transport::wait_for_srq`] falls back to polling `*STB?`.
+impl Transport for Instrument {
+    fn command(&self, cmd: &str) -> Result<()> {
+        Instrument::command(self, cmd).map_err(instrument_error)
+    }
+
+    fn command_raw(&self, cmd: &[u8]) -> Result<()> {
+        match std::str::from_utf8(cmd) {
+            Ok(cmd) => Instrument::command(self, cmd).map_err(instrument_error),
+            Err(_) => Err(MauiError::Unsupported(
+                "binary commands over VISA, connect with vicp://, vxi11:// or tcp:// instead"
+                    .into(),
+            )),
+        }
+    }
+
+    fn query(&self, cmd: &str) -> Result<String> {
+        Instrument::query(self, cmd).map_err(instrument_error)
+    }
+
+    fn query_raw(&self, cmd: &str) -> Result<Vec<u8>> {
+        Instrument::query_raw(self, cmd).map_err(instrument_error)
+    }
+
+    fn set_timeout(&self, duration: Duration) {
+        Instrument::set_timeout(self, duration);
+    }
+}
+
+/// Sort the untyped errors of `instrument_ctl` into timeouts and other
+/// transport failures.
+fn instrument_error(e: anyhow::Error) -> MauiError {
+    let message = format!("{:#}", e);
+    if message.to_lowercase().contains("timed out") || message.to_lowercase().contains("timeout") {
+        MauiError::Timeout(message)
+    } else {
+        MauiError::Transport(message)
+    }
+}
+
+/// Convert a raw response into a string, keeping the lossy text in the error
+/// if it is not valid UTF-8.
+pub(crate) fn response_to_string(resp: Vec<u8>) -> Result<String> {
+    String::from_utf8(resp).map_err(|e| {
+        let response = String::from_utf8_lossy(e.as_bytes()).to_string();
+        MauiError::parse(response, e.utf8_error())
+    })
+}
+
+/// Return the number of data bytes received and the length announced by the
+/// definite length block header of a partial response, once the header has
+/// arrived.
+pub(crate) fn block_progress(response: &[u8]) -> Option<(usize, usize)> {
+    let start = response.iter().position(|&b| b == b'#')?;
+    let digits = (*response.get(start + 1)? as char)
+        .to_digit(10)
+        .filter(|d| *d > 0)? as usize;
+    let length = std::str::from_utf8(response.get(start + 2..start + 2 + digits)?)
+        .ok()?
+        .parse::<usize>()
+        .ok()?;
+    let received = response.len().saturating_sub(start + 2 + digits);
+    Some((received.min(length), length))
+}
+
+/// Append `default_port` to a host that does not specify a port. IPv6
+/// literals are accepted bare or in brackets, as in `[::1]:1861`.
+pub(crate) fn socket_address(host: &str, default_port: u16) -> String {
+    if host.parse::<Ipv6Addr>().is_ok() {
+        return format!("[{}]:{}", host, default_port);
+    }
+    match host.rsplit_once(':') {
+        Some((_, port)) if !port.ends_with(']') => host.to_string(),
+        _ => format!("{}:{}", host, default_port),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socket_address_appends_default_port() {
+        assert_eq!(socket_address("scope", 1861), "scope:1861");
+        assert_eq!(socket_address("10.0.0.2", 1861), "10.0.0.2:1861");
+        assert_eq!(socket_address("10.0.0.2:5025", 1861), "10.0.0.2:5025");
+        assert_eq!(socket_address("::1", 1861), "[::1]:1861");
+        assert_eq!(socket_address("fe80::1:2", 1861), "[fe80::1:2]:1861");
+        assert_eq!(socket_address("[::1]", 1861), "[::1]:1861");
+        assert_eq!(socket_address("[::1]:5025", 1861), "[::1]:5025");
+    }
+}
@@ -0,0 +1,602 @@
+//! ## Record and Replay Transports
+//!
+//! A pair of transports used for regression testing. The recording transport
+//! wraps a live connection and writes every exchange to a session file. The
+//! replay transport reads that file back and serves the recorded responses,
+//! failing as soon as the driver sends something different from what was
+//! recorded.
+//!
+
+use crate::{MauiError, Result, Transport};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// ## Exchange
+///
+/// A single interaction with the device, as stored in a session file. Each
+/// exchange is written as one line of JSON. Raw responses are hex encoded so
+/// that binary data survives the round trip.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Exchange {
+    Command {
+        command: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<RecordedError>,
+    },
+    Query {
+        command: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        response: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<RecordedError>,
+    },
+    QueryRaw {
+        command: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        response: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<RecordedError>,
+    },
+    CommandRaw {
+        /// The hex encoded command.
+        command: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<RecordedError>,
+    },
+    SetTimeout {
+        millis: u64,
+    },
+    WaitForSrq {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<RecordedError>,
+    },
+    Clear {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<RecordedError>,
+    },
+}
+
+/// ## Recorded Error
+///
+/// A failure stored in a session file. Timeouts are kept apart from other
+/// failures so that they are replayed as timeouts.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedError {
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub timeout: bool,
+    pub message: String,
+}
+
+impl From<&MauiError> for RecordedError {
+    fn from(e: &MauiError) -> Self {
+        match e {
+            MauiError::Timeout(message) => RecordedError {
+                timeout: true,
+                message: message.clone(),
+            },
+            MauiError::Transport(message) => RecordedError {
+                timeout: false,
+                message: message.clone(),
+            },
+            e => RecordedError {
+                timeout: false,
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+impl From<RecordedError> for MauiError {
+    fn from(e: RecordedError) -> Self {
+        if e.timeout {
+            MauiError::Timeout(e.message)
+        } else {
+            MauiError::Transport(e.message)
+        }
+    }
+}
+
+impl Exchange {
+    /// Describe the exchange for divergence reports.
+    fn describe(&self) -> String {
+        match self {
+            Exchange::Command { command, .. } => format!("command '{}'", command),
+            Exchange::Query { command, .. } => format!("query '{}'", command),
+            Exchange::QueryRaw { command, .. } => format!("raw query '{}'", command),
+            Exchange::CommandRaw { command, .. } => {
+                format!("raw command of {} bytes", command.len() / 2)
+            }
+            Exchange::SetTimeout { millis } => format!("timeout of {} ms", millis),
+            Exchange::WaitForSrq { .. } => "wait for service request".to_string(),
+            Exchange::Clear { .. } => "clear".to_string(),
+        }
+    }
+}
+
+/// ## Recording Transport
+///
+/// Wraps another transport and appends every exchange to a session file.
+/// The file is flushed after each exchange, so a session interrupted by a
+/// crash is still usable up to that point.
+///
+pub struct RecordingTransport {
+    inner: Arc<dyn Transport>,
+    writer: Mutex<BufWriter<std::fs::File>>,
+}
+
+impl RecordingTransport {
+    /// ## Create
+    ///
+    /// Start recording the exchanges made over `inner` into a new session file.
+    ///
+    pub fn create(inner: Arc<dyn Transport>, filepath: &str) -> Result<RecordingTransport> {
+        let file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(filepath)?;
+
+        Ok(RecordingTransport {
+            inner,
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    /// Append an exchange to the session file.
+    fn record(&self, exchange: &Exchange) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        serde_json::to_writer(&mut *writer, exchange)
+            .map_err(|e| MauiError::Transport(format!("could not record exchange: {}", e)))?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn command(&self, cmd: &str) -> Result<()> {
+        let result = self.inner.command(cmd);
+        self.record(&Exchange::Command {
+            command: normalize(cmd),
+            error: result.as_ref().err().map(RecordedError::from),
+        })?;
+        result
+    }
+
+    fn command_raw(&self, cmd: &[u8]) -> Result<()> {
+        let result = self.inner.command_raw(cmd);
+        self.record(&Exchange::CommandRaw {
+            command: to_hex(cmd),
+            error: result.as_ref().err().map(RecordedError::from),
+        })?;
+        result
+    }
+
+    fn query(&self, cmd: &str) -> Result<String> {
+        let result = self.inner.query(cmd);
+        let (response, error) = match &result {
+            Ok(resp) => (Some(resp.clone()), None),
+            Err(e) => (None, Some(RecordedError::from(e))),
+        };
+        self.record(&Exchange::Query {
+            command: normalize(cmd),
+            response,
+            error,
+        })?;
+        result
+    }
+
+    fn query_raw(&self, cmd: &str) -> Result<Vec<u8>> {
+        let result = self.inner.query_raw(cmd);
+        let (response, error) = match &result {
+            Ok(resp) => (Some(to_hex(resp)), None),
+            Err(e) => (None, Some(RecordedError::from(e))),
+        };
+        self.record(&Exchange::QueryRaw {
+            command: normalize(cmd),
+            response,
+            error,
+        })?;
+        result
+    }
+
+    fn set_timeout(&self, duration: Duration) {
+        self.inner.set_timeout(duration);
+        // the trait offers no way to report a failure here, the next
+        // exchange will surface any problem with the session file
+        let _ = self.record(&Exchange::SetTimeout {
+            millis: duration.as_millis() as u64,
+        });
+    }
+
+    fn wait_for_srq(&self, timeout: Duration) -> Result<()> {
+        let result = self.inner.wait_for_srq(timeout);
+        self.record(&Exchange::WaitForSrq {
+            error: result.as_ref().err().map(RecordedError::from),
+        })?;
+        result
+    }
+
+    fn query_raw_with_progress(
+        &self,
+        cmd: &str,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<Vec<u8>> {
+        let result = self.inner.query_raw_with_progress(cmd, progress);
+        let (response, error) = match &result {
+            Ok(resp) => (Some(to_hex(resp)), None),
+            Err(e) => (None, Some(RecordedError::from(e))),
+        };
+        self.record(&Exchange::QueryRaw {
+            command: normalize(cmd),
+            response,
+            error,
+        })?;
+        result
+    }
+
+    fn command_raw_with_progress(
+        &self,
+        cmd: &[u8],
+        chunk_size: usize,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        let result = self
+            .inner
+            .command_raw_with_progress(cmd, chunk_size, progress);
+        self.record(&Exchange::CommandRaw {
+            command: to_hex(cmd),
+            error: result.as_ref().err().map(RecordedError::from),
+        })?;
+        result
+    }
+
+    fn clear(&self) -> Result<()> {
+        let result = self.inner.clear();
+        self.record(&Exchange::Clear {
+            error: result.as_ref().err().map(RecordedError::from),
+        })?;
+        result
+    }
+}
+
+/// The position of a replay within its session.
+struct ReplayState {
+    exchanges: Vec<Exchange>,
+    position: usize,
+    divergence: Option<String>,
+}
+
+/// ## Replay Transport
+///
+/// Serves the responses of a recorded session in order. Every command and
+/// query must match the recorded one exactly; the first mismatch is returned
+/// as an error and every later exchange fails with the same message. Call
+/// [`ReplayTransport::finish`] at the end of a test to also catch sessions
+/// that were not fully consumed.
+///
+pub struct ReplayTransport {
+    state: Mutex<ReplayState>,
+}
+
+impl ReplayTransport {
+    /// ## Open
+    ///
+    /// Load a session file written by a [`RecordingTransport`].
+    ///
+    pub fn open(filepath: &str) -> Result<ReplayTransport> {
+        let file = OpenOptions::new().read(true).open(filepath)?;
+        let mut exchanges = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let exchange: Exchange = serde_json::from_str(&line).map_err(|e| {
+                MauiError::parse(
+                    line.clone(),
+                    format!("line {} of '{}': {}", number + 1, filepath, e),
+                )
+            })?;
+            exchanges.push(exchange);
+        }
+
+        Ok(Self::from_exchanges(exchanges))
+    }
+
+    /// ## From Exchanges
+    ///
+    /// Build a replay from exchanges held in memory.
+    ///
+    pub fn from_exchanges(exchanges: Vec<Exchange>) -> ReplayTransport {
+        ReplayTransport {
+            state: Mutex::new(ReplayState {
+                exchanges,
+                position: 0,
+                divergence: None,
+            }),
+        }
+    }
+
+    /// ## Remaining
+    ///
+    /// Return the number of recorded exchanges not yet replayed.
+    ///
+    pub fn remaining(&self) -> usize {
+        let state = self.lock();
+        state.exchanges.len() - state.position
+    }
+
+    /// ## Finish
+    ///
+    /// Check that the replay ran to completion without diverging from the
+    /// recorded session.
+    ///
+    pub fn finish(&self) -> Result<()> {
+        let state = self.lock();
+        if let Some(divergence) = &state.divergence {
+            return Err(MauiError::Transport(divergence.clone()));
+        }
+        if state.position < state.exchanges.len() {
+            return Err(MauiError::Transport(format!(
+                "session ended early: {} of {} exchanges replayed, next expected {}",
+                state.position,
+                state.exchanges.len(),
+                state.exchanges[state.position].describe()
+            )));
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ReplayState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Pop the next recorded exchange, checking that it matches `actual`.
+    fn next(&self, actual: &Exchange) -> Result<Exchange> {
+        let mut state = self.lock();
+        if let Some(divergence) = &state.divergence {
+            return Err(MauiError::Transport(divergence.clone()));
+        }
+
+        let position = state.position;
+        let expected = match state.exchanges.get(position) {
+            Some(expected) => expected.clone(),
+            None => {
+                let divergence = format!(
+                    "session diverged at exchange {}: expected end of session, got {}",
+                    position + 1,
+                    actual.describe()
+                );
+                state.divergence = Some(divergence.clone());
+                return Err(MauiError::Transport(divergence));
+            }
+        };
+
+        let matches = match (&expected, actual) {
+            (Exchange::Command { command: a, .. }, Exchange::Command { command: b, .. }) => a == b,
+            (Exchange::Query { command: a, .. }, Exchange::Query { command: b, .. }) => a == b,
+            (Exchange::QueryRaw { command: a, .. }, Exchange::QueryRaw { command: b, .. }) => {
+                a == b
+            }
+            (Exchange::CommandRaw { command: a, .. }, Exchange::CommandRaw { command: b, .. }) => {
+                a == b
+            }
+            (Exchange::SetTimeout { millis: a }, Exchange::SetTimeout { millis: b }) => a == b,
+            // the timeout of a wait depends on timing, only its place in the session matters
+            (Exchange::WaitForSrq { .. }, Exchange::WaitForSrq { .. }) => true,
+            (Exchange::Clear { .. }, Exchange::Clear { .. }) => true,
+            _ => false,
+        };
+        if !matches {
+            let divergence = format!(
+                "session diverged at exchange {}: expected {}, got {}",
+                position + 1,
+                expected.describe(),
+                actual.describe()
+            );
+            state.divergence = Some(divergence.clone());
+            return Err(MauiError::Transport(divergence));
+        }
+
+        state.position += 1;
+        Ok(expected)
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn command(&self, cmd: &str) -> Result<()> {
+        let actual = Exchange::Command {
+            command: normalize(cmd),
+            error: None,
+        };
+        match self.next(&actual)? {
+            Exchange::Command { error: Some(e), .. } => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn command_raw(&self, cmd: &[u8]) -> Result<()> {
+        let actual = Exchange::CommandRaw {
+            command: to_hex(cmd),
+            error: None,
+        };
+        match self.next(&actual)? {
+            Exchange::CommandRaw { error: Some(e), .. } => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn query(&self, cmd: &str) -> Result<String> {
+        let actual = Exchange::Query {
+            command: normalize(cmd),
+            response: None,
+            error: None,
+        };
+        match self.next(&actual)? {
+            Exchange::Query {
+                response: Some(resp),
+                ..
+            } => Ok(resp),
+            Exchange::Query { error: Some(e), .. } => Err(e.into()),
+            _ => Err(MauiError::Transport(format!(
+                "recorded query '{}' has no response",
+                cmd.trim()
+            ))),
+        }
+    }
+
+    fn query_raw(&self, cmd: &str) -> Result<Vec<u8>> {
+        let actual = Exchange::QueryRaw {
+            command: normalize(cmd),
+            response: None,
+            error: None,
+        };
+        match self.next(&actual)? {
+            Exchange::QueryRaw {
+                response: Some(resp),
+                ..
+            } => from_hex(&resp),
+            Exchange::QueryRaw { error: Some(e), .. } => Err(e.into()),
+            _ => Err(MauiError::Transport(format!(
+                "recorded query '{}' has no response",
+                cmd.trim()
+            ))),
+        }
+    }
+
+    fn set_timeout(&self, duration: Duration) {
+        // a divergence here is reported by the next exchange or by `finish`
+        let _ = self.next(&Exchange::SetTimeout {
+            millis: duration.as_millis() as u64,
+        });
+    }
+
+    fn wait_for_srq(&self, _timeout: Duration) -> Result<()> {
+        match self.next(&Exchange::WaitForSrq { error: None })? {
+            Exchange::WaitForSrq { error: Some(e) } => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn clear(&self) -> Result<()> {
+        match self.next(&Exchange::Clear { error: None })? {
+            Exchange::Clear { error: Some(e) } => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Strip the line terminator that some callers append to commands.
+fn normalize(cmd: &str) -> String {
+    cmd.trim_end_matches(['\n', '\r']).to_string()
+}
+
+/// Encode bytes as a lowercase hex string.
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode a hex string into bytes.
+fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(MauiError::Transport(
+            "recorded raw response has an odd number of hex digits".into(),
+        ));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| MauiError::Transport("recorded raw response is not valid hex".into()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::waveform::Waveform;
+    use crate::transports::simulated::SimulatedScope;
+    use crate::{MauiOscilloscope, WaitOptions};
+
+    /// Drive a scope through a short session, returning what it read.
+    fn session(scope: &MauiOscilloscope) -> Result<(f32, Waveform, Vec<u8>)> {
+        scope.set_strict(true)?;
+        scope.acquisition.set_volt_div(1, 0.2)?;
+        scope
+            .acquisition
+            .arm_and_wait_for_acquisition(&WaitOptions::timeout(Duration::from_secs(5)))?;
+        let waveform = scope.waveform.read_waveform("C1")?;
+        scope
+            .storage
+            .write_file_on_device("D:\\data.bin", &[0, 1, 2, 255])?;
+        let data = scope.storage.read_file_on_device("D:\\data.bin")?;
+        Ok((scope.acquisition.get_volt_div(1)?, waveform, data))
+    }
+
+    #[test]
+    fn replay_reproduces_recorded_session() {
+        let filepath =
+            std::env::temp_dir().join(format!("rs-maui-session-{}.jsonl", std::process::id()));
+        let filepath = filepath.to_str().unwrap();
+
+        let recording = RecordingTransport::create(Arc::new(SimulatedScope::new()), filepath);
+        let recorded =
+            session(&MauiOscilloscope::with_transport(Arc::new(recording.unwrap())).unwrap());
+
+        let replay = ReplayTransport::open(filepath);
+        std::fs::remove_file(filepath).unwrap();
+        let replay = Arc::new(replay.unwrap());
+        let replayed = session(&MauiOscilloscope::with_transport(replay.clone()).unwrap());
+
+        let (volt_div, waveform, data) = recorded.unwrap();
+        let (replayed_volt_div, replayed_waveform, replayed_data) = replayed.unwrap();
+        assert_eq!(volt_div, replayed_volt_div);
+        assert_eq!(waveform.to_bytes(), replayed_waveform.to_bytes());
+        assert_eq!(data, replayed_data);
+        replay.finish().unwrap();
+    }
+
+    #[test]
+    fn mismatched_command_is_reported() {
+        let replay = ReplayTransport::from_exchanges(vec![
+            Exchange::Command {
+                command: "TDIV 1E-3".into(),
+                error: None,
+            },
+            Exchange::Query {
+                command: "TDIV?".into(),
+                response: Some("1E-3".into()),
+                error: None,
+            },
+        ]);
+
+        let error = replay.command("TDIV 2E-3").unwrap_err().to_string();
+        assert!(error.contains("exchange 1"), "{}", error);
+        assert!(
+            error.contains("expected command 'TDIV 1E-3', got command 'TDIV 2E-3'"),
+            "{}",
+            error
+        );
+
+        // the divergence sticks for every later exchange
+        assert_eq!(replay.query("TDIV?").unwrap_err().to_string(), error);
+        assert_eq!(replay.finish().unwrap_err().to_string(), error);
+    }
+
+    #[test]
+    fn unfinished_session_is_reported() {
+        let replay = ReplayTransport::from_exchanges(vec![Exchange::SetTimeout { millis: 1000 }]);
+        assert_eq!(replay.remaining(), 1);
+        assert!(replay.finish().is_err());
+        replay.set_timeout(Duration::from_secs(1));
+        assert_eq!(replay.remaining(), 0);
+        replay.finish().unwrap();
+    }
+}
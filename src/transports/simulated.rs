@@ -0,0 +1,1840 @@
+//! ## Simulated Transport
+//!
+//! An in-process stand-in for a MAUI oscilloscope. It understands the remote
+//! commands emitted by this crate and keeps a consistent panel state, a virtual
+//! filesystem and a trigger state machine, so the driver can be exercised
+//! without any hardware.
+//!
+
+use crate::data::wavedesc::{ByteOrder, CommType, Timebase, WaveDesc, WaveSource};
+use crate::transport::response_to_string;
+use crate::utils::crc32;
+use crate::{MauiError, Result, Transport};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The bandwidth limit keywords accepted by the simulated channels.
+const BANDWIDTH_LIMITS: [&str; 9] = [
+    "OFF", "20MHZ", "200MHZ", "500MHZ", "1GHZ", "2GHZ", "3GHZ", "4GHZ", "6GHZ",
+];
+
+/// The traces that can be selected for autosave.
+const AUTOSAVE_TRACES: [&str; 9] = [
+    "C1",
+    "C2",
+    "C3",
+    "C4",
+    "F1",
+    "F2",
+    "F3",
+    "F4",
+    "ALL_DISPLAYED",
+];
+
+/// The directories present on a freshly booted simulated scope.
+const DEFAULT_DIRECTORIES: [&str; 4] = ["D:", "D:\\WAVEFORMS", "D:\\SETUPS", "D:\\HARDCOPY"];
+
+/// The capacity of the virtual disk, in bytes.
+const DISK_CAPACITY: u64 = 256 * 1024 * 1024 * 1024;
+
+/// The month abbreviations used in directory listings.
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// The number of points in a simulated acquisition.
+const ACQUISITION_POINTS: usize = 1000;
+
+/// The frequency of the sine wave present on the inputs.
+const SIGNAL_FREQUENCY: f64 = 1e6;
+
+/// The time between the triggers of two segments in sequence mode.
+const SEGMENT_PERIOD: f64 = 1e-3;
+
+/// The identification string of the simulated scope.
+const IDENTITY: &str = "LECROY,WAVERUNNER-SIM,SIM000001,9.0.0";
+
+// Error codes reported through the CMR and EXR registers.
+const CMR_UNRECOGNIZED_HEADER: u8 = 1;
+const CMR_ILLEGAL_NUMBER: u8 = 3;
+const CMR_UNRECOGNIZED_KEYWORD: u8 = 5;
+const CMR_STRING_ERROR: u8 = 6;
+const CMR_BLOCK_EXPECTED: u8 = 10;
+const EXR_PARAMETER_MISSING: u8 = 27;
+const EXR_HEX_DATA: u8 = 30;
+const EXR_FILE_NOT_FOUND: u8 = 58;
+
+// Bits of the standard event status register.
+const ESR_OPC: u8 = 1 << 0;
+const ESR_EXE: u8 = 1 << 4;
+const ESR_CME: u8 = 1 << 5;
+
+// Bits of the internal state change register.
+const INR_NEW_SIGNAL: u16 = 1 << 0;
+const INR_SCREEN_DUMP: u16 = 1 << 1;
+const INR_SEGMENT_ACQUIRED: u16 = 1 << 4;
+const INR_STORAGE_FULL: u16 = 1 << 7;
+
+/// ## Trigger Mode
+///
+/// The trigger modes of the simulated scope.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    Auto,
+    Normal,
+    Single,
+    Stop,
+}
+
+impl TriggerMode {
+    fn keyword(&self) -> &'static str {
+        match self {
+            TriggerMode::Auto => "AUTO",
+            TriggerMode::Normal => "NORM",
+            TriggerMode::Single => "SINGLE",
+            TriggerMode::Stop => "STOP",
+        }
+    }
+}
+
+/// The vertical settings of a single channel.
+#[derive(Debug, Clone)]
+struct Channel {
+    volt_div: f64,
+    offset: f64,
+    attenuation: u16,
+    bandwidth_limit: String,
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Channel {
+            volt_div: 0.05,
+            offset: 0.0,
+            attenuation: 1,
+            bandwidth_limit: "OFF".into(),
+        }
+    }
+}
+
+/// The autosave configuration set with STORE_SETUP.
+#[derive(Debug, Clone)]
+struct Autosave {
+    trace: String,
+    mode: String,
+    format: String,
+    index: usize,
+}
+
+impl Default for Autosave {
+    fn default() -> Self {
+        Autosave {
+            trace: "C1".into(),
+            mode: "OFF".into(),
+            format: "BINARY".into(),
+            index: 0,
+        }
+    }
+}
+
+/// The waveform transfer format set with COMM_FORMAT, COMM_ORDER and
+/// WAVEFORM_SETUP.
+#[derive(Debug, Clone, Default)]
+struct Transfer {
+    word: bool,
+    little_endian: bool,
+    sparsing: usize,
+    points: usize,
+    first_point: usize,
+    segment: usize,
+}
+
+/// The sequence mode configuration set with SEQUENCE.
+#[derive(Debug, Clone)]
+struct Sequence {
+    enabled: bool,
+    segments: usize,
+    max_samples: usize,
+}
+
+impl Default for Sequence {
+    fn default() -> Self {
+        Sequence {
+            enabled: false,
+            segments: 10,
+            max_samples: ACQUISITION_POINTS,
+        }
+    }
+}
+
+/// A file stored on the virtual disk.
+#[derive(Debug, Clone)]
+struct VirtualFile {
+    path: String,
+    data: Vec<u8>,
+    modified: SystemTime,
+}
+
+/// A directory stored on the virtual disk.
+#[derive(Debug, Clone)]
+struct VirtualDirectory {
+    path: String,
+    modified: SystemTime,
+}
+
+/// The complete internal state of the simulated scope.
+#[derive(Debug)]
+struct State {
+    // status registers
+    ese: u8,
+    esr: u8,
+    sre: u8,
+    ine: u16,
+    inr: u16,
+    cmr: u8,
+    exr: u8,
+    ddr: u16,
+    vab: bool,
+
+    // panel
+    headers: String,
+    time_div: f64,
+    channels: [Channel; 4],
+    sample_clock: String,
+    reference_clock: String,
+    log_level: String,
+    remote_log: String,
+    hardcopy: String,
+
+    // trigger state machine
+    trigger_mode: TriggerMode,
+    armed: bool,
+    signal_present: bool,
+    acquisitions: u64,
+    sequence: Sequence,
+    transfer: Transfer,
+
+    // storage
+    autosave: Autosave,
+    autosave_capacity: usize,
+    files: BTreeMap<String, VirtualFile>,
+    directories: BTreeMap<String, VirtualDirectory>,
+    vbs: BTreeMap<String, String>,
+
+    // connection
+    timeout: Duration,
+    history: Vec<String>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            ese: 0,
+            esr: 0,
+            sre: 0,
+            ine: 0,
+            inr: 0,
+            cmr: 0,
+            exr: 0,
+            ddr: 0,
+            vab: false,
+            headers: "SHORT".into(),
+            time_div: 1e-6,
+            channels: Default::default(),
+            sample_clock: "INTERNAL".into(),
+            reference_clock: "INTERNAL".into(),
+            log_level: "OFF".into(),
+            remote_log: String::new(),
+            hardcopy: "DEV,JPEG,FORMAT,LANDSCAPE,BCKG,BLACK,DEST,REMOTE,AREA,FULLSCREEN".into(),
+            trigger_mode: TriggerMode::Auto,
+            armed: true,
+            signal_present: true,
+            acquisitions: 0,
+            sequence: Sequence::default(),
+            transfer: Transfer::default(),
+            autosave: Autosave::default(),
+            autosave_capacity: 1000,
+            files: BTreeMap::new(),
+            directories: DEFAULT_DIRECTORIES
+                .iter()
+                .map(|d| {
+                    let directory = VirtualDirectory {
+                        path: d.to_string(),
+                        modified: SystemTime::now(),
+                    };
+                    (d.to_string(), directory)
+                })
+                .collect(),
+            vbs: BTreeMap::new(),
+            timeout: Duration::from_secs(10),
+            history: Vec::new(),
+        }
+    }
+}
+
+/// ## Simulated Scope
+///
+/// A [`Transport`] that emulates a MAUI oscilloscope in memory.
+///
+/// The simulated scope keeps the panel settings, status registers, trigger
+/// state and a virtual disk consistent across commands. Device side errors are
+/// reported the same way as on a real scope: the command is ignored and the
+/// CMR or EXR register is set along with the matching ESR bit. Queries the
+/// scope does not understand produce no response, which surfaces as an error.
+///
+pub struct SimulatedScope {
+    state: Mutex<State>,
+}
+
+impl Default for SimulatedScope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulatedScope {
+    /// ## New
+    ///
+    /// Create a simulated scope in its power-on state.
+    ///
+    pub fn new() -> SimulatedScope {
+        SimulatedScope {
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// ## Set Signal Present
+    ///
+    /// Select whether a triggerable signal is present on the inputs. When it is,
+    /// an armed scope acquires immediately. When it is not, the scope stays armed
+    /// until [`SimulatedScope::trigger`] or FORCE_TRIGGER is used.
+    ///
+    pub fn set_signal_present(&self, present: bool) {
+        self.lock().signal_present = present;
+    }
+
+    /// ## Trigger
+    ///
+    /// Inject a trigger event. Returns `true` if the scope was armed and made an
+    /// acquisition.
+    ///
+    pub fn trigger(&self) -> bool {
+        let mut state = self.lock();
+        if state.armed {
+            state.acquire();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// ## Acquisition Count
+    ///
+    /// Return the number of acquisitions made since power-on.
+    ///
+    pub fn acquisition_count(&self) -> u64 {
+        self.lock().acquisitions
+    }
+
+    /// ## Set Autosave Capacity
+    ///
+    /// Set the number of files the autosave can write before the FILL mode
+    /// turns itself off, or before the WRAP mode starts overwriting.
+    ///
+    pub fn set_autosave_capacity(&self, capacity: usize) {
+        self.lock().autosave_capacity = capacity.max(1);
+    }
+
+    /// ## Insert File
+    ///
+    /// Place a file on the virtual disk, creating its parent directories.
+    ///
+    pub fn insert_file(&self, path: &str, data: &[u8]) {
+        self.lock().write_file(path, data.to_vec());
+    }
+
+    /// ## File
+    ///
+    /// Return the contents of a file on the virtual disk.
+    ///
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        self.lock()
+            .files
+            .get(&normalize_path(path))
+            .map(|f| f.data.clone())
+    }
+
+    /// ## Files
+    ///
+    /// Return the paths of all the files on the virtual disk.
+    ///
+    pub fn files(&self) -> Vec<String> {
+        self.lock().files.values().map(|f| f.path.clone()).collect()
+    }
+
+    /// ## Directory Exists
+    ///
+    /// Return whether a directory exists on the virtual disk.
+    ///
+    pub fn directory_exists(&self, path: &str) -> bool {
+        self.lock().directories.contains_key(&normalize_path(path))
+    }
+
+    /// ## History
+    ///
+    /// Return every command and query received, in order.
+    ///
+    pub fn history(&self) -> Vec<String> {
+        self.lock().history.clone()
+    }
+
+    /// ## Timeout
+    ///
+    /// Return the timeout last set on the connection.
+    ///
+    pub fn timeout(&self) -> Duration {
+        self.lock().timeout
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Transport for SimulatedScope {
+    fn command(&self, cmd: &str) -> Result<()> {
+        let mut state = self.lock();
+        for message in split_messages(cmd) {
+            state.receive(&message);
+            state.execute(&message);
+        }
+        Ok(())
+    }
+
+    fn command_raw(&self, cmd: &[u8]) -> Result<()> {
+        if let Ok(cmd) = std::str::from_utf8(cmd) {
+            return self.command(cmd);
+        }
+
+        // only the header before the block is text
+        let (header, data) = match cmd.iter().position(|&b| b == b'#') {
+            Some(start) => cmd.split_at(start),
+            None => (cmd, &[][..]),
+        };
+        let header = String::from_utf8_lossy(header).to_string();
+        let mut state = self.lock();
+        state.receive(header.trim());
+        state.execute_block(header.trim(), data);
+        Ok(())
+    }
+
+    fn query(&self, cmd: &str) -> Result<String> {
+        let resp = response_to_string(self.query_raw(cmd)?)?;
+        Ok(resp)
+    }
+
+    fn query_raw(&self, cmd: &str) -> Result<Vec<u8>> {
+        let mut state = self.lock();
+        let mut response = None;
+        for message in split_messages(cmd) {
+            state.receive(&message);
+            if let Some(resp) = state.execute(&message) {
+                response = Some(resp);
+            }
+        }
+        response.ok_or_else(|| {
+            MauiError::Timeout(format!(
+                "simulated scope did not respond to '{}'",
+                cmd.trim()
+            ))
+        })
+    }
+
+    fn set_timeout(&self, duration: Duration) {
+        self.lock().timeout = duration;
+    }
+}
+
+impl State {
+    /// Record a message in the history and the remote log.
+    fn receive(&mut self, message: &str) {
+        self.history.push(message.to_string());
+        if self.log_level == "FD" {
+            self.remote_log.push_str(message);
+            self.remote_log.push('\n');
+        }
+    }
+
+    /// Execute a single message and return the response if it produced one.
+    fn execute(&mut self, message: &str) -> Option<Vec<u8>> {
+        let (header, args) = split_header(message);
+
+        // VBS is handled separately since its argument is free-form
+        if header.eq_ignore_ascii_case("VBS") || header.eq_ignore_ascii_case("VBS?") {
+            return self.vbs(args);
+        }
+
+        let (channel, name) = match header.split_once(':') {
+            Some((prefix, name)) => match parse_channel(prefix) {
+                Some(channel) => (Some(channel), name),
+                None => {
+                    self.command_error(CMR_UNRECOGNIZED_HEADER);
+                    return None;
+                }
+            },
+            None => (None, header),
+        };
+
+        let is_query = name.ends_with('?');
+        let name = name.trim_end_matches('?').to_ascii_uppercase();
+
+        match (canonical(&name), is_query) {
+            ("*IDN", true) => Some(IDENTITY.into()),
+            ("*OPC", true) => Some(b"1".to_vec()),
+            ("*OPC", false) => {
+                // every operation of the simulator completes immediately
+                self.esr |= ESR_OPC;
+                None
+            }
+            ("*RST", false) => {
+                let history = std::mem::take(&mut self.history);
+                let files = std::mem::take(&mut self.files);
+                let directories = std::mem::take(&mut self.directories);
+                *self = State {
+                    history,
+                    files,
+                    directories,
+                    ..State::default()
+                };
+                None
+            }
+            ("*CLS", false) => {
+                self.esr = 0;
+                self.inr = 0;
+                self.cmr = 0;
+                self.exr = 0;
+                self.ddr = 0;
+                self.vab = false;
+                None
+            }
+            ("*ESE", false) => self.set_register(args, |s, v| s.ese = v as u8),
+            ("*ESE", true) => Some(self.ese.to_string().into_bytes()),
+            ("*SRE", false) => self.set_register(args, |s, v| s.sre = v as u8),
+            ("*SRE", true) => Some(self.sre.to_string().into_bytes()),
+            ("INE", false) => self.set_register(args, |s, v| s.ine = v as u16),
+            ("INE", true) => Some(self.ine.to_string().into_bytes()),
+            ("*ESR", true) => Some(std::mem::take(&mut self.esr).to_string().into_bytes()),
+            ("INR", true) => Some(std::mem::take(&mut self.inr).to_string().into_bytes()),
+            ("CMR", true) => Some(std::mem::take(&mut self.cmr).to_string().into_bytes()),
+            ("EXR", true) => Some(std::mem::take(&mut self.exr).to_string().into_bytes()),
+            ("DDR", true) => Some(std::mem::take(&mut self.ddr).to_string().into_bytes()),
+            ("*STB", true) => Some(self.status_byte().to_string().into_bytes()),
+            ("CHDR", false) => {
+                match args.to_ascii_uppercase().as_str() {
+                    "OFF" | "SHORT" | "LONG" => self.headers = args.to_ascii_uppercase(),
+                    _ => self.command_error(CMR_UNRECOGNIZED_KEYWORD),
+                }
+                None
+            }
+            ("CHDR", true) => Some(self.headers.clone().into_bytes()),
+
+            // acquisition
+            ("ARM", false) => {
+                if self.trigger_mode == TriggerMode::Stop {
+                    self.trigger_mode = TriggerMode::Single;
+                }
+                self.arm();
+                None
+            }
+            ("FRTR", false) => {
+                if self.trigger_mode != TriggerMode::Stop {
+                    self.acquire();
+                }
+                None
+            }
+            ("*TRG", false) => {
+                self.acquire();
+                None
+            }
+            ("STOP", false) => {
+                self.trigger_mode = TriggerMode::Stop;
+                self.armed = false;
+                None
+            }
+            ("WAIT", false) => None,
+            ("ASET", false) => {
+                if let Some(channel) = channel {
+                    self.channels[channel].offset = 0.0;
+                }
+                self.armed = true;
+                None
+            }
+            ("TRMD", false) => {
+                let mode = match args.to_ascii_uppercase().as_str() {
+                    "AUTO" => TriggerMode::Auto,
+                    "NORM" | "NORMAL" => TriggerMode::Normal,
+                    "SINGLE" => TriggerMode::Single,
+                    "STOP" => TriggerMode::Stop,
+                    _ => {
+                        self.command_error(CMR_UNRECOGNIZED_KEYWORD);
+                        return None;
+                    }
+                };
+                self.trigger_mode = mode;
+                if mode == TriggerMode::Stop {
+                    self.armed = false;
+                } else {
+                    self.arm();
+                }
+                None
+            }
+            ("TRMD", true) => Some(self.trigger_mode.keyword().into()),
+            ("SEQ", false) => {
+                let fields: Vec<String> = args
+                    .split(',')
+                    .map(|f| f.trim().to_ascii_uppercase())
+                    .collect();
+                let segments = fields.get(1).map(|f| f.parse::<usize>().ok());
+                let max_samples = fields.get(2).map(|f| parse_number(f));
+                match (fields[0].as_str(), segments, max_samples) {
+                    ("OFF", _, _) => self.sequence.enabled = false,
+                    ("ON", Some(None), _) | ("ON", _, Some(None)) => {
+                        self.command_error(CMR_ILLEGAL_NUMBER)
+                    }
+                    ("ON", segments, max_samples) => {
+                        self.sequence.enabled = true;
+                        if let Some(Some(segments)) = segments {
+                            let clamped = segments.clamp(2, 10_000);
+                            self.vab |= clamped != segments;
+                            self.sequence.segments = clamped;
+                        }
+                        if let Some(Some(max_samples)) = max_samples {
+                            let clamped = (max_samples as usize).clamp(2, ACQUISITION_POINTS);
+                            self.vab |= clamped as f64 != max_samples;
+                            self.sequence.max_samples = clamped;
+                        }
+                    }
+                    _ => self.command_error(CMR_UNRECOGNIZED_KEYWORD),
+                }
+                None
+            }
+            ("SEQ", true) => {
+                let mode = if self.sequence.enabled { "ON" } else { "OFF" };
+                Some(
+                    format!(
+                        "{},{},{:E}",
+                        mode, self.sequence.segments, self.sequence.max_samples as f64
+                    )
+                    .into_bytes(),
+                )
+            }
+            ("TDIV", false) => {
+                match parse_number(args) {
+                    Some(value) => {
+                        let (value, adapted) = adapt_time_div(value);
+                        self.time_div = value;
+                        self.vab |= adapted;
+                    }
+                    None => self.command_error(CMR_ILLEGAL_NUMBER),
+                }
+                None
+            }
+            ("TDIV", true) => Some(format_number(self.time_div)),
+            ("SCLK", false) => self.set_clock(args, |s, v| s.sample_clock = v),
+            ("SCLK", true) => Some(self.sample_clock.clone().into_bytes()),
+            ("RCLK", false) => self.set_clock(args, |s, v| s.reference_clock = v),
+            ("RCLK", true) => Some(self.reference_clock.clone().into_bytes()),
+
+            // channels
+            ("VDIV", _) | ("OFST", _) | ("ATTN", _) | ("BWL", _) => {
+                let Some(channel) = channel else {
+                    if canonical(&name) == "BWL" && !is_query {
+                        for channel in 0..4 {
+                            self.channel_command(channel, "BWL", args);
+                        }
+                    } else {
+                        self.execution_error(EXR_PARAMETER_MISSING);
+                    }
+                    return None;
+                };
+                if is_query {
+                    Some(self.channel_query(channel, canonical(&name)))
+                } else {
+                    self.channel_command(channel, canonical(&name), args);
+                    None
+                }
+            }
+
+            // communication
+            ("CHL", true) => {
+                let log = if args.eq_ignore_ascii_case("CLR") {
+                    std::mem::take(&mut self.remote_log)
+                } else {
+                    self.remote_log.clone()
+                };
+                Some(log.into_bytes())
+            }
+            ("CHLP", false) => {
+                let level = args.split(',').next().unwrap_or("").to_ascii_uppercase();
+                match level.as_str() {
+                    "OFF" | "EO" | "FD" => self.log_level = level,
+                    _ => self.command_error(CMR_UNRECOGNIZED_KEYWORD),
+                }
+                None
+            }
+            ("CHLP", true) => Some(format!("{},YES", self.log_level).into_bytes()),
+
+            // setup
+            ("PNSU", true) => {
+                let mut setup = self.panel_setup().into_bytes();
+                setup.extend_from_slice(b"ffffffff");
+                Some(block(&setup))
+            }
+            ("PNSU", false) => {
+                match parse_block(args.as_bytes()) {
+                    Some(setup) => {
+                        // the last eight bytes are the CRC
+                        let setup = &setup[..setup.len().saturating_sub(8)];
+                        let setup = String::from_utf8_lossy(setup).to_string();
+                        for line in setup.lines().filter(|l| !l.trim().is_empty()) {
+                            self.execute(line.trim());
+                        }
+                    }
+                    None => self.command_error(CMR_BLOCK_EXPECTED),
+                }
+                None
+            }
+
+            // storage
+            ("STST", false) => {
+                let fields: Vec<String> = args
+                    .split(',')
+                    .map(|f| f.trim().to_ascii_uppercase())
+                    .collect();
+                match fields.as_slice() {
+                    [key, mode] if key == "AUTO" => match mode.as_str() {
+                        "OFF" | "FILL" | "WRAP" => {
+                            self.autosave.mode = mode.clone();
+                            self.autosave.index = 0;
+                        }
+                        _ => self.command_error(CMR_UNRECOGNIZED_KEYWORD),
+                    },
+                    [key, format] if key == "FORMAT" => match format.as_str() {
+                        "ASCII" | "BINARY" | "EXCEL" | "MATHCAD" | "MATLAB" => {
+                            self.autosave.format = format.clone()
+                        }
+                        _ => self.command_error(CMR_UNRECOGNIZED_KEYWORD),
+                    },
+                    [trace, dest] if AUTOSAVE_TRACES.contains(&trace.as_str()) => {
+                        if dest == "HDD" {
+                            self.autosave.trace = trace.clone();
+                        } else {
+                            self.command_error(CMR_UNRECOGNIZED_KEYWORD);
+                        }
+                    }
+                    _ => self.command_error(CMR_UNRECOGNIZED_KEYWORD),
+                }
+                None
+            }
+            ("STST", true) => Some(
+                format!(
+                    "{},HDD,AUTO,{},FORMAT,{}",
+                    self.autosave.trace, self.autosave.mode, self.autosave.format
+                )
+                .into_bytes(),
+            ),
+            ("DELF", false) => {
+                match parse_file_args(args) {
+                    Some((path, _)) => {
+                        if self.files.remove(&normalize_path(&path)).is_none() {
+                            self.execution_error(EXR_FILE_NOT_FOUND);
+                        }
+                    }
+                    None => self.command_error(CMR_STRING_ERROR),
+                }
+                None
+            }
+            ("TRFL", false) => {
+                match parse_file_args(args) {
+                    Some((path, Some(data))) => self.receive_file(&path, data.as_bytes()),
+                    Some((_, None)) => self.execution_error(EXR_PARAMETER_MISSING),
+                    None => self.command_error(CMR_STRING_ERROR),
+                }
+                None
+            }
+            ("TRFL", true) => match parse_file_args(args) {
+                Some((path, _)) => match self.files.get(&normalize_path(&path)) {
+                    Some(file) => {
+                        let mut data = file.data.clone();
+                        data.extend_from_slice(format!("{:08x}", crc32(&file.data)).as_bytes());
+                        Some(block(&data))
+                    }
+                    None => {
+                        self.execution_error(EXR_FILE_NOT_FOUND);
+                        None
+                    }
+                },
+                None => {
+                    self.command_error(CMR_STRING_ERROR);
+                    None
+                }
+            },
+
+            ("DIR", true) => match parse_file_args(args) {
+                Some((path, _)) => Some(self.list_directory(&path)),
+                None => {
+                    self.command_error(CMR_STRING_ERROR);
+                    None
+                }
+            },
+
+            // waveform transfer
+            ("CFMT", false) => {
+                let fields: Vec<String> = args
+                    .split(',')
+                    .map(|f| f.trim().to_ascii_uppercase())
+                    .collect();
+                match fields.as_slice() {
+                    [block, size, encoding]
+                        if block == "DEF9" && encoding == "BIN" && (size == "BYTE" || size == "WORD") =>
+                    {
+                        self.transfer.word = size == "WORD";
+                    }
+                    _ => self.command_error(CMR_UNRECOGNIZED_KEYWORD),
+                }
+                None
+            }
+            ("CFMT", true) => {
+                let size = if self.transfer.word { "WORD" } else { "BYTE" };
+                Some(format!("DEF9,{},BIN", size).into_bytes())
+            }
+            ("CORD", false) => {
+                match args.trim().to_ascii_uppercase().as_str() {
+                    "LO" => self.transfer.little_endian = true,
+                    "HI" => self.transfer.little_endian = false,
+                    _ => self.command_error(CMR_UNRECOGNIZED_KEYWORD),
+                }
+                None
+            }
+            ("CORD", true) => {
+                let order = if self.transfer.little_endian { "LO" } else { "HI" };
+                Some(order.into())
+            }
+            ("WFSU", false) => {
+                let fields: Vec<&str> = args.split(',').map(|f| f.trim()).collect();
+                for pair in fields.chunks(2) {
+                    let value = pair.get(1).and_then(|v| v.parse::<usize>().ok());
+                    match (pair[0].to_ascii_uppercase().as_str(), value) {
+                        ("SP", Some(value)) => self.transfer.sparsing = value,
+                        ("NP", Some(value)) => self.transfer.points = value,
+                        ("FP", Some(value)) => self.transfer.first_point = value,
+                        ("SN", Some(value)) => self.transfer.segment = value,
+                        (_, None) => self.command_error(CMR_ILLEGAL_NUMBER),
+                        _ => self.command_error(CMR_UNRECOGNIZED_KEYWORD),
+                    }
+                }
+                None
+            }
+            ("WFSU", true) => Some(
+                format!(
+                    "SP,{},NP,{},FP,{},SN,{}",
+                    self.transfer.sparsing,
+                    self.transfer.points,
+                    self.transfer.first_point,
+                    self.transfer.segment
+                )
+                .into_bytes(),
+            ),
+            ("WF", true) => {
+                let Some(channel) = channel else {
+                    self.execution_error(EXR_PARAMETER_MISSING);
+                    return None;
+                };
+                let block_name = match args.trim().to_ascii_uppercase() {
+                    name if name.is_empty() => "ALL".to_string(),
+                    name => name,
+                };
+                let waveform = self.waveform(channel, &self.transfer);
+                match block_name.as_str() {
+                    "ALL" => Some(block(&waveform.concat())),
+                    "DESC" => Some(block(&waveform[0])),
+                    "TIME" => Some(block(&waveform[1])),
+                    "DAT1" => Some(block(&waveform[2])),
+                    _ => {
+                        self.command_error(CMR_UNRECOGNIZED_KEYWORD);
+                        None
+                    }
+                }
+            }
+
+            // hardcopy
+            ("HCSU", false) => {
+                self.hardcopy = args.trim().to_string();
+                None
+            }
+            ("HCSU", true) => Some(self.hardcopy.clone().into_bytes()),
+            ("SCDP", true) => {
+                self.inr |= INR_SCREEN_DUMP;
+                Some(screen_dump(&self.hardcopy))
+            }
+            ("SCDP", false) => {
+                // a dump to the remote port is dropped, as nobody queried it
+                let destination = hardcopy_field(&self.hardcopy, "DEST").unwrap_or_default();
+                if destination.eq_ignore_ascii_case("FILE") {
+                    let directory = hardcopy_field(&self.hardcopy, "DIR").unwrap_or_default();
+                    let file = hardcopy_field(&self.hardcopy, "FILE").unwrap_or_default();
+                    let format = hardcopy_field(&self.hardcopy, "DEV").unwrap_or("JPEG");
+                    let path = format!(
+                        "{}\\{}.{}",
+                        directory.trim_end_matches('\\'),
+                        file,
+                        format.to_ascii_lowercase()
+                    );
+                    let image = screen_dump(&self.hardcopy);
+                    self.write_file(&path, image);
+                }
+                self.inr |= INR_SCREEN_DUMP;
+                None
+            }
+
+            _ => {
+                self.command_error(CMR_UNRECOGNIZED_HEADER);
+                None
+            }
+        }
+    }
+
+    /// Handle a VBS command or query.
+    fn vbs(&mut self, args: &str) -> Option<Vec<u8>> {
+        let script = args.trim().trim_end_matches(';').trim();
+        let Some(script) = script
+            .strip_prefix('\'')
+            .and_then(|s| s.trim_end_matches(';').strip_suffix('\''))
+        else {
+            self.command_error(CMR_STRING_ERROR);
+            return None;
+        };
+        let script = script.trim().trim_end_matches(';').trim();
+
+        // queries
+        if let Some(expr) = script
+            .strip_prefix("Return=")
+            .or_else(|| script.strip_prefix("return="))
+        {
+            let value = self.vbs_get(expr.trim());
+            return Some(value.into_bytes());
+        }
+
+        // assignments
+        if let Some((key, value)) = script.split_once('=') {
+            let value = value.trim().trim_matches('"').to_string();
+            self.vbs_set(key.trim(), value);
+            return None;
+        }
+
+        // methods
+        match script.to_ascii_lowercase().as_str() {
+            "app.saverecall.utilities.createdir" => {
+                let directory = self.vbs_get("app.SaveRecall.Utilities.Directory");
+                self.create_directory(&directory);
+            }
+            "app.saverecall.utilities.deleteall" => {
+                let directory = normalize_path(&self.vbs_get("app.SaveRecall.Utilities.Directory"));
+                let prefix = format!("{}\\", directory);
+                self.files.retain(|key, _| {
+                    !key.starts_with(&prefix) || key[prefix.len()..].contains('\\')
+                });
+            }
+            _ => (),
+        }
+        None
+    }
+
+    /// Read an automation property. A few properties mirror the panel state.
+    fn vbs_get(&self, key: &str) -> String {
+        let lower = key.to_ascii_lowercase();
+        match lower.as_str() {
+            "app.acquisition.horizontal.horscale" => format!("{}", self.time_div),
+            "app.acquisition.triggermode" => match self.trigger_mode {
+                TriggerMode::Auto => "Auto".into(),
+                TriggerMode::Normal => "Normal".into(),
+                TriggerMode::Single => "Single".into(),
+                TriggerMode::Stop => "Stopped".into(),
+            },
+            _ => {
+                if let Some((channel, property)) = vbs_channel_property(&lower) {
+                    match property {
+                        "verscale" => return format!("{}", self.channels[channel].volt_div),
+                        "veroffset" => return format!("{}", self.channels[channel].offset),
+                        _ => (),
+                    }
+                }
+                self.vbs.get(&lower).cloned().unwrap_or_default()
+            }
+        }
+    }
+
+    /// Write an automation property. A few properties mirror the panel state.
+    fn vbs_set(&mut self, key: &str, value: String) {
+        let lower = key.to_ascii_lowercase();
+        match lower.as_str() {
+            "app.acquisition.horizontal.horscale" => {
+                if let Some(value) = parse_number(&value) {
+                    let (value, adapted) = adapt_time_div(value);
+                    self.time_div = value;
+                    self.vab |= adapted;
+                }
+            }
+            _ => {
+                if let Some((channel, property)) = vbs_channel_property(&lower) {
+                    match property {
+                        "verscale" => {
+                            return self.channel_command(channel, "VDIV", &value);
+                        }
+                        "veroffset" => {
+                            return self.channel_command(channel, "OFST", &value);
+                        }
+                        _ => (),
+                    }
+                }
+                self.vbs.insert(lower, value);
+            }
+        }
+    }
+
+    /// Apply a command to a single channel.
+    fn channel_command(&mut self, channel: usize, name: &str, args: &str) {
+        match name {
+            "VDIV" => match parse_number(args) {
+                Some(value) => {
+                    let attenuation = self.channels[channel].attenuation as f64;
+                    let (min, max) = (1e-3 * attenuation, 10.0 * attenuation);
+                    let clamped = value.clamp(min, max);
+                    self.vab |= clamped != value;
+                    self.channels[channel].volt_div = clamped;
+                }
+                None => self.command_error(CMR_ILLEGAL_NUMBER),
+            },
+            "OFST" => match parse_number(args) {
+                Some(value) => {
+                    let ch = &self.channels[channel];
+                    let range = if ch.volt_div / (ch.attenuation as f64) < 0.1 {
+                        1.0
+                    } else {
+                        10.0
+                    };
+                    let limit = range * ch.attenuation as f64;
+                    let clamped = value.clamp(-limit, limit);
+                    self.vab |= clamped != value;
+                    self.channels[channel].offset = clamped;
+                }
+                None => self.command_error(CMR_ILLEGAL_NUMBER),
+            },
+            "ATTN" => match args.trim().parse::<u16>() {
+                Ok(value) if (1..=10000).contains(&value) => {
+                    self.channels[channel].attenuation = value;
+                }
+                Ok(_) => self.vab = true,
+                Err(_) => self.command_error(CMR_ILLEGAL_NUMBER),
+            },
+            "BWL" => {
+                let value = args.trim().to_ascii_uppercase();
+                if BANDWIDTH_LIMITS.contains(&value.as_str()) {
+                    self.channels[channel].bandwidth_limit = value;
+                } else {
+                    self.command_error(CMR_UNRECOGNIZED_KEYWORD);
+                }
+            }
+            _ => self.command_error(CMR_UNRECOGNIZED_HEADER),
+        }
+    }
+
+    /// The voltage present on the input of a channel at time `t` relative to
+    /// the trigger of a segment.
+    fn signal(&self, channel: usize, segment: usize, t: f64) -> f64 {
+        if !self.signal_present {
+            return 0.0;
+        }
+        let amplitude = 0.1 * (channel + 1) as f64;
+        let phase = (self.acquisitions as f64 + segment as f64) * 0.5;
+        amplitude * (2.0 * std::f64::consts::PI * SIGNAL_FREQUENCY * t + phase).sin()
+    }
+
+    /// Build the descriptor, the TRIGTIME array and the wave array of a
+    /// channel, following the transfer format.
+    fn waveform(&self, channel: usize, transfer: &Transfer) -> [Vec<u8>; 3] {
+        let ch = &self.channels[channel];
+        let byte_order = if transfer.little_endian {
+            ByteOrder::LittleEndian
+        } else {
+            ByteOrder::BigEndian
+        };
+
+        // in sequence mode the screen is shared by the samples of one segment
+        let (segments, segment_points) = match self.sequence.enabled {
+            true => (
+                self.sequence.segments,
+                self.sequence.max_samples.min(ACQUISITION_POINTS),
+            ),
+            false => (1, ACQUISITION_POINTS),
+        };
+        let selected: Vec<usize> = match transfer.segment {
+            0 => (0..segments).collect(),
+            segment => (segment - 1..segment.min(segments)).collect(),
+        };
+
+        let interval = self.time_div * 10.0 / segment_points as f64;
+        let start = -5.0 * self.time_div;
+        let sparsing = transfer.sparsing.max(1);
+        let points = match transfer.points {
+            0 => usize::MAX,
+            points => points,
+        };
+
+        // 25 counts per division for bytes, 256 times more for words
+        let gain = if transfer.word {
+            ch.volt_div / 6400.0
+        } else {
+            ch.volt_div / 25.0
+        };
+
+        let mut samples = Vec::new();
+        let mut trigtime = Vec::new();
+        let mut count: usize = 0;
+        for &segment in &selected {
+            for value in [
+                segment as f64 * SEGMENT_PERIOD,
+                start + transfer.first_point as f64 * interval,
+            ] {
+                match byte_order {
+                    ByteOrder::BigEndian => trigtime.extend_from_slice(&value.to_be_bytes()),
+                    ByteOrder::LittleEndian => trigtime.extend_from_slice(&value.to_le_bytes()),
+                }
+            }
+
+            for index in (transfer.first_point..segment_points)
+                .step_by(sparsing)
+                .take(points)
+            {
+                let volts = self.signal(channel, segment, start + index as f64 * interval);
+                let raw = ((volts + ch.offset) / gain).round();
+                if transfer.word {
+                    let raw = raw.clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+                    match byte_order {
+                        ByteOrder::BigEndian => samples.extend_from_slice(&raw.to_be_bytes()),
+                        ByteOrder::LittleEndian => samples.extend_from_slice(&raw.to_le_bytes()),
+                    }
+                } else {
+                    samples.push(raw.clamp(i8::MIN as f64, i8::MAX as f64) as i8 as u8);
+                }
+                count += 1;
+            }
+        }
+        // single segment acquisitions carry no TRIGTIME array
+        if !self.sequence.enabled {
+            trigtime.clear();
+        }
+
+        let descriptor = WaveDesc {
+            comm_type: if transfer.word {
+                CommType::Word
+            } else {
+                CommType::Byte
+            },
+            byte_order,
+            trigtime_array_length: trigtime.len() as u32,
+            wave_array_1_length: samples.len() as u32,
+            instrument_name: "LECROYSIM".into(),
+            wave_array_count: count as u32,
+            points_per_screen: segment_points as u32,
+            last_valid_point: count.saturating_sub(1) as u32,
+            first_point: transfer.first_point as u32,
+            sparsing_factor: sparsing as u32,
+            segment_index: transfer.segment as u32,
+            subarray_count: selected.len() as u32,
+            vertical_gain: gain as f32,
+            vertical_offset: ch.offset as f32,
+            nominal_bits: if transfer.word { 16 } else { 8 },
+            nominal_subarray_count: segments as u16,
+            horizontal_interval: (interval * sparsing as f64) as f32,
+            horizontal_offset: start + transfer.first_point as f64 * interval,
+            timebase: Timebase::PerDivision(self.time_div),
+            probe_attenuation: ch.attenuation as f32,
+            fixed_vertical_gain: Some(ch.volt_div),
+            bandwidth_limit: ch.bandwidth_limit != "OFF",
+            wave_source: WaveSource::Channel(channel as u8 + 1),
+            ..WaveDesc::default()
+        };
+
+        [descriptor.to_bytes(), trigtime, samples]
+    }
+
+    /// Answer a query on a single channel.
+    fn channel_query(&self, channel: usize, name: &str) -> Vec<u8> {
+        let ch = &self.channels[channel];
+        match name {
+            "VDIV" => format_number(ch.volt_div),
+            "OFST" => format_number(ch.offset),
+            "ATTN" => ch.attenuation.to_string().into_bytes(),
+            _ => ch.bandwidth_limit.clone().into_bytes(),
+        }
+    }
+
+    /// Parse the argument of a register mask command.
+    fn set_register(&mut self, args: &str, set: impl FnOnce(&mut State, u32)) -> Option<Vec<u8>> {
+        match args.trim().parse::<u32>() {
+            Ok(value) => set(self, value),
+            Err(_) => self.command_error(CMR_ILLEGAL_NUMBER),
+        }
+        None
+    }
+
+    /// Parse the argument of a clock selection command.
+    fn set_clock(&mut self, args: &str, set: impl FnOnce(&mut State, String)) -> Option<Vec<u8>> {
+        let value = args.trim().to_ascii_uppercase();
+        match value.as_str() {
+            "INTERNAL" | "EXTERNAL" => set(self, value),
+            _ => self.command_error(CMR_UNRECOGNIZED_KEYWORD),
+        }
+        None
+    }
+
+    /// Arm the trigger, acquiring immediately when a signal is present.
+    fn arm(&mut self) {
+        self.armed = true;
+        if self.signal_present || self.trigger_mode == TriggerMode::Auto {
+            self.acquire();
+        }
+    }
+
+    /// Make one acquisition and run the autosave.
+    fn acquire(&mut self) {
+        self.acquisitions += 1;
+        self.inr |= INR_NEW_SIGNAL;
+        if self.sequence.enabled {
+            self.inr |= INR_SEGMENT_ACQUIRED;
+        }
+        if self.trigger_mode == TriggerMode::Single {
+            self.trigger_mode = TriggerMode::Stop;
+            self.armed = false;
+        }
+        self.autosave();
+    }
+
+    /// Save the acquired traces to the autosave directory.
+    fn autosave(&mut self) {
+        let mode = self.autosave.mode.clone();
+        if mode == "OFF" {
+            return;
+        }
+        if mode == "FILL" && self.autosave.index >= self.autosave_capacity {
+            self.autosave.mode = "OFF".into();
+            self.inr |= INR_STORAGE_FULL;
+            return;
+        }
+
+        let traces: Vec<String> = if self.autosave.trace == "ALL_DISPLAYED" {
+            (1..=4).map(|c| format!("C{}", c)).collect()
+        } else {
+            vec![self.autosave.trace.clone()]
+        };
+        let directory = self.vbs_get("app.SaveRecall.Waveform.WaveformDir");
+        let directory = if directory.is_empty() {
+            "D:\\Waveforms".to_string()
+        } else {
+            directory
+        };
+        let title = self.vbs_get("app.SaveRecall.Waveform.TraceTitle");
+        let extension = if self.autosave.format == "BINARY" {
+            "trc"
+        } else {
+            "txt"
+        };
+        let index = self.autosave.index % self.autosave_capacity;
+
+        for trace in traces {
+            let path = format!(
+                "{}\\{}{}{:05}.{}",
+                directory.trim_end_matches('\\'),
+                trace,
+                title,
+                index,
+                extension
+            );
+            let data = self.autosave_file(&trace);
+            self.write_file(&path, data);
+        }
+
+        self.autosave.index += 1;
+        if mode == "FILL" && self.autosave.index >= self.autosave_capacity {
+            self.autosave.mode = "OFF".into();
+            self.inr |= INR_STORAGE_FULL;
+        }
+    }
+
+    /// Produce the content of an autosaved file in the selected format. Only
+    /// channels carry a signal, other traces are saved as placeholders.
+    fn autosave_file(&self, trace: &str) -> Vec<u8> {
+        let Some(channel) = parse_channel(trace) else {
+            return format!("{} acquisition {}", trace, self.acquisitions).into_bytes();
+        };
+
+        // files are always saved with 16 bit little endian samples
+        let transfer = Transfer {
+            word: true,
+            little_endian: true,
+            ..Transfer::default()
+        };
+        let data = block(&self.waveform(channel, &transfer).concat());
+        if self.autosave.format == "BINARY" {
+            return data;
+        }
+
+        let mut text = String::from("Time,Ampl\r\n");
+        if let Ok(waveform) = crate::data::trc::decode(&data) {
+            for (time, volts) in waveform.times.iter().zip(&waveform.voltages) {
+                text.push_str(&format!("{:e},{:e}\r\n", time, volts));
+            }
+        }
+        text.into_bytes()
+    }
+
+    /// Create a directory and all of its parents.
+    fn create_directory(&mut self, path: &str) {
+        let path = display_path(path);
+        let mut current = String::new();
+        for component in path.split('\\') {
+            if !current.is_empty() {
+                current.push('\\');
+            }
+            current.push_str(component);
+            self.directories
+                .entry(normalize_path(&current))
+                .or_insert_with(|| VirtualDirectory {
+                    path: current.clone(),
+                    modified: SystemTime::now(),
+                });
+        }
+    }
+
+    /// Build the `DIRECTORY?` listing of a directory. A missing directory is
+    /// listed as empty.
+    fn list_directory(&self, path: &str) -> Vec<u8> {
+        let key = normalize_path(path);
+        let display = display_path(path);
+
+        // the entries whose parent is the directory
+        let is_child =
+            |entry: &str| entry.rsplit_once('\\').map(|(parent, _)| parent) == Some(&key);
+        let mut lines = Vec::new();
+        // subdirectories list themselves and their parent first, like the scope
+        if key.contains('\\') {
+            for name in [".", ".."] {
+                lines.push(format!(
+                    "{:<24} {:>12}  {}",
+                    name,
+                    "<DIR>",
+                    file_time(SystemTime::now())
+                ));
+            }
+        }
+        for (_, directory) in self.directories.iter().filter(|(d, _)| is_child(d)) {
+            let name = directory.path.rsplit('\\').next().unwrap_or_default();
+            lines.push(format!(
+                "{:<24} {:>12}  {}",
+                name,
+                "<DIR>",
+                file_time(directory.modified)
+            ));
+        }
+        let mut count = 0;
+        for (_, file) in self.files.iter().filter(|(f, _)| is_child(f)) {
+            let name = file.path.rsplit('\\').next().unwrap_or_default();
+            lines.push(format!(
+                "{:<24} {:>12}  {}",
+                name,
+                file.data.len(),
+                file_time(file.modified)
+            ));
+            count += 1;
+        }
+
+        let used: u64 = self.files.values().map(|f| f.data.len() as u64).sum();
+        let mut listing = format!("DIR DISK,HDD,\"\nDirectory of {}\\\n\n", display);
+        for line in lines {
+            listing.push_str(&line);
+            listing.push('\n');
+        }
+        listing.push_str(&format!(
+            "{:>6} File(s)  {} bytes free\n\"",
+            count,
+            DISK_CAPACITY.saturating_sub(used)
+        ));
+        listing.into_bytes()
+    }
+
+    /// Execute a message whose arguments end with a binary block.
+    fn execute_block(&mut self, header: &str, data: &[u8]) {
+        let (name, args) = split_header(header);
+        match canonical(&name.to_ascii_uppercase()) {
+            "TRFL" => match parse_file_args(args) {
+                Some((path, _)) => self.receive_file(&path, data),
+                None => self.command_error(CMR_STRING_ERROR),
+            },
+            _ => self.command_error(CMR_UNRECOGNIZED_HEADER),
+        }
+    }
+
+    /// Store the file sent in a `TRANSFER_FILE` block, whose last eight bytes
+    /// are the CRC-32 of the file in hex.
+    fn receive_file(&mut self, path: &str, block: &[u8]) {
+        let Some(block) = parse_block(block) else {
+            self.command_error(CMR_BLOCK_EXPECTED);
+            return;
+        };
+        let (data, crc) = block.split_at(block.len().saturating_sub(8));
+        let crc = std::str::from_utf8(crc)
+            .ok()
+            .and_then(|crc| u32::from_str_radix(crc, 16).ok());
+        if crc != Some(crc32(data)) {
+            self.execution_error(EXR_HEX_DATA);
+            return;
+        }
+        self.write_file(path, data.to_vec());
+    }
+
+    /// Write a file to the virtual disk, creating its parent directories.
+    fn write_file(&mut self, path: &str, data: Vec<u8>) {
+        let key = normalize_path(path);
+        let path = display_path(path);
+        if let Some((parent, _)) = path.rsplit_once('\\') {
+            self.create_directory(parent);
+        }
+        self.files.insert(
+            key,
+            VirtualFile {
+                path,
+                data,
+                modified: SystemTime::now(),
+            },
+        );
+    }
+
+    /// Build the panel setup script describing the current state.
+    fn panel_setup(&self) -> String {
+        let mut lines = vec![format!("TDIV {}", self.time_div)];
+        for (i, ch) in self.channels.iter().enumerate() {
+            lines.push(format!("C{}:ATTN {}", i + 1, ch.attenuation));
+            lines.push(format!("C{}:VDIV {}", i + 1, ch.volt_div));
+            lines.push(format!("C{}:OFST {}", i + 1, ch.offset));
+            lines.push(format!("C{}:BWL {}", i + 1, ch.bandwidth_limit));
+        }
+        lines.push(format!("SCLK {}", self.sample_clock));
+        lines.push(format!("RCLK {}", self.reference_clock));
+        lines.push(format!(
+            "SEQ {},{},{}",
+            if self.sequence.enabled { "ON" } else { "OFF" },
+            self.sequence.segments,
+            self.sequence.max_samples
+        ));
+        lines.push(format!("TRMD {}", self.trigger_mode.keyword()));
+        let mut setup = lines.join("\r\n");
+        setup.push_str("\r\n");
+        setup
+    }
+
+    /// Compute the status byte from the other registers.
+    fn status_byte(&self) -> u8 {
+        let mut stb: u8 = 0;
+        if self.inr & self.ine != 0 {
+            stb |= 1 << 0;
+        }
+        if self.vab {
+            stb |= 1 << 2;
+        }
+        if self.esr & self.ese != 0 {
+            stb |= 1 << 5;
+        }
+        if stb & self.sre != 0 {
+            stb |= 1 << 6;
+        }
+        stb
+    }
+
+    fn command_error(&mut self, code: u8) {
+        self.cmr = code;
+        self.esr |= ESR_CME;
+    }
+
+    fn execution_error(&mut self, code: u8) {
+        self.exr = code;
+        self.esr |= ESR_EXE;
+    }
+}
+
+/// Split a program message into its individual messages.
+fn split_messages(cmd: &str) -> Vec<String> {
+    let cmd = cmd.trim_end_matches(['\n', '\r']);
+
+    // messages carrying quoted strings or data blocks are never split
+    if cmd.contains('\'') || (cmd.contains('#') && !cmd.trim_start().starts_with('*')) {
+        return vec![cmd.trim().to_string()];
+    }
+
+    cmd.split(';')
+        .map(|m| m.trim().to_string())
+        .filter(|m| !m.is_empty())
+        .collect()
+}
+
+/// Split a message into its header and its arguments.
+fn split_header(message: &str) -> (&str, &str) {
+    let message = message.trim();
+    // VBS commands may be directly followed by a quote
+    if message.get(..3).is_some_and(|h| h.eq_ignore_ascii_case("VBS")) {
+        let (header, args) = message.split_at(3);
+        if let Some(args) = args.strip_prefix('?') {
+            return ("VBS?", args.trim());
+        }
+        return (header, args.trim());
+    }
+    match message.split_once(char::is_whitespace) {
+        Some((header, args)) => (header, args.trim()),
+        None => (message, ""),
+    }
+}
+
+/// Map the long form of a header to its short form.
+fn canonical(name: &str) -> &str {
+    match name {
+        "ARM_ACQUISITION" => "ARM",
+        "FORCE_TRIGGER" => "FRTR",
+        "AUTO_SETUP" => "ASET",
+        "TRIG_MODE" => "TRMD",
+        "TIME_DIV" => "TDIV",
+        "VOLT_DIV" => "VDIV",
+        "OFFSET" => "OFST",
+        "ATTENUATION" => "ATTN",
+        "BANDWIDTH_LIMIT" => "BWL",
+        "SAMPLE_CLOCK" => "SCLK",
+        "REFERENCE_CLOCK" => "RCLK",
+        "COMM_HEADER" => "CHDR",
+        "COMM_HELP" => "CHLP",
+        "COMM_HELP_LOG" => "CHL",
+        "PANEL_SETUP" => "PNSU",
+        "STORE_SETUP" => "STST",
+        "TRANSFER_FILE" => "TRFL",
+        "DELETE_FILE" => "DELF",
+        "DIRECTORY" => "DIR",
+        "HARDCOPY_SETUP" => "HCSU",
+        "SCREEN_DUMP" => "SCDP",
+        "COMM_FORMAT" => "CFMT",
+        "COMM_ORDER" => "CORD",
+        "WAVEFORM_SETUP" => "WFSU",
+        "WAVEFORM" => "WF",
+        "SEQUENCE" => "SEQ",
+        _ => name,
+    }
+}
+
+/// Parse a channel prefix such as `C2` into a zero-based index.
+fn parse_channel(prefix: &str) -> Option<usize> {
+    let number = prefix.strip_prefix(['C', 'c'])?.parse::<usize>().ok()?;
+    (1..=4).contains(&number).then(|| number - 1)
+}
+
+/// Parse a numeric argument, ignoring any unit suffix.
+fn parse_number(args: &str) -> Option<f64> {
+    let args = args.trim();
+    let end = args
+        .char_indices()
+        .find(|(_, c)| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E')))
+        .map(|(i, _)| i)
+        .unwrap_or(args.len());
+    args[..end].parse::<f64>().ok()
+}
+
+/// Format a number the way the scope answers numeric queries.
+fn format_number(value: f64) -> Vec<u8> {
+    format!("{:E}", value).into_bytes()
+}
+
+/// Adjust a time per division to the closest 1-2-5 step within range.
+fn adapt_time_div(value: f64) -> (f64, bool) {
+    let clamped = value.clamp(20e-12, 1e3);
+    let exponent = clamped.log10().floor();
+    let mantissa = clamped / 10f64.powf(exponent);
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .min_by(|a, b| (a - mantissa).abs().total_cmp(&(b - mantissa).abs()))
+        .unwrap_or(1.0);
+    let adapted = step * 10f64.powf(exponent);
+    (adapted, clamped != value)
+}
+
+/// Wrap data in an IEEE 488.2 definite length block.
+fn block(data: &[u8]) -> Vec<u8> {
+    let mut resp = format!("#9{:0>9}", data.len()).into_bytes();
+    resp.extend_from_slice(data);
+    resp
+}
+
+/// Format a time as `dd-Mon-yyyy hh:mm:ss` in UTC, like a directory listing.
+fn file_time(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+
+    // civil date from the days since 1970, with years starting in March
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:02}-{}-{:04} {:02}:{:02}:{:02}",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Extract the data of an IEEE 488.2 definite length block.
+fn parse_block(args: &[u8]) -> Option<&[u8]> {
+    let args = args.strip_prefix(b"#")?;
+    let digits = (*args.first()? as char).to_digit(10)? as usize;
+    let length = std::str::from_utf8(args.get(1..1 + digits)?).ok()?;
+    let length = length.parse::<usize>().ok()?;
+    args.get(1 + digits..1 + digits + length)
+}
+
+/// Parse the `DISK,HDD,FILE,'<path>'[,<data>]` arguments of the file commands.
+fn parse_file_args(args: &str) -> Option<(String, Option<&str>)> {
+    let start = args.find('\'')?;
+    let end = start + 1 + args[start + 1..].find('\'')?;
+    let path = args[start + 1..end].to_string();
+    let rest = args[end + 1..].trim_start();
+    let data = rest.strip_prefix(',').map(|d| d.trim_start());
+    Some((path, data))
+}
+
+/// Normalize a device path into a case-insensitive key.
+fn normalize_path(path: &str) -> String {
+    display_path(path).to_ascii_uppercase()
+}
+
+/// Normalize a device path for display, defaulting to the D: drive.
+fn display_path(path: &str) -> String {
+    let path = path.trim().replace('/', "\\");
+    let path = path.trim_end_matches('\\');
+    if path.is_empty() {
+        "D:".to_string()
+    } else if path.starts_with('\\') {
+        format!("D:{}", path)
+    } else if path.len() >= 2 && path.as_bytes()[1] == b':' {
+        path.to_string()
+    } else {
+        format!("D:\\{}", path)
+    }
+}
+
+/// Match automation paths of the form `app.acquisition.cN.<property>`.
+fn vbs_channel_property(key: &str) -> Option<(usize, &str)> {
+    let rest = key.strip_prefix("app.acquisition.")?;
+    let (channel, property) = rest.split_once('.')?;
+    Some((parse_channel(channel)?, property))
+}
+
+/// Return the value following a keyword of the HCSU arguments, unquoted.
+fn hardcopy_field<'a>(hardcopy: &'a str, keyword: &str) -> Option<&'a str> {
+    let fields: Vec<&str> = hardcopy.split(',').map(str::trim).collect();
+    fields
+        .chunks_exact(2)
+        .find(|pair| pair[0].eq_ignore_ascii_case(keyword))
+        .map(|pair| pair[1].trim_matches('"'))
+}
+
+/// Produce a placeholder image in the format selected by HCSU.
+fn screen_dump(hardcopy: &str) -> Vec<u8> {
+    let format = hardcopy_field(hardcopy, "DEV")
+        .unwrap_or("JPEG")
+        .to_ascii_uppercase();
+    match format.as_str() {
+        "PNG" => b"\x89PNG\r\n\x1a\n".to_vec(),
+        "BMP" => b"BM".to_vec(),
+        "TIFF" => b"II*\x00".to_vec(),
+        _ => b"\xff\xd8\xff\xe0\xff\xd9".to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystems::storage::SyncOptions;
+    use crate::{MauiOscilloscope, WaitOptions};
+    use std::sync::Arc;
+
+    fn connect() -> (Arc<SimulatedScope>, MauiOscilloscope) {
+        let sim = Arc::new(SimulatedScope::new());
+        let scope = MauiOscilloscope::with_transport(sim.clone()).unwrap();
+        scope.set_strict(true).unwrap();
+        (sim, scope)
+    }
+
+    #[test]
+    fn connect_initializes_the_scope() {
+        let (sim, scope) = connect();
+        let history = sim.history();
+        assert_eq!(history[0], "CHDR OFF");
+        assert!(history.iter().any(|m| m.starts_with("*ESE")));
+        assert!(history.iter().any(|m| m.starts_with("INE")));
+        assert_eq!(scope.query("*IDN?").unwrap().trim(), IDENTITY);
+    }
+
+    #[test]
+    fn acquisition_waits_for_the_trigger() {
+        let (sim, scope) = connect();
+        let options = WaitOptions::timeout(Duration::from_secs(5));
+        scope.acquisition.arm_and_wait_for_acquisition(&options).unwrap();
+        assert_eq!(sim.acquisition_count(), 1);
+
+        // without a signal the scope stays armed until triggered
+        sim.set_signal_present(false);
+        scope.acquisition.set_trigger_mode_normal().unwrap();
+        let options = WaitOptions::timeout(Duration::from_millis(200));
+        assert!(matches!(
+            scope.acquisition.arm_and_wait_for_acquisition(&options),
+            Err(MauiError::Timeout(_))
+        ));
+        assert!(sim.trigger());
+        assert_eq!(sim.acquisition_count(), 2);
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let (_, scope) = connect();
+        scope.acquisition.set_volt_div(2, 0.5).unwrap();
+        assert_eq!(scope.acquisition.get_volt_div(2).unwrap(), 0.5);
+        scope.acquisition.set_sequence_mode(4, 1000).unwrap();
+        assert_eq!(scope.acquisition.get_sequence_mode().unwrap(), Some((4, 1000)));
+    }
+
+    #[test]
+    fn waveform_download_matches_descriptor() {
+        let (_, scope) = connect();
+        scope
+            .acquisition
+            .arm_and_wait_for_acquisition(&WaitOptions::new())
+            .unwrap();
+        let waveform = scope.waveform.read_waveform("C1").unwrap();
+        assert_eq!(waveform.voltages.len(), ACQUISITION_POINTS);
+        assert_eq!(waveform.times.len(), ACQUISITION_POINTS);
+        assert_eq!(
+            waveform.descriptor.wave_array_count as usize,
+            ACQUISITION_POINTS
+        );
+        assert!(waveform.voltages.iter().any(|v| *v != 0.0));
+    }
+
+    #[test]
+    fn storage_files_and_directories() {
+        let (sim, scope) = connect();
+        let data: Vec<u8> = (0..=255).cycle().take(3000).collect();
+        scope
+            .storage
+            .write_file_on_device("D:\\Test\\data.bin", &data)
+            .unwrap();
+        assert_eq!(sim.file("D:\\Test\\data.bin").unwrap(), data);
+        assert_eq!(
+            scope.storage.read_file_on_device("D:/Test/data.bin").unwrap(),
+            data
+        );
+        assert!(scope.storage.exists("D:\\Test\\data.bin").unwrap());
+
+        let listing = scope.storage.list_directory("D:\\Test").unwrap();
+        assert_eq!(listing.len(), 1);
+        assert_eq!(listing[0].name, "data.bin");
+        assert_eq!(listing[0].size, data.len() as u64);
+
+        scope.storage.delete_file_on_device("D:\\Test\\data.bin").unwrap();
+        assert!(!scope.storage.exists("D:\\Test\\data.bin").unwrap());
+        assert!(scope.storage.read_file_on_device("D:\\Test\\data.bin").is_err());
+    }
+
+    #[test]
+    fn storage_pull_directory() {
+        let (sim, scope) = connect();
+        sim.insert_file("D:\\Waveforms\\C1Trace00000.trc", b"one");
+        sim.insert_file("D:\\Waveforms\\C1Trace00000.txt", b"two");
+
+        let local = std::env::temp_dir().join(format!("rs-maui-sim-{}", std::process::id()));
+        let options = SyncOptions::new().with_include("*.trc");
+        let report = scope
+            .storage
+            .pull_directory("D:\\Waveforms", &local, &options)
+            .unwrap();
+        let pulled = std::fs::read(local.join("C1Trace00000.trc"));
+        let skipped = local.join("C1Trace00000.txt").exists();
+        std::fs::remove_dir_all(&local).unwrap();
+
+        assert_eq!(report.transferred.len(), 1);
+        assert_eq!(pulled.unwrap(), b"one");
+        assert!(!skipped);
+    }
+
+    #[test]
+    fn storage_pull_nested_directory() {
+        let (sim, scope) = connect();
+        sim.insert_file("D:\\Waveforms\\C1Trace00000.trc", b"one");
+        sim.insert_file("D:\\Waveforms\\run 1\\C1Trace00001.trc", b"two");
+        sim.insert_file("D:\\Waveforms\\run 1\\deep\\C1Trace00002.trc", b"three");
+
+        let local = std::env::temp_dir().join(format!("rs-maui-nested-{}", std::process::id()));
+        let report = scope
+            .storage
+            .pull_directory("D:\\Waveforms", &local, &SyncOptions::new())
+            .unwrap();
+        let pulled = |path: &str| std::fs::read(local.join(path)).unwrap();
+        let (one, two, three) = (
+            pulled("C1Trace00000.trc"),
+            pulled("run 1/C1Trace00001.trc"),
+            pulled("run 1/deep/C1Trace00002.trc"),
+        );
+        std::fs::remove_dir_all(&local).unwrap();
+
+        let mut transferred = report.transferred.clone();
+        transferred.sort();
+        assert_eq!(
+            transferred,
+            [
+                "C1Trace00000.trc",
+                "run 1/C1Trace00001.trc",
+                "run 1/deep/C1Trace00002.trc",
+            ]
+        );
+        assert_eq!(one, b"one");
+        assert_eq!(two, b"two");
+        assert_eq!(three, b"three");
+    }
+
+    #[test]
+    fn non_ascii_message_is_an_unknown_command() {
+        let sim = SimulatedScope::new();
+        sim.command("éé").unwrap();
+        assert_eq!(sim.query("CMR?").unwrap().trim(), CMR_UNRECOGNIZED_HEADER.to_string());
+        assert_eq!(split_header("éé b"), ("éé", "b"));
+    }
+}
@@ -0,0 +1,336 @@
+//! ## Raw TCP Transport
+//!
+//! A transport for raw socket bridges, where commands and responses are plain
+//! text separated by terminator characters.
+//!
+
+use crate::transport::{response_to_string, socket_address};
+use crate::{MauiError, Result, Transport};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The port used when an address does not specify one.
+pub const DEFAULT_TCP_PORT: u16 = 5025;
+
+/// The number of bytes of a block read between two progress reports.
+const RECEIVE_PIECE: usize = 64 * 1024;
+
+/// The state of an open socket.
+struct Socket {
+    writer: TcpStream,
+    reader: BufReader<TcpStream>,
+    write_terminator: Vec<u8>,
+    read_terminator: u8,
+}
+
+impl Socket {
+    fn send(&mut self, cmd: &[u8]) -> Result<()> {
+        self.send_in_pieces(cmd, usize::MAX, &mut |_, _| {})
+    }
+
+    /// Send a command in pieces of at most `chunk_size` bytes, reporting the
+    /// bytes of `cmd` sent after each piece.
+    fn send_in_pieces(
+        &mut self,
+        cmd: &[u8],
+        chunk_size: usize,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        let mut message = cmd.to_vec();
+        if !message.ends_with(&self.write_terminator) {
+            message.extend_from_slice(&self.write_terminator);
+        }
+        let mut sent = 0;
+        for piece in message.chunks(chunk_size.max(1)) {
+            self.writer.write_all(piece).map_err(io_error)?;
+            sent += piece.len();
+            progress(sent.min(cmd.len()), cmd.len());
+        }
+        Ok(())
+    }
+
+    /// Read one response. Definite length blocks are read by length, so they
+    /// may contain the terminator character, and their progress is reported
+    /// as they arrive. A block can only start the response or follow a space
+    /// or a comma, any other `#` is text.
+    fn receive(&mut self, progress: &mut dyn FnMut(usize, usize)) -> Result<Vec<u8>> {
+        let mut response = Vec::new();
+        loop {
+            let mut byte = [0u8; 1];
+            self.reader.read_exact(&mut byte).map_err(io_error)?;
+
+            let block_can_start = matches!(response.last(), None | Some(b' ' | b','));
+            if byte[0] == b'#' && block_can_start {
+                response.push(b'#');
+                if self.receive_block(&mut response, progress)? {
+                    return Ok(response);
+                }
+                continue;
+            }
+            if byte[0] == self.read_terminator {
+                return Ok(response);
+            }
+            response.push(byte[0]);
+        }
+    }
+
+    /// Read the rest of a definite length block whose `#` was already read.
+    /// Returns `true` if the response terminator was read instead of a block.
+    /// If the header is not followed by digits, the bytes read are kept as
+    /// text.
+    fn receive_block(
+        &mut self,
+        response: &mut Vec<u8>,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<bool> {
+        let mut digits = [0u8; 1];
+        self.reader.read_exact(&mut digits).map_err(io_error)?;
+        if digits[0] == self.read_terminator {
+            return Ok(true);
+        }
+        response.push(digits[0]);
+        let digits = match (digits[0] as char).to_digit(10) {
+            // `#0` is an indefinite length block, terminated like any response
+            Some(0) | None => return Ok(false),
+            Some(digits) => digits as usize,
+        };
+
+        let mut length = 0;
+        for _ in 0..digits {
+            let mut digit = [0u8; 1];
+            self.reader.read_exact(&mut digit).map_err(io_error)?;
+            if digit[0] == self.read_terminator {
+                return Ok(true);
+            }
+            response.push(digit[0]);
+            match (digit[0] as char).to_digit(10) {
+                Some(digit) => length = length * 10 + digit as usize,
+                None => return Ok(false),
+            }
+        }
+
+        let start = response.len();
+        response.resize(start + length, 0);
+        let mut received = 0;
+        for piece in response[start..].chunks_mut(RECEIVE_PIECE) {
+            self.reader.read_exact(piece).map_err(io_error)?;
+            received += piece.len();
+            progress(received, length);
+        }
+
+        Ok(false)
+    }
+}
+
+/// ## TCP Transport
+///
+/// A [`Transport`] over a raw TCP socket with configurable terminators.
+///
+/// Commands are followed by the write terminator, `"\n"` by default, and
+/// responses end at the read terminator, `b'\n'` by default.
+///
+pub struct TcpTransport {
+    socket: Mutex<Socket>,
+}
+
+impl TcpTransport {
+    /// ## Connect
+    ///
+    /// Open a socket to `host`. The port defaults to 5025 if `host` does not
+    /// specify one. IPv6 literals with a port are written in brackets, as in
+    /// `[::1]:5025`.
+    ///
+    pub fn connect(host: &str) -> Result<TcpTransport> {
+        Self::connect_to(socket_address(host, DEFAULT_TCP_PORT))
+    }
+
+    /// ## Connect To
+    ///
+    /// Open a socket to an explicit socket address.
+    ///
+    pub fn connect_to(address: impl ToSocketAddrs) -> Result<TcpTransport> {
+        let stream = TcpStream::connect(address).map_err(io_error)?;
+        stream.set_nodelay(true).map_err(io_error)?;
+        let reader = BufReader::new(stream.try_clone()?);
+
+        let transport = TcpTransport {
+            socket: Mutex::new(Socket {
+                writer: stream,
+                reader,
+                write_terminator: b"\n".to_vec(),
+                read_terminator: b'\n',
+            }),
+        };
+        transport.set_timeout(Duration::from_secs(10));
+
+        Ok(transport)
+    }
+
+    /// ## With Terminators
+    ///
+    /// Replace the terminator appended to commands and the terminator that
+    /// ends responses.
+    ///
+    pub fn with_terminators(self, write_terminator: &str, read_terminator: u8) -> TcpTransport {
+        {
+            let mut socket = self.lock();
+            socket.write_terminator = write_terminator.as_bytes().to_vec();
+            socket.read_terminator = read_terminator;
+        }
+        self
+    }
+
+    /// ## Discard Input
+    ///
+    /// Drop any data already received but not read, for instance after a
+    /// timed out query.
+    ///
+    pub fn discard_input(&self) -> Result<()> {
+        let mut socket = self.lock();
+        let timeout = socket.writer.read_timeout()?;
+        socket
+            .writer
+            .set_read_timeout(Some(Duration::from_millis(50)))?;
+        let result = loop {
+            let available = match socket.reader.fill_buf() {
+                Ok([]) => {
+                    break Err(MauiError::Transport(
+                        "connection closed by the device".into(),
+                    ))
+                }
+                Ok(buffer) => buffer.len(),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    break Ok(())
+                }
+                Err(e) => break Err(io_error(e)),
+            };
+            socket.reader.consume(available);
+        };
+        let restore = socket.writer.set_read_timeout(timeout);
+
+        result?;
+        restore?;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Socket> {
+        self.socket.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Transport for TcpTransport {
+    fn command(&self, cmd: &str) -> Result<()> {
+        self.lock().send(cmd.as_bytes())
+    }
+
+    fn command_raw(&self, cmd: &[u8]) -> Result<()> {
+        self.lock().send(cmd)
+    }
+
+    fn query(&self, cmd: &str) -> Result<String> {
+        let resp = response_to_string(self.query_raw(cmd)?)?;
+        Ok(resp.trim_end_matches('\r').to_string())
+    }
+
+    fn query_raw(&self, cmd: &str) -> Result<Vec<u8>> {
+        self.query_raw_with_progress(cmd, &mut |_, _| {})
+    }
+
+    fn query_raw_with_progress(
+        &self,
+        cmd: &str,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<Vec<u8>> {
+        let mut socket = self.lock();
+        socket.send(cmd.as_bytes())?;
+        socket.receive(progress)
+    }
+
+    fn command_raw_with_progress(
+        &self,
+        cmd: &[u8],
+        chunk_size: usize,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        self.lock().send_in_pieces(cmd, chunk_size, progress)
+    }
+
+    fn set_timeout(&self, duration: Duration) {
+        let socket = self.lock();
+        // a zero duration is rejected by the socket, treat it as no timeout
+        let duration = (!duration.is_zero()).then_some(duration);
+        let _ = socket.writer.set_read_timeout(duration);
+        let _ = socket.writer.set_write_timeout(duration);
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.discard_input()
+    }
+}
+
+/// Sort socket errors into timeouts and other transport failures.
+fn io_error(e: std::io::Error) -> MauiError {
+    match e.kind() {
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+            MauiError::Timeout("TCP transfer timed out".into())
+        }
+        std::io::ErrorKind::UnexpectedEof => {
+            MauiError::Transport("connection closed by the device".into())
+        }
+        _ => MauiError::Transport(format!("TCP socket error: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answer the first command on a local socket with `response`.
+    fn serve(response: &'static [u8]) -> TcpTransport {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            (&stream).write_all(response).unwrap();
+            thread::sleep(Duration::from_millis(500));
+        });
+        TcpTransport::connect(&address.to_string()).unwrap()
+    }
+
+    #[test]
+    fn terminator_inside_block_is_data() {
+        let transport = serve(b"TRFL #15a\nb\nc\n");
+        let mut reports = Vec::new();
+        let resp = transport
+            .query_raw_with_progress("TRFL? DISK,HDD,FILE,'D:\\a.bin'", &mut |r, t| {
+                reports.push((r, t))
+            })
+            .unwrap();
+        assert_eq!(resp, b"TRFL #15a\nb\nc");
+        assert_eq!(reports, [(5, 5)]);
+    }
+
+    #[test]
+    fn hash_in_text_is_kept() {
+        assert_eq!(
+            serve(b"VBS 'Trace#1 ok'\n").query("VBS? 'x'").unwrap(),
+            "VBS 'Trace#1 ok'"
+        );
+        assert_eq!(serve(b"NAME #1abc\n").query("NAME?").unwrap(), "NAME #1abc");
+        assert_eq!(serve(b"NAME #3ab\n").query("NAME?").unwrap(), "NAME #3ab");
+        assert_eq!(serve(b"NAME #2\n").query("NAME?").unwrap(), "NAME #2");
+        assert_eq!(serve(b"#\n").query("NAME?").unwrap(), "#");
+    }
+
+    #[test]
+    fn terminators_are_configurable() {
+        let transport = serve(b"1\r").with_terminators("\n", b'\r');
+        assert_eq!(transport.query("*OPC?").unwrap(), "1");
+    }
+}
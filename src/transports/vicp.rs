@@ -0,0 +1,582 @@
+//! ## VICP Transport
+//!
+//! A pure Rust client for the Teledyne-Lecroy VICP protocol, which carries
+//! remote commands over TCP port 1861 without the need for a VISA stack.
+//!
+//! Every VICP block starts with an 8 byte header:
+//!
+//! | byte | content                                   |
+//! |------|-------------------------------------------|
+//! | 0    | operation flags                           |
+//! | 1    | header version (always 1)                 |
+//! | 2    | sequence number                           |
+//! | 3    | spare (always 0)                          |
+//! | 4..8 | length of the following block, big endian |
+//!
+
+use crate::transport::{block_progress, response_to_string, socket_address};
+use crate::{MauiError, Result, Transport};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The TCP port on which MAUI oscilloscopes listen for VICP connections.
+pub const VICP_PORT: u16 = 1861;
+
+/// The version written in every header.
+const HEADER_VERSION: u8 = 1;
+
+// Operation flags of the first header byte.
+const OPERATION_DATA: u8 = 0x80;
+const OPERATION_REMOTE: u8 = 0x40;
+const OPERATION_LOCKOUT: u8 = 0x20;
+const OPERATION_CLEAR: u8 = 0x10;
+const OPERATION_SRQ: u8 = 0x08;
+const OPERATION_SERIAL_POLL: u8 = 0x04;
+const OPERATION_EOI: u8 = 0x01;
+
+/// The state of an open VICP connection.
+struct Connection {
+    stream: TcpStream,
+    sequence: u8,
+    remote: bool,
+    lockout: bool,
+    srq: bool,
+    /// Set when a transfer was interrupted mid-block, leaving the stream at
+    /// an unknown position until the next device clear.
+    broken: bool,
+}
+
+impl Connection {
+    /// Return the next sequence number, skipping zero as VICP 1a requires.
+    fn next_sequence(&mut self) -> u8 {
+        self.sequence = self.sequence.wrapping_add(1);
+        if self.sequence == 0 {
+            self.sequence = 1;
+        }
+        self.sequence
+    }
+
+    /// Fail if an earlier transfer left the stream out of sync.
+    fn check(&self) -> Result<()> {
+        match self.broken {
+            true => Err(MauiError::Transport(
+                "VICP connection is out of sync after an interrupted transfer, \
+                 call device_clear or reconnect"
+                    .into(),
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Send a single block with the given operation flags.
+    fn send(&mut self, flags: u8, data: &[u8]) -> Result<()> {
+        self.check()?;
+        let mut flags = flags;
+        if self.remote {
+            flags |= OPERATION_REMOTE;
+        }
+        if self.lockout {
+            flags |= OPERATION_LOCKOUT;
+        }
+
+        let length = u32::try_from(data.len()).map_err(|_| {
+            MauiError::Transport(format!(
+                "block of {} bytes is too long for VICP",
+                data.len()
+            ))
+        })?;
+
+        let mut packet = Vec::with_capacity(8 + data.len());
+        packet.push(flags);
+        packet.push(HEADER_VERSION);
+        packet.push(self.next_sequence());
+        packet.push(0);
+        packet.extend_from_slice(&length.to_be_bytes());
+        packet.extend_from_slice(data);
+
+        // a partially written packet cannot be taken back
+        self.stream.write_all(&packet).map_err(|e| {
+            self.broken = true;
+            io_error(e)
+        })
+    }
+
+    /// Read a single block, returning its operation flags and data. A timeout
+    /// before the first byte of the header leaves the stream between blocks,
+    /// any later failure marks the connection as broken.
+    fn receive_block(&mut self) -> Result<(u8, Vec<u8>)> {
+        self.check()?;
+        let mut header = [0u8; 8];
+        self.stream.read_exact(&mut header[..1]).map_err(io_error)?;
+
+        let result = self.receive_block_rest(header);
+        if result.is_err() {
+            self.broken = true;
+        }
+        result
+    }
+
+    /// Read the rest of a block whose first header byte was already read.
+    fn receive_block_rest(&mut self, mut header: [u8; 8]) -> Result<(u8, Vec<u8>)> {
+        self.stream.read_exact(&mut header[1..]).map_err(io_error)?;
+
+        if header[1] != HEADER_VERSION {
+            return Err(MauiError::Transport(format!(
+                "unsupported VICP header version {}, the connection is out of sync",
+                header[1]
+            )));
+        }
+
+        let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let mut data = vec![0u8; length];
+        self.stream.read_exact(&mut data).map_err(io_error)?;
+
+        Ok((header[0], data))
+    }
+
+    /// Read blocks until one carries the EOI flag and return the joined data.
+    /// The connection is marked as broken if the response does not arrive, as
+    /// it would otherwise be read as the response to the next query.
+    fn receive(&mut self, progress: &mut dyn FnMut(usize, usize)) -> Result<Vec<u8>> {
+        let result = self.receive_response(progress);
+        if result.is_err() {
+            self.broken = true;
+        }
+        result
+    }
+
+    fn receive_response(&mut self, progress: &mut dyn FnMut(usize, usize)) -> Result<Vec<u8>> {
+        let mut response = Vec::new();
+        loop {
+            let (flags, data) = self.receive_block()?;
+
+            // service requests arrive out of band and are only remembered
+            if flags & OPERATION_SRQ != 0 {
+                self.srq = data.first() == Some(&b'1');
+                continue;
+            }
+
+            if flags & OPERATION_DATA != 0 {
+                response.extend_from_slice(&data);
+                if let Some((received, total)) = block_progress(&response) {
+                    progress(received, total);
+                }
+                if flags & OPERATION_EOI != 0 {
+                    return Ok(response);
+                }
+            }
+        }
+    }
+
+    /// Send a device clear and drop whatever is left in the socket.
+    fn clear(&mut self) -> Result<()> {
+        self.broken = false;
+        self.send(OPERATION_DATA | OPERATION_CLEAR, &[])?;
+
+        let timeout = self.stream.read_timeout()?;
+        self.stream
+            .set_read_timeout(Some(Duration::from_millis(50)))?;
+        let mut buffer = [0u8; 4096];
+        let result = loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    break Err(MauiError::Transport(
+                        "VICP connection closed by the device".into(),
+                    ))
+                }
+                Ok(_) => continue,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    break Ok(())
+                }
+                Err(e) => break Err(io_error(e)),
+            }
+        };
+        let restore = self.stream.set_read_timeout(timeout);
+        self.srq = false;
+
+        result?;
+        restore?;
+        Ok(())
+    }
+}
+
+/// ## VICP Transport
+///
+/// A [`Transport`] speaking VICP directly over TCP.
+///
+/// Commands are sent as a single block with the EOI flag set, and responses
+/// are read until a block with EOI is received. Service request blocks sent by
+/// the scope are recorded and can be read with [`VicpTransport::take_srq`], or
+/// waited for with [`Transport::wait_for_srq`] without polling the scope.
+///
+/// A transfer interrupted in the middle of a block, or a query whose response
+/// did not arrive in time, leaves the connection out of sync. Every later
+/// exchange then fails until [`VicpTransport::device_clear`] is called.
+///
+pub struct VicpTransport {
+    connection: Mutex<Connection>,
+}
+
+impl VicpTransport {
+    /// ## Connect
+    ///
+    /// Open a VICP connection to the scope at `host`. The port defaults to
+    /// 1861 if `host` does not specify one. IPv6 literals with a port are
+    /// written in brackets, as in `[::1]:1861`.
+    ///
+    pub fn connect(host: &str) -> Result<VicpTransport> {
+        Self::connect_to(socket_address(host, VICP_PORT))
+    }
+
+    /// ## Connect To
+    ///
+    /// Open a VICP connection to an explicit socket address.
+    ///
+    pub fn connect_to(address: impl ToSocketAddrs) -> Result<VicpTransport> {
+        let stream = TcpStream::connect(address).map_err(io_error)?;
+        stream.set_nodelay(true).map_err(io_error)?;
+
+        let transport = VicpTransport {
+            connection: Mutex::new(Connection {
+                stream,
+                sequence: 0,
+                remote: true,
+                lockout: false,
+                srq: false,
+                broken: false,
+            }),
+        };
+        transport.set_timeout(Duration::from_secs(10));
+
+        Ok(transport)
+    }
+
+    /// ## Device Clear
+    ///
+    /// Clear the input and output buffers of the scope, and discard anything
+    /// the scope had already sent to the controller. This also recovers a
+    /// connection left out of sync by an interrupted transfer.
+    ///
+    pub fn device_clear(&self) -> Result<()> {
+        let mut connection = self.lock();
+        let result = connection.clear();
+        connection.broken = result.is_err();
+        result
+    }
+
+    /// ## Serial Poll
+    ///
+    /// Read the status byte of the scope out of band.
+    ///
+    pub fn serial_poll(&self) -> Result<u8> {
+        let mut connection = self.lock();
+        connection.send(OPERATION_DATA | OPERATION_SERIAL_POLL, &[])?;
+        let response = connection.receive(&mut |_, _| {})?;
+        response
+            .first()
+            .copied()
+            .ok_or_else(|| MauiError::Transport("empty response to VICP serial poll".into()))
+    }
+
+    /// ## Set Remote
+    ///
+    /// Select whether blocks are sent with the REMOTE flag. While it is set, the
+    /// front panel of the scope shows that it is under remote control.
+    ///
+    pub fn set_remote(&self, remote: bool) {
+        self.lock().remote = remote;
+    }
+
+    /// ## Set Lockout
+    ///
+    /// Select whether blocks are sent with the LOCKOUT flag, which disables the
+    /// front panel controls while the scope is in remote mode.
+    ///
+    pub fn set_lockout(&self, lockout: bool) {
+        self.lock().lockout = lockout;
+    }
+
+    /// ## Go To Local
+    ///
+    /// Return the scope to local mode, giving control back to the front panel.
+    ///
+    pub fn go_to_local(&self) -> Result<()> {
+        let mut connection = self.lock();
+        connection.remote = false;
+        connection.lockout = false;
+        connection.send(OPERATION_DATA | OPERATION_EOI, &[])
+    }
+
+    /// ## Take SRQ
+    ///
+    /// Return whether the scope has asserted a service request since the last
+    /// call, and clear the flag.
+    ///
+    pub fn take_srq(&self) -> bool {
+        std::mem::take(&mut self.lock().srq)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Transport for VicpTransport {
+    fn command(&self, cmd: &str) -> Result<()> {
+        self.lock()
+            .send(OPERATION_DATA | OPERATION_EOI, cmd.as_bytes())
+    }
+
+    fn command_raw(&self, cmd: &[u8]) -> Result<()> {
+        self.lock().send(OPERATION_DATA | OPERATION_EOI, cmd)
+    }
+
+    fn query(&self, cmd: &str) -> Result<String> {
+        let resp = response_to_string(self.query_raw(cmd)?)?;
+        Ok(resp.trim_end_matches(['\n', '\r']).to_string())
+    }
+
+    fn query_raw(&self, cmd: &str) -> Result<Vec<u8>> {
+        self.query_raw_with_progress(cmd, &mut |_, _| {})
+    }
+
+    fn query_raw_with_progress(
+        &self,
+        cmd: &str,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<Vec<u8>> {
+        let mut connection = self.lock();
+        connection.send(OPERATION_DATA | OPERATION_EOI, cmd.as_bytes())?;
+        connection.receive(progress)
+    }
+
+    fn command_raw_with_progress(
+        &self,
+        cmd: &[u8],
+        chunk_size: usize,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        let mut connection = self.lock();
+        // only the last block of the message carries EOI
+        let blocks: Vec<&[u8]> = match cmd.is_empty() {
+            true => vec![cmd],
+            false => cmd.chunks(chunk_size.max(1)).collect(),
+        };
+        let mut sent = 0;
+        for (index, block) in blocks.iter().enumerate() {
+            let eoi = match index + 1 == blocks.len() {
+                true => OPERATION_EOI,
+                false => 0,
+            };
+            connection.send(OPERATION_DATA | eoi, block)?;
+            sent += block.len();
+            progress(sent, cmd.len());
+        }
+        Ok(())
+    }
+
+    fn set_timeout(&self, duration: Duration) {
+        let connection = self.lock();
+        // a zero duration is rejected by the socket, treat it as no timeout
+        let duration = (!duration.is_zero()).then_some(duration);
+        let _ = connection.stream.set_read_timeout(duration);
+        let _ = connection.stream.set_write_timeout(duration);
+    }
+
+    fn wait_for_srq(&self, timeout: Duration) -> Result<()> {
+        let mut connection = self.lock();
+        if std::mem::take(&mut connection.srq) {
+            return Ok(());
+        }
+
+        connection.check()?;
+        let previous = connection.stream.read_timeout()?;
+        let deadline = Instant::now() + timeout;
+        let result = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break Err(MauiError::Timeout(format!(
+                    "no service request within {:?}",
+                    timeout
+                )));
+            }
+            connection.stream.set_read_timeout(Some(remaining))?;
+
+            match connection.receive_block() {
+                Ok((flags, data)) if flags & OPERATION_SRQ != 0 => {
+                    if data.first() == Some(&b'1') {
+                        break Ok(());
+                    }
+                }
+                // no query is pending, so any data block is stray and dropped
+                Ok(_) => continue,
+                // a timeout mid-block has marked the connection as broken
+                Err(MauiError::Timeout(_)) if !connection.broken => continue,
+                Err(e) => break Err(e),
+            }
+        };
+        let restore = connection.stream.set_read_timeout(previous);
+
+        result?;
+        restore?;
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.device_clear()
+    }
+}
+
+/// Sort socket errors into timeouts and other transport failures.
+fn io_error(e: std::io::Error) -> MauiError {
+    match e.kind() {
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+            MauiError::Timeout("VICP transfer timed out".into())
+        }
+        std::io::ErrorKind::UnexpectedEof => {
+            MauiError::Transport("VICP connection closed by the device".into())
+        }
+        _ => MauiError::Transport(format!("VICP socket error: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Accept one connection and hand it to `serve` on another thread.
+    fn serve(serve: impl FnOnce(TcpStream) + Send + 'static) -> VicpTransport {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener.accept().unwrap().0));
+        VicpTransport::connect(&address.to_string()).unwrap()
+    }
+
+    fn header(flags: u8, length: u32) -> Vec<u8> {
+        let mut header = vec![flags, HEADER_VERSION, 1, 0];
+        header.extend_from_slice(&length.to_be_bytes());
+        header
+    }
+
+    #[test]
+    fn timeout_mid_block_breaks_connection() {
+        let (done, wait) = std::sync::mpsc::channel::<()>();
+        let transport = serve(move |mut stream| {
+            // half a header, then nothing until the test ends
+            stream.write_all(&header(OPERATION_SRQ, 1)[..4]).unwrap();
+            let _ = wait.recv();
+        });
+
+        let error = transport.wait_for_srq(Duration::from_millis(200));
+        assert!(matches!(error, Err(MauiError::Timeout(_))));
+        assert!(matches!(
+            transport.command("*CLS"),
+            Err(MauiError::Transport(e)) if e.contains("device_clear")
+        ));
+        drop(done);
+    }
+
+    #[test]
+    fn timed_out_query_breaks_connection_until_cleared() {
+        let (done, wait) = std::sync::mpsc::channel::<()>();
+        let transport = serve(move |mut stream| {
+            let mut buffer = [0u8; 64];
+            // ignore the query, then answer the device clear
+            let _ = stream.read(&mut buffer);
+            let _ = stream.read(&mut buffer);
+            let _ = wait.recv();
+        });
+
+        transport.set_timeout(Duration::from_millis(100));
+        assert!(matches!(
+            transport.query("*IDN?"),
+            Err(MauiError::Timeout(_))
+        ));
+        assert!(transport.command("*CLS").is_err());
+
+        transport.device_clear().unwrap();
+        let timeout = transport.lock().stream.read_timeout().unwrap();
+        assert_eq!(timeout, Some(Duration::from_millis(100)));
+        assert!(!transport.lock().broken);
+        drop(done);
+    }
+
+    #[test]
+    fn idle_wait_for_srq_keeps_connection() {
+        let (done, wait) = std::sync::mpsc::channel::<()>();
+        let transport = serve(move |mut stream| {
+            let _ = wait.recv();
+            let mut srq = header(OPERATION_SRQ, 1);
+            srq.push(b'1');
+            stream.write_all(&srq).unwrap();
+            thread::sleep(Duration::from_millis(500));
+        });
+
+        let error = transport.wait_for_srq(Duration::from_millis(100));
+        assert!(matches!(error, Err(MauiError::Timeout(_))));
+        done.send(()).unwrap();
+        transport.wait_for_srq(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn raw_command_is_sent_in_blocks() {
+        let (blocks, received) = std::sync::mpsc::channel();
+        let transport = serve(move |mut stream| {
+            let mut header = [0u8; 8];
+            while stream.read_exact(&mut header).is_ok() {
+                let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+                let mut data = vec![0u8; length as usize];
+                stream.read_exact(&mut data).unwrap();
+                blocks.send((header[0] & OPERATION_EOI != 0, data)).unwrap();
+            }
+        });
+
+        let mut reports = Vec::new();
+        transport
+            .command_raw_with_progress(b"0123456789", 4, &mut |sent, total| {
+                reports.push((sent, total))
+            })
+            .unwrap();
+        drop(transport);
+
+        let blocks: Vec<(bool, Vec<u8>)> = received.iter().collect();
+        assert_eq!(
+            blocks,
+            [
+                (false, b"0123".to_vec()),
+                (false, b"4567".to_vec()),
+                (true, b"89".to_vec())
+            ]
+        );
+        assert_eq!(reports, [(4, 10), (8, 10), (10, 10)]);
+    }
+
+    #[test]
+    fn response_progress_follows_blocks() {
+        let transport = serve(move |mut stream| {
+            let mut buffer = [0u8; 64];
+            let _ = stream.read(&mut buffer);
+            for (flags, data) in [
+                (OPERATION_DATA, &b"#14ab"[..]),
+                (OPERATION_DATA | OPERATION_EOI, b"cd"),
+            ] {
+                let mut block = header(flags, data.len() as u32);
+                block.extend_from_slice(data);
+                stream.write_all(&block).unwrap();
+            }
+        });
+
+        let mut reports = Vec::new();
+        let response = transport
+            .query_raw_with_progress("TRFL?", &mut |received, total| {
+                reports.push((received, total))
+            })
+            .unwrap();
+        assert_eq!(response, b"#14abcd");
+        assert_eq!(reports, [(2, 4), (4, 4)]);
+    }
+}
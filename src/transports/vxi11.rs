@@ -0,0 +1,553 @@
+//! ## VXI-11 Transport
+//!
+//! A pure Rust VXI-11 client, the protocol used by LXI instruments. VXI-11 is
+//! built on ONC RPC over TCP: the core channel port is looked up with the
+//! portmapper, a link to the device is created, and commands and responses are
+//! carried by the `device_write` and `device_read` procedures.
+//!
+
+use crate::transport::response_to_string;
+use crate::{MauiError, Result, Transport};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The port of the ONC RPC portmapper.
+const PORTMAPPER_PORT: u16 = 111;
+const PORTMAPPER_PROGRAM: u32 = 100_000;
+const PORTMAPPER_VERSION: u32 = 2;
+const PORTMAPPER_GETPORT: u32 = 3;
+const IPPROTO_TCP: u32 = 6;
+
+/// The VXI-11 core channel program.
+const CORE_PROGRAM: u32 = 0x0006_07AF;
+const CORE_VERSION: u32 = 1;
+
+// Core channel procedures.
+const CREATE_LINK: u32 = 10;
+const DEVICE_WRITE: u32 = 11;
+const DEVICE_READ: u32 = 12;
+const DEVICE_CLEAR: u32 = 15;
+const DESTROY_LINK: u32 = 23;
+
+// Device flags.
+const FLAG_END: u32 = 0x08;
+
+// Reasons a device read terminated.
+const REASON_END: u32 = 0x04;
+
+/// The largest response requested in a single `device_read`.
+const READ_SIZE: u32 = 1024 * 1024;
+
+/// The largest RPC record accepted, a full `device_read` reply with room for
+/// its headers. A longer record is a corrupted stream, not a reply.
+const MAX_RECORD: usize = READ_SIZE as usize + 4096;
+
+/// An encoder for XDR data, the serialization used by ONC RPC.
+#[derive(Default)]
+struct Xdr {
+    buffer: Vec<u8>,
+}
+
+impl Xdr {
+    fn u32(mut self, value: u32) -> Self {
+        self.buffer.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn opaque(mut self, data: &[u8]) -> Self {
+        self = self.u32(data.len() as u32);
+        self.buffer.extend_from_slice(data);
+        let padding = (4 - data.len() % 4) % 4;
+        self.buffer.extend(std::iter::repeat_n(0, padding));
+        self
+    }
+}
+
+/// A decoder for XDR data.
+struct XdrReader<'a> {
+    data: &'a [u8],
+}
+
+impl XdrReader<'_> {
+    fn u32(&mut self) -> Result<u32> {
+        if self.data.len() < 4 {
+            return Err(MauiError::Transport("truncated RPC reply".into()));
+        }
+        let (value, rest) = self.data.split_at(4);
+        self.data = rest;
+        Ok(u32::from_be_bytes([value[0], value[1], value[2], value[3]]))
+    }
+
+    fn opaque(&mut self) -> Result<Vec<u8>> {
+        let length = self.u32()? as usize;
+        let padded = length + (4 - length % 4) % 4;
+        if self.data.len() < padded {
+            return Err(MauiError::Transport("truncated RPC reply".into()));
+        }
+        let value = self.data[..length].to_vec();
+        self.data = &self.data[padded..];
+        Ok(value)
+    }
+}
+
+/// An ONC RPC client bound to a single program over TCP.
+struct RpcClient {
+    stream: TcpStream,
+    program: u32,
+    version: u32,
+    xid: u32,
+}
+
+impl RpcClient {
+    fn connect(address: SocketAddr, program: u32, version: u32) -> Result<RpcClient> {
+        let stream = TcpStream::connect(address).map_err(io_error)?;
+        stream.set_nodelay(true).map_err(io_error)?;
+        Ok(RpcClient {
+            stream,
+            program,
+            version,
+            xid: 0,
+        })
+    }
+
+    /// Call a procedure and return the encoded results.
+    fn call(&mut self, procedure: u32, args: Xdr) -> Result<Vec<u8>> {
+        self.xid = self.xid.wrapping_add(1);
+
+        let message = Xdr::default()
+            .u32(self.xid)
+            .u32(0) // CALL
+            .u32(2) // RPC version
+            .u32(self.program)
+            .u32(self.version)
+            .u32(procedure)
+            .u32(0) // AUTH_NULL credentials
+            .u32(0)
+            .u32(0) // AUTH_NULL verifier
+            .u32(0)
+            .buffer;
+
+        // a single fragment with the last fragment bit set
+        let mut record = (0x8000_0000 | (message.len() + args.buffer.len()) as u32)
+            .to_be_bytes()
+            .to_vec();
+        record.extend_from_slice(&message);
+        record.extend_from_slice(&args.buffer);
+        self.stream.write_all(&record).map_err(io_error)?;
+
+        loop {
+            let reply = self.receive_record()?;
+            let mut reader = XdrReader { data: &reply };
+            if reader.u32()? != self.xid {
+                // a stale reply to an earlier call that timed out
+                continue;
+            }
+            if reader.u32()? != 1 {
+                return Err(MauiError::Transport(
+                    "RPC reply is not a REPLY message".into(),
+                ));
+            }
+            if reader.u32()? != 0 {
+                return Err(MauiError::Transport(format!(
+                    "RPC call to procedure {} was denied",
+                    procedure
+                )));
+            }
+            reader.u32()?; // verifier flavor
+            reader.opaque()?; // verifier body
+            match reader.u32()? {
+                0 => return Ok(reader.data.to_vec()),
+                stat => {
+                    return Err(MauiError::Transport(format!(
+                        "RPC call to procedure {} failed with accept status {}",
+                        procedure, stat
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Read all the fragments of one record, of at most [`MAX_RECORD`] bytes.
+    fn receive_record(&mut self) -> Result<Vec<u8>> {
+        let mut record = Vec::new();
+        loop {
+            let mut marker = [0u8; 4];
+            self.stream.read_exact(&mut marker).map_err(io_error)?;
+            let marker = u32::from_be_bytes(marker);
+            let length = (marker & 0x7FFF_FFFF) as usize;
+            if record.len() + length > MAX_RECORD {
+                return Err(MauiError::Transport(format!(
+                    "RPC record of more than {} bytes",
+                    MAX_RECORD
+                )));
+            }
+
+            let start = record.len();
+            record.resize(start + length, 0);
+            self.stream
+                .read_exact(&mut record[start..])
+                .map_err(io_error)?;
+            if marker & 0x8000_0000 != 0 {
+                return Ok(record);
+            }
+        }
+    }
+}
+
+/// The state of an open VXI-11 link.
+struct Link {
+    client: RpcClient,
+    id: u32,
+    max_receive_size: usize,
+    io_timeout: Duration,
+}
+
+impl Link {
+    fn io_timeout_ms(&self) -> u32 {
+        self.io_timeout.as_millis().min(u32::MAX as u128) as u32
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let chunk_size = self.max_receive_size.max(1);
+        let chunks: Vec<&[u8]> = if data.is_empty() {
+            vec![data]
+        } else {
+            data.chunks(chunk_size).collect()
+        };
+        let count = chunks.len();
+
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let flags = if i + 1 == count { FLAG_END } else { 0 };
+            let args = Xdr::default()
+                .u32(self.id)
+                .u32(self.io_timeout_ms())
+                .u32(self.io_timeout_ms())
+                .u32(flags)
+                .opaque(chunk);
+            let reply = self.client.call(DEVICE_WRITE, args)?;
+            let mut reader = XdrReader { data: &reply };
+            check_device_error(reader.u32()?, "device_write")?;
+        }
+
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<Vec<u8>> {
+        let mut response = Vec::new();
+        loop {
+            let args = Xdr::default()
+                .u32(self.id)
+                .u32(READ_SIZE)
+                .u32(self.io_timeout_ms())
+                .u32(self.io_timeout_ms())
+                .u32(0)
+                .u32(0);
+            let reply = self.client.call(DEVICE_READ, args)?;
+            let mut reader = XdrReader { data: &reply };
+            check_device_error(reader.u32()?, "device_read")?;
+            let reason = reader.u32()?;
+            response.extend_from_slice(&reader.opaque()?);
+            if reason & REASON_END != 0 {
+                return Ok(response);
+            }
+        }
+    }
+
+    fn generic(&mut self, procedure: u32, name: &str) -> Result<()> {
+        let args = Xdr::default()
+            .u32(self.id)
+            .u32(0)
+            .u32(self.io_timeout_ms())
+            .u32(self.io_timeout_ms());
+        let reply = self.client.call(procedure, args)?;
+        let mut reader = XdrReader { data: &reply };
+        check_device_error(reader.u32()?, name)
+    }
+}
+
+/// ## VXI-11 Transport
+///
+/// A [`Transport`] speaking VXI-11 to an LXI compliant scope.
+///
+/// The link is created when connecting and destroyed when the transport is
+/// dropped.
+///
+pub struct Vxi11Transport {
+    link: Mutex<Link>,
+}
+
+impl Vxi11Transport {
+    /// ## Connect
+    ///
+    /// Open a link to the default `inst0` device of the scope at `host`.
+    ///
+    pub fn connect(host: &str) -> Result<Vxi11Transport> {
+        Self::connect_device(host, "inst0")
+    }
+
+    /// ## Connect Device
+    ///
+    /// Open a link to a named device of the scope at `host`.
+    ///
+    pub fn connect_device(host: &str, device: &str) -> Result<Vxi11Transport> {
+        let address = (host, PORTMAPPER_PORT)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| MauiError::Transport(format!("could not resolve '{}'", host)))?;
+
+        // look up the core channel port
+        let mut portmapper = RpcClient::connect(address, PORTMAPPER_PROGRAM, PORTMAPPER_VERSION)?;
+        let args = Xdr::default()
+            .u32(CORE_PROGRAM)
+            .u32(CORE_VERSION)
+            .u32(IPPROTO_TCP)
+            .u32(0);
+        let reply = portmapper.call(PORTMAPPER_GETPORT, args)?;
+        let port = XdrReader { data: &reply }.u32()?;
+        if port == 0 || port > u16::MAX as u32 {
+            return Err(MauiError::Transport(format!(
+                "'{}' does not offer a VXI-11 core channel",
+                host
+            )));
+        }
+
+        let mut client = RpcClient::connect(
+            SocketAddr::new(address.ip(), port as u16),
+            CORE_PROGRAM,
+            CORE_VERSION,
+        )?;
+        let io_timeout = Duration::from_secs(10);
+        client.stream.set_read_timeout(Some(io_timeout * 2))?;
+
+        // create the link
+        let args = Xdr::default()
+            .u32(std::process::id())
+            .u32(0) // do not lock the device
+            .u32(io_timeout.as_millis() as u32)
+            .opaque(device.as_bytes());
+        let reply = client.call(CREATE_LINK, args)?;
+        let mut reader = XdrReader { data: &reply };
+        check_device_error(reader.u32()?, "create_link")?;
+        let id = reader.u32()?;
+        reader.u32()?; // abort port
+        let max_receive_size = reader.u32()? as usize;
+
+        Ok(Vxi11Transport {
+            link: Mutex::new(Link {
+                client,
+                id,
+                max_receive_size,
+                io_timeout,
+            }),
+        })
+    }
+
+    /// ## Device Clear
+    ///
+    /// Clear the input and output buffers of the device.
+    ///
+    pub fn device_clear(&self) -> Result<()> {
+        self.lock().generic(DEVICE_CLEAR, "device_clear")
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Link> {
+        self.link.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for Vxi11Transport {
+    fn drop(&mut self) {
+        let mut link = self.lock();
+        let args = Xdr::default().u32(link.id);
+        let _ = link.client.call(DESTROY_LINK, args);
+    }
+}
+
+impl Transport for Vxi11Transport {
+    fn command(&self, cmd: &str) -> Result<()> {
+        self.lock().write(cmd.as_bytes())
+    }
+
+    fn command_raw(&self, cmd: &[u8]) -> Result<()> {
+        self.lock().write(cmd)
+    }
+
+    fn query(&self, cmd: &str) -> Result<String> {
+        let resp = response_to_string(self.query_raw(cmd)?)?;
+        Ok(resp.trim_end_matches(['\n', '\r']).to_string())
+    }
+
+    fn query_raw(&self, cmd: &str) -> Result<Vec<u8>> {
+        let mut link = self.lock();
+        link.write(cmd.as_bytes())?;
+        link.read()
+    }
+
+    fn set_timeout(&self, duration: Duration) {
+        let mut link = self.lock();
+        link.io_timeout = duration;
+        // leave the device time to report its own timeout first
+        let socket_timeout = (!duration.is_zero()).then_some(duration * 2);
+        let _ = link.client.stream.set_read_timeout(socket_timeout);
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.device_clear()
+    }
+}
+
+/// Turn a non-zero VXI-11 error code into an error.
+fn check_device_error(code: u32, procedure: &str) -> Result<()> {
+    let description = match code {
+        0 => return Ok(()),
+        1 => "syntax error",
+        3 => "device not accessible",
+        4 => "invalid link identifier",
+        5 => "parameter error",
+        6 => "channel not established",
+        8 => "operation not supported",
+        9 => "out of resources",
+        11 => "device locked by another link",
+        12 => "no lock held by this link",
+        15 => {
+            return Err(MauiError::Timeout(format!(
+                "VXI-11 {} failed: I/O timeout (error 15)",
+                procedure
+            )))
+        }
+        17 => "I/O error",
+        21 => "invalid address",
+        23 => "abort",
+        29 => "channel already established",
+        _ => "unknown error",
+    };
+    Err(MauiError::Transport(format!(
+        "VXI-11 {} failed: {} (error {})",
+        procedure, description, code
+    )))
+}
+
+/// Sort socket errors into timeouts and other transport failures.
+fn io_error(e: std::io::Error) -> MauiError {
+    match e.kind() {
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+            MauiError::Timeout("VXI-11 transfer timed out".into())
+        }
+        std::io::ErrorKind::UnexpectedEof => {
+            MauiError::Transport("VXI-11 connection closed by the device".into())
+        }
+        _ => MauiError::Transport(format!("VXI-11 socket error: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answer each call on a local link with the next raw record from
+    /// `replies`, given the xid of the call.
+    fn serve(replies: Vec<Box<dyn FnOnce(u32) -> Vec<u8> + Send>>) -> Link {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut stream = listener.accept().unwrap().0;
+            for reply in replies {
+                let mut marker = [0u8; 4];
+                stream.read_exact(&mut marker).unwrap();
+                let mut call = vec![0u8; (u32::from_be_bytes(marker) & 0x7FFF_FFFF) as usize];
+                stream.read_exact(&mut call).unwrap();
+                let xid = u32::from_be_bytes([call[0], call[1], call[2], call[3]]);
+                stream.write_all(&reply(xid)).unwrap();
+            }
+            thread::sleep(Duration::from_millis(500));
+        });
+
+        let client = RpcClient::connect(address, CORE_PROGRAM, CORE_VERSION).unwrap();
+        client
+            .stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        Link {
+            client,
+            id: 1,
+            max_receive_size: 1024,
+            io_timeout: Duration::from_secs(1),
+        }
+    }
+
+    /// An accepted reply carrying `results`, in a single fragment.
+    fn accepted(results: Xdr) -> Box<dyn FnOnce(u32) -> Vec<u8> + Send> {
+        Box::new(move |xid| {
+            let body = Xdr::default()
+                .u32(xid)
+                .u32(1) // REPLY
+                .u32(0) // MSG_ACCEPTED
+                .u32(0) // AUTH_NULL verifier
+                .u32(0)
+                .u32(0) // SUCCESS
+                .buffer;
+            let mut record = (0x8000_0000 | (body.len() + results.buffer.len()) as u32)
+                .to_be_bytes()
+                .to_vec();
+            record.extend_from_slice(&body);
+            record.extend_from_slice(&results.buffer);
+            record
+        })
+    }
+
+    fn read_reply(error: u32, reason: u32, data: &[u8]) -> Box<dyn FnOnce(u32) -> Vec<u8> + Send> {
+        accepted(Xdr::default().u32(error).u32(reason).opaque(data))
+    }
+
+    #[test]
+    fn device_errors_are_sorted() {
+        assert!(check_device_error(0, "device_read").is_ok());
+        assert!(matches!(
+            check_device_error(15, "device_read"),
+            Err(MauiError::Timeout(e)) if e.contains("device_read")
+        ));
+        assert!(matches!(
+            check_device_error(17, "device_write"),
+            Err(MauiError::Transport(e)) if e.contains("I/O error")
+        ));
+        assert!(matches!(
+            check_device_error(99, "create_link"),
+            Err(MauiError::Transport(e)) if e.contains("unknown error")
+        ));
+    }
+
+    #[test]
+    fn rpc_errors_reach_the_caller() {
+        let mut link = serve(vec![
+            read_reply(15, 0, b""),
+            accepted(Xdr::default().u32(11).u32(0)),
+        ]);
+        assert!(matches!(link.read(), Err(MauiError::Timeout(_))));
+        assert!(matches!(
+            link.write(b"*CLS"),
+            Err(MauiError::Transport(e)) if e.contains("locked")
+        ));
+    }
+
+    #[test]
+    fn read_joins_replies_until_end() {
+        let mut link = serve(vec![
+            read_reply(0, 0, b"TRFL #14a\n"),
+            read_reply(0, REASON_END, b"bc\n"),
+        ]);
+        assert_eq!(link.read().unwrap(), b"TRFL #14a\nbc\n");
+    }
+
+    #[test]
+    fn oversized_record_is_rejected() {
+        let mut link = serve(vec![Box::new(|_| 0xFFFF_FFFFu32.to_be_bytes().to_vec())]);
+        assert!(matches!(
+            link.read(),
+            Err(MauiError::Transport(e)) if e.contains("RPC record")
+        ));
+    }
+}
@@ -1,27 +1,153 @@
 //! ## Utils Module
-//! 
+//!
 //! A set of utility functions used across the crate.
-//! 
-
-use instrument_ctl::Instrument;
-use std::sync::Arc;
-use anyhow::Result;
-
-use std::thread;
-use std::time::Duration;
-
-/// ## Operation Complete
-/// 
-/// Blocks sending new commands until the operation is complete.
-/// 
-pub fn wait_operation_complete(client: &Arc<Instrument>) -> Result<()> {
-    // Just loop until the operation is complete. In reality, the loop isn't required.
-    loop {
-        match client.query("*OPC?")?.as_str() {
-            "0" => thread::sleep(Duration::from_micros(10)),
-            "1" => break,
-            _ => panic!("invalid response from *OPC?"),
-        };
+//!
+
+use crate::{MauiError, Result};
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// ## Parse Response
+///
+/// Parse a response from the device into a value, keeping the raw response
+/// in the error if it cannot be parsed.
+///
+pub(crate) fn parse_response<T>(resp: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    resp.trim()
+        .parse::<T>()
+        .map_err(|e| MauiError::parse(resp, e))
+}
+
+/// ## Parse Block
+///
+/// Return the data of an IEEE 488.2 definite length block (`#<n><length><data>`),
+/// skipping any command header sent before it.
+///
+pub(crate) fn parse_block(resp: &[u8]) -> Result<&[u8]> {
+    let invalid = |reason: &str| {
+        let start = String::from_utf8_lossy(&resp[..resp.len().min(32)]).to_string();
+        MauiError::parse(start, reason)
+    };
+
+    let start = resp
+        .iter()
+        .position(|&b| b == b'#')
+        .ok_or_else(|| invalid("missing block header"))?;
+    let header = &resp[start + 1..];
+    let digits = header
+        .first()
+        .and_then(|d| (*d as char).to_digit(10))
+        .filter(|d| *d > 0)
+        .ok_or_else(|| invalid("block header is not of definite length"))? as usize;
+    let length = header
+        .get(1..1 + digits)
+        .and_then(|l| std::str::from_utf8(l).ok())
+        .and_then(|l| l.parse::<usize>().ok())
+        .ok_or_else(|| invalid("invalid block length"))?;
+
+    header
+        .get(1 + digits..1 + digits + length)
+        .ok_or_else(|| invalid("block is shorter than its header says"))
+}
+
+/// The table of the reflected CRC-32 polynomial 0xEDB88320.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// ## CRC32
+///
+/// Compute the CRC-32 (IEEE 802.3) checksum of `data`, as used by zip
+/// archives and the `TRANSFER_FILE` command.
+///
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// ## Glob Match
+///
+/// Return whether a `/` separated path matches a glob pattern, ignoring case
+/// like the file system of the scope. `?` matches one character and `*` any
+/// number of characters within a component, while `**` also crosses `/`. A
+/// pattern without a `/` is matched against the file name only.
+///
+pub(crate) fn glob_match(pattern: &str, path: &str) -> bool {
+    let path = match pattern.contains('/') {
+        true => path,
+        false => path.rsplit('/').next().unwrap_or(path),
+    };
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let path: Vec<char> = path.to_lowercase().chars().collect();
+    glob_match_chars(&pattern, &path)
+}
+
+fn glob_match_chars(pattern: &[char], path: &[char]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        // `**/` also matches no directory at all
+        ['*', '*', '/', rest @ ..] if glob_match_chars(rest, path) => true,
+        ['*', '*', rest @ ..] => (0..=path.len()).any(|i| glob_match_chars(rest, &path[i..])),
+        ['*', rest @ ..] => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != '/')
+            .any(|i| glob_match_chars(rest, &path[i..])),
+        ['?', rest @ ..] => {
+            matches!(path.first(), Some(c) if *c != '/') && glob_match_chars(rest, &path[1..])
+        }
+        [c, rest @ ..] => path.first() == Some(c) && glob_match_chars(rest, &path[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_known_vectors() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414F_A339);
+    }
+
+    #[test]
+    fn parse_block_skips_header() {
+        assert_eq!(parse_block(b"TRFL #14abcd").unwrap(), b"abcd");
+        assert_eq!(parse_block(b"#210\x00\x01\x02\x03\x04\x05\x06\x07\x08\x09").unwrap().len(), 10);
+        assert!(parse_block(b"#15abc").is_err());
+        assert!(parse_block(b"#0abc").is_err());
+        assert!(parse_block(b"abc").is_err());
+    }
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("*", "a.trc"));
+        assert!(glob_match("*", "sub/a.trc"));
+        assert!(!glob_match("*/*", "a.trc"));
+        assert!(glob_match("*/*", "sub/a.trc"));
+        assert!(glob_match("C?Trace*.trc", "c1trace00001.TRC"));
+        assert!(!glob_match("C?Trace*.trc", "C12Trace00001.trc"));
+        assert!(!glob_match("sub?a.trc", "sub/a.trc"));
+        assert!(glob_match("**/*.trc", "a.trc"));
+        assert!(glob_match("**/*.trc", "x/y/a.trc"));
+        assert!(!glob_match("sub/*.trc", "sub/x/a.trc"));
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "a.trc"));
     }
-    Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,189 @@
+//! ## Wait Module
+//!
+//! Deadlines and cancellation for the blocking waits of the crate.
+//!
+
+use crate::{MauiError, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// ## Cancellation Token
+///
+/// A flag shared between a waiting thread and any thread that wants to stop
+/// the wait. Clones share the same flag.
+///
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// ## New
+    ///
+    /// Create a token that is not cancelled.
+    ///
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// ## Cancel
+    ///
+    /// Cancel every wait using this token or one of its clones. Waits notice
+    /// the cancellation within a fraction of a second and return
+    /// [`MauiError::Cancelled`].
+    ///
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// ## Is Cancelled
+    ///
+    /// Return whether the token has been cancelled.
+    ///
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// ## Wait Options
+///
+/// The deadline and cancellation token of a blocking wait. A wait without a
+/// deadline blocks until its event occurs or it is cancelled.
+///
+/// ```ignore
+/// let token = CancellationToken::new();
+/// let options = WaitOptions::timeout(Duration::from_secs(30)).with_cancellation(token.clone());
+/// scope.status.wait_for_acquisition(&options)?;
+/// ```
+///
+#[derive(Debug, Clone, Default)]
+pub struct WaitOptions {
+    deadline: Option<Instant>,
+    tokens: Vec<CancellationToken>,
+}
+
+impl WaitOptions {
+    /// ## New
+    ///
+    /// Wait without a deadline and without cancellation.
+    ///
+    pub fn new() -> WaitOptions {
+        WaitOptions::default()
+    }
+
+    /// ## Timeout
+    ///
+    /// Wait for at most `timeout`, starting now.
+    ///
+    pub fn timeout(timeout: Duration) -> WaitOptions {
+        WaitOptions::deadline(Instant::now() + timeout)
+    }
+
+    /// ## Deadline
+    ///
+    /// Wait until `deadline` at the latest.
+    ///
+    pub fn deadline(deadline: Instant) -> WaitOptions {
+        WaitOptions {
+            deadline: Some(deadline),
+            tokens: Vec::new(),
+        }
+    }
+
+    /// ## With Cancellation
+    ///
+    /// Stop the wait when `token` is cancelled. When called several times, the
+    /// wait stops as soon as any of the tokens is cancelled.
+    ///
+    pub fn with_cancellation(mut self, token: CancellationToken) -> WaitOptions {
+        self.tokens.push(token);
+        self
+    }
+
+    /// Fail if the wait was cancelled or its deadline has passed.
+    pub(crate) fn check(&self, waiting_for: &str) -> Result<()> {
+        if self.tokens.iter().any(|t| t.is_cancelled()) {
+            return Err(MauiError::Cancelled);
+        }
+        if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            return Err(MauiError::Timeout(format!(
+                "deadline passed while waiting for {}",
+                waiting_for
+            )));
+        }
+        Ok(())
+    }
+
+    /// Return the time left before the deadline, capped at `max`.
+    pub(crate) fn slice(&self, max: Duration) -> Duration {
+        match self.deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()).min(max),
+            None => max,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystems::status::InternalStateChange;
+    use crate::transports::simulated::SimulatedScope;
+    use crate::MauiOscilloscope;
+    use std::thread;
+
+    /// A scope that never acquires on its own.
+    fn scope() -> MauiOscilloscope {
+        let sim = Arc::new(SimulatedScope::new());
+        sim.set_signal_present(false);
+        MauiOscilloscope::with_transport(sim).unwrap()
+    }
+
+    #[test]
+    fn expired_deadline_is_a_timeout() {
+        let scope = scope();
+        let start = Instant::now();
+        let result = scope.status.wait_for_internal_state(
+            InternalStateChange::NEW_SIGNAL_ACQUIRED,
+            &WaitOptions::timeout(Duration::from_millis(300)),
+        );
+
+        assert!(matches!(result, Err(MauiError::Timeout(_))));
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn cancellation_from_another_thread_stops_the_wait() {
+        let scope = scope();
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            canceller.cancel();
+        });
+
+        let start = Instant::now();
+        let options = WaitOptions::new().with_cancellation(token.clone());
+        let result = scope.status.wait_for_acquisition(&options);
+
+        assert!(matches!(result, Err(MauiError::Cancelled)));
+        assert!(token.is_cancelled());
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn any_token_cancels() {
+        let cancelled = CancellationToken::new();
+        cancelled.cancel();
+        let options = WaitOptions::timeout(Duration::from_secs(60))
+            .with_cancellation(CancellationToken::new())
+            .with_cancellation(cancelled);
+        assert!(matches!(options.check("test"), Err(MauiError::Cancelled)));
+        assert!(WaitOptions::new().check("test").is_ok());
+        assert_eq!(
+            WaitOptions::new().slice(Duration::from_millis(5)),
+            Duration::from_millis(5)
+        );
+    }
+}